@@ -0,0 +1,38 @@
+//! Decode and execute arbitrary program memory and internal RAM with `Isa::step`
+//!
+//! ```text
+//! cargo +nightly fuzz run step
+//! ```
+//!
+//! The first byte selects the variant, the next 256 bytes fill internal RAM and the rest is
+//! program memory. Faults are expected, panics and hangs are not.
+
+#![no_main]
+
+use area8051::{Isa, Mcu, Variant};
+use libfuzzer_sys::fuzz_target;
+
+const VARIANTS: [Variant; 4] = [Variant::I8051, Variant::I8052, Variant::At89s52, Variant::Ite];
+
+/// Instructions executed for each input
+const STEPS: usize = 1000;
+
+fuzz_target!(|data: &[u8]| {
+    let (variant, data) = match data.split_first() {
+        Some((&variant, data)) => (VARIANTS[variant as usize % VARIANTS.len()], data),
+        None => return,
+    };
+    let (iram, pmem) = data.split_at(data.len().min(256));
+
+    let mut mcu = Mcu::with_variant(variant, pmem.to_vec().into_boxed_slice());
+    mcu.power_on(Some(0));
+    for (byte, &value) in mcu.iram.iter_mut().zip(iram.iter()) {
+        *byte = value;
+    }
+
+    for _ in 0..STEPS {
+        if mcu.halted || mcu.step().is_err() {
+            break;
+        }
+    }
+});
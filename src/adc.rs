@@ -0,0 +1,230 @@
+use crate::{Addr, PeripheralInterrupt};
+
+/// Level on an ADC channel in volts
+pub enum Analog {
+    Constant(f64),
+    /// Level as a function of emulated time in nanoseconds
+    Waveform(Box<dyn Fn(u64) -> f64>),
+}
+
+impl Analog {
+    pub fn waveform<F: Fn(u64) -> f64 + 'static>(f: F) -> Self {
+        Analog::Waveform(Box::new(f))
+    }
+
+    /// Level at `ns` nanoseconds
+    pub fn at(&self, ns: u64) -> f64 {
+        match self {
+            Analog::Constant(volts) => *volts,
+            Analog::Waveform(f) => f(ns),
+        }
+    }
+}
+
+/// Placement of a result in the high and low result registers
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AdcAlign {
+    /// Low register holds the low 8 bits, high register the rest
+    Right,
+    /// Left justified across both registers
+    Left,
+    /// High register holds the high 8 bits, low register the rest right aligned
+    Split,
+}
+
+/// Register map and timing of an ADC
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AdcMap {
+    pub control: Addr,
+    /// Power or enable bit in the control register, zero if there is none
+    pub enable: u8,
+    /// Start bit in the control register, cleared by hardware when the conversion is done
+    pub start: u8,
+    /// Channel select register and the mask of its channel field
+    pub select: Addr,
+    pub channel: u8,
+    /// Status register and its done flag, which firmware clears
+    pub status: Addr,
+    pub done: u8,
+    pub result_high: Addr,
+    pub result_low: Addr,
+    pub align: AdcAlign,
+    /// Result resolution in bits
+    pub bits: u8,
+    /// Oscillator clocks from start to done, converted to machine cycles of the variant
+    pub clocks: u64,
+    /// Interrupt requested while the done flag is set
+    pub interrupt: Option<PeripheralInterrupt>,
+}
+
+impl AdcMap {
+    /// ADC_CONTR, ADC_RES and ADC_RESL of the STC12C5A60S2, at the slowest conversion speed
+    pub fn stc12() -> Self {
+        Self {
+            control: Addr::Reg(0xBC),
+            enable: 1 << 7,
+            start: 1 << 3,
+            select: Addr::Reg(0xBC),
+            channel: 0b111,
+            status: Addr::Reg(0xBC),
+            done: 1 << 4,
+            result_high: Addr::Reg(0xBD),
+            result_low: Addr::Reg(0xBE),
+            align: AdcAlign::Split,
+            bits: 10,
+            // SPEED 00
+            clocks: 540,
+            // Vector 0x2B, EADC and PADC
+            interrupt: Some(PeripheralInterrupt { vector: 0x2B, enable: 0xAD, priority: 0xBD }),
+        }
+    }
+
+    /// ADC0CN, AMX0P, ADC0H and ADC0L of the Silicon Labs C8051F34x, right justified
+    pub fn c8051f() -> Self {
+        Self {
+            control: Addr::Reg(0xE8),
+            enable: 1 << 7,
+            start: 1 << 4,
+            select: Addr::Reg(0xBB),
+            channel: 0b1_1111,
+            status: Addr::Reg(0xE8),
+            done: 1 << 5,
+            result_high: Addr::Reg(0xBE),
+            result_low: Addr::Reg(0xBD),
+            align: AdcAlign::Right,
+            bits: 10,
+            clocks: 36,
+            interrupt: None,
+        }
+    }
+
+    /// Register addresses, the index of the first match identifies a register
+    fn registers(&self) -> [Addr; 5] {
+        [self.control, self.select, self.status, self.result_high, self.result_low]
+    }
+}
+
+/// Successive approximation ADC, sampling its channel when a conversion starts
+pub struct Adc {
+    pub map: AdcMap,
+    /// Full scale reference in volts
+    pub vref: f64,
+    /// Channel levels, missing channels read 0 V
+    pub inputs: Vec<Analog>,
+    /// Conversions as channel and result
+    pub log: Vec<(u8, u16)>,
+    values: [u8; 5],
+    /// Conversion in progress
+    busy: Option<u64>,
+    conversions: u64,
+}
+
+impl Adc {
+    pub fn new(map: AdcMap) -> Self {
+        Self {
+            map,
+            vref: 3.3,
+            inputs: Vec::new(),
+            log: Vec::new(),
+            values: [0; 5],
+            busy: None,
+            conversions: 0,
+        }
+    }
+
+    /// Set the level of channel `channel`
+    pub fn set(&mut self, channel: usize, analog: Analog) {
+        while self.inputs.len() <= channel {
+            self.inputs.push(Analog::Constant(0.0));
+        }
+        self.inputs[channel] = analog;
+    }
+
+    pub fn reset(&mut self) {
+        self.values = [0; 5];
+        self.busy = None;
+    }
+
+    fn index(&self, addr: Addr) -> Option<usize> {
+        self.map.registers().iter().position(|&register| register == addr)
+    }
+
+    fn value(&self, addr: Addr) -> u8 {
+        self.load(addr).unwrap_or(0)
+    }
+
+    fn update<F: FnOnce(u8) -> u8>(&mut self, addr: Addr, f: F) {
+        if let Some(index) = self.index(addr) {
+            self.values[index] = f(self.values[index]);
+        }
+    }
+
+    /// Done flag set
+    pub fn done(&self) -> bool {
+        self.value(self.map.status) & self.map.done != 0
+    }
+
+    pub fn channel(&self) -> u8 {
+        let mask = self.map.channel;
+        (self.value(self.map.select) & mask) >> mask.trailing_zeros()
+    }
+
+    /// Result code of channel `channel` at `ns` nanoseconds
+    pub fn sample(&self, channel: u8, ns: u64) -> u16 {
+        let volts = self.inputs.get(channel as usize).map_or(0.0, |input| input.at(ns));
+        let max = (1u32 << self.map.bits) - 1;
+        let code = (volts / self.vref * (max + 1) as f64).floor();
+        code.clamp(0.0, max as f64) as u16
+    }
+
+    pub(crate) fn load(&self, addr: Addr) -> Option<u8> {
+        self.index(addr).map(|index| self.values[index])
+    }
+
+    /// Register write, returns false if `addr` is not one of the registers
+    pub(crate) fn store(&mut self, addr: Addr, value: u8) -> bool {
+        match self.index(addr) {
+            Some(index) => {
+                self.values[index] = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Begin a conversion if one was started, returns its number and channel
+    pub(crate) fn start(&mut self) -> Option<(u64, u8)> {
+        let control = self.value(self.map.control);
+        let enabled = control & self.map.enable == self.map.enable;
+        if self.busy.is_some() || ! enabled || control & self.map.start == 0 {
+            return None;
+        }
+        self.conversions += 1;
+        self.busy = Some(self.conversions);
+        Some((self.conversions, self.channel()))
+    }
+
+    /// Complete conversion `conversion` of `channel` with result `code`
+    pub(crate) fn finish(&mut self, conversion: u64, channel: u8, code: u16) {
+        if self.busy != Some(conversion) {
+            return;
+        }
+        self.busy = None;
+        debug!(" ; adc channel {} result 0x{:03X}", channel, code);
+        self.log.push((channel, code));
+        let bits = self.map.bits as u32;
+        let (high, low) = match self.map.align {
+            AdcAlign::Right => ((code >> 8) as u8, code as u8),
+            AdcAlign::Left => {
+                let value = code << (16 - bits);
+                ((value >> 8) as u8, value as u8)
+            },
+            AdcAlign::Split => ((code >> (bits - 8)) as u8, (code & ((1 << (bits - 8)) - 1)) as u8),
+        };
+        let (start, done) = (self.map.start, self.map.done);
+        self.update(self.map.result_high, |_| high);
+        self.update(self.map.result_low, |_| low);
+        self.update(self.map.control, |value| value & !start);
+        self.update(self.map.status, |value| value | done);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use crate::I2cDevice;
+
+/// Smart Battery Data command codes
+pub mod sbs {
+    pub const MANUFACTURER_ACCESS: u8 = 0x00;
+    pub const REMAINING_CAPACITY_ALARM: u8 = 0x01;
+    pub const TEMPERATURE: u8 = 0x08;
+    pub const VOLTAGE: u8 = 0x09;
+    pub const CURRENT: u8 = 0x0A;
+    pub const AVERAGE_CURRENT: u8 = 0x0B;
+    pub const RELATIVE_STATE_OF_CHARGE: u8 = 0x0D;
+    pub const ABSOLUTE_STATE_OF_CHARGE: u8 = 0x0E;
+    pub const REMAINING_CAPACITY: u8 = 0x0F;
+    pub const FULL_CHARGE_CAPACITY: u8 = 0x10;
+    pub const RUN_TIME_TO_EMPTY: u8 = 0x11;
+    pub const AVERAGE_TIME_TO_EMPTY: u8 = 0x12;
+    pub const AVERAGE_TIME_TO_FULL: u8 = 0x13;
+    pub const CHARGING_CURRENT: u8 = 0x14;
+    pub const CHARGING_VOLTAGE: u8 = 0x15;
+    pub const BATTERY_STATUS: u8 = 0x16;
+    pub const CYCLE_COUNT: u8 = 0x17;
+    pub const DESIGN_CAPACITY: u8 = 0x18;
+    pub const DESIGN_VOLTAGE: u8 = 0x19;
+    pub const SPECIFICATION_INFO: u8 = 0x1A;
+    pub const MANUFACTURE_DATE: u8 = 0x1B;
+    pub const SERIAL_NUMBER: u8 = 0x1C;
+    pub const MANUFACTURER_NAME: u8 = 0x20;
+    pub const DEVICE_NAME: u8 = 0x21;
+    pub const DEVICE_CHEMISTRY: u8 = 0x22;
+    pub const MANUFACTURER_DATA: u8 = 0x23;
+}
+
+/// Smart battery responding to SMBus read and write word and block read commands
+/// Registers hold their bytes as sent on the bus, words little-endian and blocks prefixed by their
+/// length. Commands without a register are not acknowledged.
+#[derive(Clone, Debug)]
+pub struct SmartBattery {
+    /// 7-bit device address, 0x0B for a smart battery
+    pub address: u8,
+    pub registers: BTreeMap<u8, Vec<u8>>,
+    command: Option<u8>,
+    /// Bytes written after the command
+    written: Vec<u8>,
+    /// Read position in the current register
+    offset: usize,
+}
+
+impl SmartBattery {
+    /// Three cell lithium-ion pack, 80% charged and discharging at rest temperature
+    pub fn new() -> Self {
+        let mut battery = Self {
+            address: 0x0B,
+            registers: BTreeMap::new(),
+            command: None,
+            written: Vec::new(),
+            offset: 0,
+        };
+        for &(command, value) in [
+            (sbs::MANUFACTURER_ACCESS, 0),
+            (sbs::REMAINING_CAPACITY_ALARM, 440),
+            (sbs::TEMPERATURE, 2982),
+            (sbs::VOLTAGE, 11_800),
+            (sbs::CURRENT, -500i16 as u16),
+            (sbs::AVERAGE_CURRENT, -500i16 as u16),
+            (sbs::RELATIVE_STATE_OF_CHARGE, 80),
+            (sbs::ABSOLUTE_STATE_OF_CHARGE, 78),
+            (sbs::REMAINING_CAPACITY, 3440),
+            (sbs::FULL_CHARGE_CAPACITY, 4300),
+            (sbs::RUN_TIME_TO_EMPTY, 412),
+            (sbs::AVERAGE_TIME_TO_EMPTY, 412),
+            (sbs::AVERAGE_TIME_TO_FULL, 0xFFFF),
+            (sbs::CHARGING_CURRENT, 0),
+            (sbs::CHARGING_VOLTAGE, 0),
+            // Initialized and discharging
+            (sbs::BATTERY_STATUS, 0x00C0),
+            (sbs::CYCLE_COUNT, 12),
+            (sbs::DESIGN_CAPACITY, 4400),
+            (sbs::DESIGN_VOLTAGE, 11_100),
+            // Smart Battery Data 1.1 with PEC support
+            (sbs::SPECIFICATION_INFO, 0x0031),
+            // 2024-01-15
+            (sbs::MANUFACTURE_DATE, (2024 - 1980) << 9 | 1 << 5 | 15),
+            (sbs::SERIAL_NUMBER, 0x0001),
+        ].iter() {
+            battery.set_word(command, value);
+        }
+        battery.set_block(sbs::MANUFACTURER_NAME, b"Emu");
+        battery.set_block(sbs::DEVICE_NAME, b"EMU3S1P");
+        battery.set_block(sbs::DEVICE_CHEMISTRY, b"LION");
+        battery.set_block(sbs::MANUFACTURER_DATA, &[]);
+        battery
+    }
+
+    pub fn set_word(&mut self, command: u8, value: u16) {
+        self.registers.insert(command, value.to_le_bytes().to_vec());
+    }
+
+    pub fn set_block(&mut self, command: u8, data: &[u8]) {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(data);
+        self.registers.insert(command, bytes);
+    }
+
+    /// Value of a word register, as last set or written by firmware
+    pub fn word(&self, command: u8) -> Option<u16> {
+        match self.registers.get(&command)?.as_slice() {
+            &[low, high] => Some(u16::from_le_bytes([low, high])),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SmartBattery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl I2cDevice for SmartBattery {
+    fn responds(&self, address: u8) -> bool {
+        address == self.address
+    }
+
+    fn start(&mut self, _address: u8, read: bool) {
+        if ! read {
+            self.command = None;
+            self.written.clear();
+        }
+        self.offset = 0;
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        match self.command {
+            None if self.registers.contains_key(&value) => {
+                self.command = Some(value);
+                true
+            },
+            None => false,
+            Some(_) => {
+                self.written.push(value);
+                true
+            },
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.command
+            .and_then(|command| self.registers.get(&command))
+            .and_then(|bytes| bytes.get(self.offset).cloned())
+            .unwrap_or(0xFF);
+        self.offset += 1;
+        value
+    }
+
+    fn stop(&mut self) {
+        if let Some(command) = self.command {
+            if ! self.written.is_empty() {
+                let written = std::mem::take(&mut self.written);
+                self.registers.insert(command, written);
+            }
+        }
+    }
+}
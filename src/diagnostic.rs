@@ -0,0 +1,13 @@
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Diagnostic {
+    /// Indirect access to internal RAM that is not present on this variant
+    IRam {
+        pc: u16,
+        addr: u8,
+    },
+    /// Direct access to a special function register that is not implemented on this variant
+    Sfr {
+        pc: u16,
+        addr: u8,
+    },
+}
@@ -0,0 +1,93 @@
+use crate::I2cDevice;
+
+/// 24Cxx serial EEPROM
+/// Parts up to 2 KiB take one address byte and use the low bits of the device address as block
+/// bits, larger parts take two address bytes. Writes wrap within a page, reads roll over at the end
+/// of memory.
+#[derive(Clone, Debug)]
+pub struct Eeprom {
+    pub data: Vec<u8>,
+    /// 7-bit device address, 0x50 with the address pins low
+    pub address: u8,
+    /// Write page size in bytes
+    pub page: usize,
+    /// Current address counter
+    pub pointer: usize,
+    /// Block bits from the device address of the current transaction
+    block: usize,
+    /// Address bytes still expected in a write
+    address_bytes: u8,
+}
+
+impl Eeprom {
+    /// Erased part of `size` bytes, a power of two from 128 to 65536
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two() && (128..=65536).contains(&size), "invalid EEPROM size {}", size);
+        let page = match size {
+            0 ..= 256 => 8,
+            257 ..= 2048 => 16,
+            2049 ..= 8192 => 32,
+            8193 ..= 32768 => 64,
+            _ => 128,
+        };
+        Self {
+            data: vec![0xFF; size],
+            address: 0x50,
+            page,
+            pointer: 0,
+            block: 0,
+            address_bytes: 0,
+        }
+    }
+
+    fn wide(&self) -> bool {
+        self.data.len() > 2048
+    }
+
+    /// Device address bits selecting a 256 byte block
+    fn block_mask(&self) -> u8 {
+        if self.wide() {
+            0
+        } else {
+            ((self.data.len() >> 8).max(1) - 1) as u8
+        }
+    }
+}
+
+impl I2cDevice for Eeprom {
+    fn responds(&self, address: u8) -> bool {
+        address & !self.block_mask() == self.address & !self.block_mask()
+    }
+
+    fn start(&mut self, address: u8, read: bool) {
+        self.block = (address & self.block_mask()) as usize;
+        self.address_bytes = match (read, self.wide()) {
+            (true, _) => 0,
+            (false, false) => 1,
+            (false, true) => 2,
+        };
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        let size = self.data.len();
+        match self.address_bytes {
+            2 => self.pointer = (value as usize) << 8 & (size - 1),
+            1 if self.wide() => self.pointer = (self.pointer & 0xFF00 | value as usize) & (size - 1),
+            1 => self.pointer = (self.block << 8 | value as usize) & (size - 1),
+            _ => {
+                self.data[self.pointer] = value;
+                let base = self.pointer & !(self.page - 1);
+                self.pointer = base | (self.pointer + 1) & (self.page - 1);
+                return true;
+            },
+        }
+        self.address_bytes -= 1;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.data[self.pointer];
+        self.pointer = (self.pointer + 1) % self.data.len();
+        value
+    }
+}
@@ -0,0 +1,26 @@
+use std::fmt;
+
+/// Fault that stops execution
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Reserved opcode 0xA5 or another opcode that is not implemented
+    UnknownOpcode {
+        pc: u16,
+        op: u8,
+    },
+    /// Store to program memory, which is read-only
+    PMemWrite {
+        addr: u16,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { pc, op } => write!(f, "unknown opcode 0x{:02X} at 0x{:04X}", op, pc),
+            Error::PMemWrite { addr } => write!(f, "write to pmem 0x{:04X}", addr),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,252 @@
+use std::{
+    fs,
+    io,
+    path::PathBuf,
+};
+
+use crate::SpiDevice;
+
+/// SPI NOR flash command codes
+pub mod spi_flash {
+    pub const PP: u8 = 0x02;
+    pub const READ: u8 = 0x03;
+    pub const WRDI: u8 = 0x04;
+    pub const RDSR: u8 = 0x05;
+    pub const WREN: u8 = 0x06;
+    pub const FAST_READ: u8 = 0x0B;
+    /// Erase a 4 KiB sector
+    pub const SE: u8 = 0x20;
+    /// Erase a 64 KiB block
+    pub const BE: u8 = 0xD8;
+    /// Erase the whole chip
+    pub const CE: u8 = 0xC7;
+    pub const CE_ALT: u8 = 0x60;
+    pub const JEDEC_ID: u8 = 0x9F;
+
+    /// Status register bits, write in progress and write enable latch
+    pub const WIP: u8 = 1 << 0;
+    pub const WEL: u8 = 1 << 1;
+}
+
+use self::spi_flash::*;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    /// Waiting for a command byte
+    Command,
+    /// Collecting address bytes, then dummy bytes
+    Address { command: u8, bytes: u8, dummy: u8 },
+    Read,
+    Program,
+    Status,
+    Id(usize),
+    /// Command finished or unknown, bytes are ignored until deselected
+    Ignore,
+}
+
+/// SPI NOR flash with 3 byte addresses, 256 byte pages, 4 KiB sectors and 64 KiB blocks
+/// Programming only clears bits. Programs and erases take effect when chip select is released, and
+/// the backing file, if any, is then rewritten.
+#[derive(Debug)]
+pub struct SpiFlash {
+    pub data: Vec<u8>,
+    /// Manufacturer, memory type and capacity returned by JEDEC-ID
+    pub jedec_id: [u8; 3],
+    pub status: u8,
+    /// Status reads that report a write in progress after each program or erase
+    pub busy_polls: u32,
+    /// File the contents are persisted to
+    pub path: Option<PathBuf>,
+    /// Last error writing the backing file
+    pub error: Option<io::Error>,
+    state: State,
+    address: usize,
+    /// Pending program or erase, as command, address and bytes
+    pending: Option<(u8, usize, Vec<u8>)>,
+    busy: u32,
+}
+
+impl SpiFlash {
+    /// Erased flash of `size` bytes, a power of two, with a Winbond W25Q series ID
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two() && (1 << 16..=1 << 24).contains(&size), "invalid flash size {}", size);
+        Self {
+            data: vec![0xFF; size],
+            jedec_id: [0xEF, 0x40, size.trailing_zeros() as u8],
+            status: 0,
+            busy_polls: 0,
+            path: None,
+            error: None,
+            state: State::Command,
+            address: 0,
+            pending: None,
+            busy: 0,
+        }
+    }
+
+    /// Flash of `size` bytes backed by the file at `path`, which is created if it does not exist
+    /// A shorter file is padded with erased bytes.
+    pub fn open<P: Into<PathBuf>>(path: P, size: usize) -> io::Result<Self> {
+        let path = path.into();
+        let mut flash = Self::new(size);
+        match fs::read(&path) {
+            Ok(data) => {
+                if data.len() > size {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "file larger than flash"));
+                }
+                flash.data[..data.len()].copy_from_slice(&data);
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        fs::write(&path, &flash.data)?;
+        flash.path = Some(path);
+        Ok(flash)
+    }
+
+    /// Write the contents to the backing file
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => fs::write(path, &self.data),
+            None => Ok(()),
+        }
+    }
+
+    /// State after the address of `command`
+    fn begin(&mut self, command: u8) -> State {
+        match command {
+            PP => {
+                self.pending = Some((PP, self.address, Vec::new()));
+                State::Program
+            },
+            SE | BE => {
+                self.pending = Some((command, self.address, Vec::new()));
+                State::Ignore
+            },
+            _ => State::Read,
+        }
+    }
+
+    fn commit(&mut self) {
+        let (command, address, bytes) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let size = self.data.len();
+        match command {
+            PP => {
+                debug!(" ; flash program 0x{:06X} {} bytes", address, bytes.len());
+                // Only the last 256 bytes are kept, wrapping within the page
+                let skip = bytes.len().saturating_sub(256);
+                for (i, &value) in bytes.iter().enumerate().skip(skip) {
+                    let offset = address & 0xFF;
+                    let at = address & !0xFF | (offset + i - skip) & 0xFF;
+                    self.data[at % size] &= value;
+                }
+            },
+            _ => {
+                let length = match command {
+                    SE => 4096,
+                    BE => 65536,
+                    _ => size,
+                };
+                let start = address & !(length - 1) & (size - 1);
+                debug!(" ; flash erase 0x{:06X} {} bytes", start, length);
+                for byte in self.data[start..start + length].iter_mut() {
+                    *byte = 0xFF;
+                }
+            },
+        }
+        self.status &= !WEL;
+        self.busy = self.busy_polls;
+        if let Err(err) = self.save() {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl SpiDevice for SpiFlash {
+    fn select(&mut self) {
+        self.state = State::Command;
+    }
+
+    fn output(&mut self) -> u8 {
+        match self.state {
+            State::Read => {
+                let value = self.data[self.address];
+                self.address = (self.address + 1) % self.data.len();
+                value
+            },
+            State::Status => {
+                let mut status = self.status;
+                if self.busy > 0 {
+                    self.busy -= 1;
+                    status |= WIP;
+                }
+                status
+            },
+            State::Id(index) => {
+                self.state = State::Id(index + 1);
+                self.jedec_id.get(index).cloned().unwrap_or(0xFF)
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn input(&mut self, value: u8) {
+        self.state = match self.state {
+            State::Command => {
+                debug!(" ; flash command 0x{:02X}", value);
+                let writable = self.status & WEL != 0;
+                match value {
+                    // Commands other than RDSR are ignored while busy
+                    RDSR => State::Status,
+                    _ if self.busy > 0 => State::Ignore,
+                    READ => State::Address { command: value, bytes: 3, dummy: 0 },
+                    FAST_READ => State::Address { command: value, bytes: 3, dummy: 1 },
+                    PP | SE | BE if writable => State::Address { command: value, bytes: 3, dummy: 0 },
+                    CE | CE_ALT if writable => {
+                        self.pending = Some((CE, 0, Vec::new()));
+                        State::Ignore
+                    },
+                    WREN => {
+                        self.status |= WEL;
+                        State::Ignore
+                    },
+                    WRDI => {
+                        self.status &= !WEL;
+                        State::Ignore
+                    },
+                    JEDEC_ID => State::Id(0),
+                    _ => State::Ignore,
+                }
+            },
+            State::Address { command, mut bytes, mut dummy } => {
+                if bytes > 0 {
+                    // Earlier address bits are shifted out of range
+                    self.address = (self.address << 8 | value as usize) & (self.data.len() - 1);
+                    bytes -= 1;
+                } else {
+                    dummy -= 1;
+                }
+                if bytes > 0 || dummy > 0 {
+                    State::Address { command, bytes, dummy }
+                } else {
+                    self.begin(command)
+                }
+            },
+            State::Program => {
+                if let Some((_, _, bytes)) = self.pending.as_mut() {
+                    bytes.push(value);
+                }
+                State::Program
+            },
+            state => state,
+        };
+    }
+
+    fn deselect(&mut self) {
+        self.commit();
+        self.state = State::Command;
+    }
+}
@@ -0,0 +1,137 @@
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Level {
+    Low,
+    High,
+    /// Not driven, open-drain output with the latch set
+    Float,
+}
+
+/// Quasi-bidirectional port, each pin is the wired-AND of the latch and any external driver
+#[derive(Clone, Copy, Debug)]
+pub struct Port {
+    /// Output latch written by firmware
+    pub latch: u8,
+    /// Pins driven by an external device
+    pub driven: u8,
+    /// Level of externally driven pins
+    pub drive: u8,
+    /// Pins with internal weak pull-ups, the others are open-drain
+    pub pullup: u8,
+}
+
+impl Port {
+    pub fn new(pullup: u8) -> Self {
+        Self {
+            latch: 0xFF,
+            driven: 0,
+            drive: 0,
+            pullup,
+        }
+    }
+
+    /// Pin levels as read by instructions that are not read-modify-write
+    /// A set latch only weakly pulls up, so external drivers can pull pins low. Floating open-drain
+    /// pins read high, as if pulled up externally.
+    pub fn pins(&self) -> u8 {
+        self.latch & (self.drive | !self.driven)
+    }
+
+    /// Level driven onto pin `bit` by the port
+    pub fn output(&self, bit: u8) -> Level {
+        let mask = 1 << bit;
+        if self.latch & mask == 0 {
+            Level::Low
+        } else if self.pullup & mask != 0 {
+            Level::High
+        } else {
+            Level::Float
+        }
+    }
+}
+
+/// Change in the state of a port
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PortChange {
+    pub cycle: u64,
+    pub port: u8,
+    pub latch: u8,
+    pub pins: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct Gpio {
+    pub ports: [Port; 4],
+    /// Changes to latches and pin levels, in order
+    pub history: Vec<PortChange>,
+}
+
+impl Gpio {
+    pub fn new() -> Self {
+        Self {
+            // P0 is open-drain
+            ports: [Port::new(0x00), Port::new(0xFF), Port::new(0xFF), Port::new(0xFF)],
+            history: Vec::new(),
+        }
+    }
+
+    /// Port index for a port special function register address
+    pub fn port(addr: u8) -> Option<usize> {
+        match addr {
+            0x80 => Some(0),
+            0x90 => Some(1),
+            0xA0 => Some(2),
+            0xB0 => Some(3),
+            _ => None,
+        }
+    }
+
+    fn update<F: FnOnce(&mut Port)>(&mut self, index: usize, cycle: u64, f: F) {
+        let port = &mut self.ports[index];
+        let old = (port.latch, port.pins());
+        f(port);
+        let new = (port.latch, port.pins());
+        if new != old {
+            debug!(" ; p{} latch 0x{:02X} pins 0x{:02X}", index, new.0, new.1);
+            self.history.push(PortChange {
+                cycle,
+                port: index as u8,
+                latch: new.0,
+                pins: new.1,
+            });
+        }
+    }
+
+    /// Write the latch of port `index`
+    pub fn write(&mut self, index: usize, value: u8, cycle: u64) {
+        self.update(index, cycle, |port| port.latch = value);
+    }
+
+    /// Drive pin `bit` of port `index` from outside, `None` releases the pin
+    pub fn drive(&mut self, index: usize, bit: u8, level: Option<bool>, cycle: u64) {
+        let mask = 1 << bit;
+        self.update(index, cycle, |port| match level {
+            Some(high) => {
+                port.driven |= mask;
+                if high {
+                    port.drive |= mask;
+                } else {
+                    port.drive &= !mask;
+                }
+            },
+            None => {
+                port.driven &= !mask;
+            }
+        });
+    }
+
+    /// Level of pin `bit` of port `index`
+    pub fn pin(&self, index: usize, bit: u8) -> bool {
+        self.ports[index].pins() & (1 << bit) != 0
+    }
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use crate::{parse_number, Budget, HostInterface, Mcu, StopReason};
+
+/// Data and command port pair of a host interface channel, status is read from the command port
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PortPair {
+    pub data: u16,
+    pub command: u16,
+}
+
+impl PortPair {
+    /// Keyboard controller
+    pub const KBC: Self = Self { data: 0x60, command: 0x64 };
+    /// ACPI embedded controller
+    pub const PMC: Self = Self { data: 0x62, command: 0x66 };
+}
+
+/// ACPI embedded controller commands
+const RD_EC: u8 = 0x80;
+const WR_EC: u8 = 0x81;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HostError {
+    /// Firmware did not clear IBF or set OBF in time
+    Timeout { port: u16 },
+    /// Firmware stopped while the host was waiting
+    Stopped(StopReason),
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostError::Timeout { port } => write!(f, "timeout waiting on port 0x{:02X}", port),
+            HostError::Stopped(reason) => write!(f, "firmware stopped: {:?}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HostError {}
+
+/// Host side of the embedded controller interface, following the IBF and OBF handshakes
+/// Waiting runs firmware, for at most `timeout` machine cycles per wait.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Host {
+    pub timeout: u64,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self { timeout: 1_000_000 }
+    }
+
+    /// Run firmware until the status of `ports` has `mask` bits equal to `value`
+    fn wait(&self, mcu: &mut Mcu, ports: PortPair, mask: u8, value: u8) -> Result<(), HostError> {
+        let ready = |mcu: &mut Mcu| mcu.io_read(ports.command) & mask == value;
+        if ready(mcu) {
+            return Ok(());
+        }
+        match mcu.run(Budget::Cycles(self.timeout), ready) {
+            StopReason::Predicate => Ok(()),
+            StopReason::Budget => Err(HostError::Timeout { port: ports.command }),
+            reason => Err(HostError::Stopped(reason)),
+        }
+    }
+
+    /// Write a command byte once the input buffer is empty
+    pub fn command(&self, mcu: &mut Mcu, ports: PortPair, value: u8) -> Result<(), HostError> {
+        self.wait(mcu, ports, HostInterface::IBF, 0)?;
+        mcu.io_write(ports.command, value);
+        Ok(())
+    }
+
+    /// Write a data byte once the input buffer is empty
+    pub fn write(&self, mcu: &mut Mcu, ports: PortPair, value: u8) -> Result<(), HostError> {
+        self.wait(mcu, ports, HostInterface::IBF, 0)?;
+        mcu.io_write(ports.data, value);
+        Ok(())
+    }
+
+    /// Read a data byte once the output buffer is full
+    pub fn read(&self, mcu: &mut Mcu, ports: PortPair) -> Result<u8, HostError> {
+        self.wait(mcu, ports, HostInterface::OBF, HostInterface::OBF)?;
+        Ok(mcu.io_read(ports.data))
+    }
+
+    /// Read EC RAM at `address` with the ACPI RD_EC command
+    pub fn ec_read(&self, mcu: &mut Mcu, address: u8) -> Result<u8, HostError> {
+        self.command(mcu, PortPair::PMC, RD_EC)?;
+        self.write(mcu, PortPair::PMC, address)?;
+        self.read(mcu, PortPair::PMC)
+    }
+
+    /// Write EC RAM at `address` with the ACPI WR_EC command
+    pub fn ec_write(&self, mcu: &mut Mcu, address: u8, value: u8) -> Result<(), HostError> {
+        self.command(mcu, PortPair::PMC, WR_EC)?;
+        self.write(mcu, PortPair::PMC, address)?;
+        self.write(mcu, PortPair::PMC, value)?;
+        // Wait for the last byte to be taken
+        self.wait(mcu, PortPair::PMC, HostInterface::IBF, 0)
+    }
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle one line of the socket protocol, returns the response line
+fn handle(mcu: &mut Mcu, line: &str) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let numbers: Option<Vec<u16>> = words.iter()
+        .skip(1)
+        .map(|word| parse_number(word).ok().filter(|&value| value <= 0xFFFF).map(|value| value as u16))
+        .collect();
+    match (words.first(), numbers.as_deref()) {
+        (Some(&"inb"), Some(&[port])) => format!("0x{:02X}", mcu.io_read(port)),
+        (Some(&"outb"), Some(&[port, value])) if value <= 0xFF => {
+            mcu.io_write(port, value as u8);
+            "ok".to_string()
+        },
+        (Some(&"cycles"), Some(&[])) => mcu.cycles.to_string(),
+        (Some(command), _) => format!("error invalid command '{}'", command),
+        (None, _) => "error empty command".to_string(),
+    }
+}
+
+/// TCP server giving an external host client access to the I/O ports of the emulated EC
+/// The protocol is line based, `inb <port>` answers the byte read like `0x01`, `outb <port>
+/// <value>` answers `ok` and `cycles` answers the machine cycle count. Numbers are decimal or hex
+/// with a 0x prefix. The client polls status ports for the IBF and OBF handshakes while firmware
+/// runs, like an ACPI driver. One client is served at a time.
+pub struct HostServer {
+    listener: TcpListener,
+    client: Option<(TcpStream, Vec<u8>)>,
+}
+
+impl HostServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, client: None })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept a client and answer its complete requests
+    pub fn poll(&mut self, mcu: &mut Mcu) -> io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some((stream, Vec::new()));
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let (stream, buffer) = self.client.as_mut().unwrap();
+        let mut data = [0; 256];
+        let closed = match stream.read(&mut data) {
+            Ok(0) => true,
+            Ok(count) => {
+                buffer.extend_from_slice(&data[..count]);
+                false
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => false,
+            Err(_) => true,
+        };
+
+        let mut response = Vec::new();
+        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if ! line.trim().is_empty() {
+                response.extend_from_slice(handle(mcu, line.trim()).as_bytes());
+                response.push(b'\n');
+            }
+        }
+        let written = write_all(stream, &response);
+        if closed || written.is_err() {
+            self.client = None;
+        }
+        Ok(())
+    }
+}
+
+/// Write to a non-blocking stream, waiting while its buffer is full
+fn write_all(stream: &mut TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while ! data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(count) => data = &data[count..],
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
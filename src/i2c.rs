@@ -0,0 +1,648 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::Addr;
+
+/// Virtual device on an I2C bus
+/// The bus handles bit timing and acknowledgement, devices see whole bytes
+pub trait I2cDevice {
+    /// Returns true if the device answers to the 7-bit `address`
+    fn responds(&self, address: u8) -> bool;
+
+    /// START or repeated START addressed to this device
+    fn start(&mut self, _address: u8, _read: bool) {}
+
+    /// Byte written by the master, returns true to acknowledge it
+    fn write(&mut self, value: u8) -> bool;
+
+    /// Next byte to send to the master
+    fn read(&mut self) -> u8;
+
+    /// STOP after a transaction with this device
+    fn stop(&mut self) {}
+
+    /// Machine cycles to hold SCL low after each acknowledged byte
+    fn stretch(&self) -> u64 {
+        0
+    }
+}
+
+/// Devices shared with the host, so tests can inspect them while attached
+impl<T: I2cDevice> I2cDevice for Rc<RefCell<T>> {
+    fn responds(&self, address: u8) -> bool {
+        self.borrow().responds(address)
+    }
+
+    fn start(&mut self, address: u8, read: bool) {
+        self.borrow_mut().start(address, read)
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        self.borrow_mut().write(value)
+    }
+
+    fn read(&mut self) -> u8 {
+        self.borrow_mut().read()
+    }
+
+    fn stop(&mut self) {
+        self.borrow_mut().stop()
+    }
+
+    fn stretch(&self) -> u64 {
+        self.borrow().stretch()
+    }
+}
+
+/// Transaction step decoded from the bus, `ack` is true if the receiver pulled SDA low
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum I2cEvent {
+    Start,
+    Address {
+        address: u8,
+        read: bool,
+        ack: bool,
+    },
+    Write {
+        value: u8,
+        ack: bool,
+    },
+    Read {
+        value: u8,
+        ack: bool,
+    },
+    Stop,
+}
+
+/// Operation of the host master, used to test firmware acting as an I2C target
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum I2cOp {
+    Start,
+    /// Write a byte, the address byte included
+    Write(u8),
+    /// Read a byte and acknowledge it if `ack` is true
+    Read {
+        ack: bool,
+    },
+    Stop,
+}
+
+/// I2C controller in special function registers, with the S1CON, S1STA, S1DAT and S1ADR layout of
+/// the SIO1 block in the Philips 80C552 and similar parts
+/// Only master modes are emulated. Setting STA starts a transaction, then each clear of SI
+/// performs the next step at once, reports it in S1STA and sets SI again. Firmware polls SI, the
+/// block raises no interrupt.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct I2cSfr {
+    /// Register addresses, S1CON, S1STA, S1DAT and S1ADR
+    pub s1con: u8,
+    pub s1sta: u8,
+    pub s1dat: u8,
+    pub s1adr: u8,
+    pub control: u8,
+    pub status: u8,
+    pub data: u8,
+    pub address: u8,
+}
+
+impl I2cSfr {
+    pub const ENS1: u8 = 1 << 6;
+    pub const STA: u8 = 1 << 5;
+    pub const STO: u8 = 1 << 4;
+    pub const SI: u8 = 1 << 3;
+    pub const AA: u8 = 1 << 2;
+
+    /// No transaction in progress
+    pub const IDLE: u8 = 0xF8;
+
+    pub fn new(s1con: u8, s1sta: u8, s1dat: u8, s1adr: u8) -> Self {
+        Self {
+            s1con,
+            s1sta,
+            s1dat,
+            s1adr,
+            control: 0,
+            status: Self::IDLE,
+            data: 0,
+            address: 0,
+        }
+    }
+
+    /// Registers of the Philips 80C552
+    pub fn p80c552() -> Self {
+        Self::new(0xD8, 0xD9, 0xDA, 0xDB)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum MasterStep {
+    /// Pull SDA low or release it
+    Sda(bool),
+    /// Pull SCL low or release it
+    Scl(bool),
+    /// Wait for SCL to go high, as a target may stretch the clock
+    WaitScl,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Master {
+    steps: VecDeque<MasterStep>,
+    half_period: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Idle,
+    Address,
+    Write,
+    Read,
+}
+
+/// Bit-banged I2C bus on two GPIO pins
+/// Lines are open-drain, each is the wired-AND of the port latch and any virtual driver. All
+/// transactions on the bus are decoded into `log`, whether or not a virtual device takes part.
+pub struct I2cBus {
+    /// Port and bit of the clock line
+    pub scl: (u8, u8),
+    /// Port and bit of the data line
+    pub sda: (u8, u8),
+    pub devices: Vec<Box<dyn I2cDevice>>,
+    pub log: Vec<I2cEvent>,
+    phase: Phase,
+    /// Line levels after the last update
+    last: (bool, bool),
+    /// Rising clock edges in the current byte, the ninth is the acknowledge bit
+    bit: u8,
+    shift: u8,
+    /// Direction of the data phase, from the address byte
+    reading: bool,
+    selected: Option<usize>,
+    /// Acknowledge of the selected device for the byte just received
+    device_ack: bool,
+    /// Byte the selected device is transmitting
+    transmit: Option<u8>,
+    pub(crate) device_sda_low: bool,
+    pub(crate) device_scl_low: bool,
+    pub(crate) master_sda_low: bool,
+    pub(crate) master_scl_low: bool,
+    pub(crate) master: Option<Master>,
+    /// Controller in special function registers, which drives the bus instead of the pins
+    pub sfr: Option<I2cSfr>,
+}
+
+impl I2cBus {
+    pub fn new(scl: (u8, u8), sda: (u8, u8)) -> Self {
+        Self {
+            scl,
+            sda,
+            devices: Vec::new(),
+            log: Vec::new(),
+            phase: Phase::Idle,
+            last: (true, true),
+            bit: 0,
+            shift: 0,
+            reading: false,
+            selected: None,
+            device_ack: false,
+            transmit: None,
+            device_sda_low: false,
+            device_scl_low: false,
+            master_sda_low: false,
+            master_scl_low: false,
+            master: None,
+            sfr: None,
+        }
+    }
+
+    /// Bus driven by an SFR controller
+    pub fn with_sfr(sfr: I2cSfr) -> Self {
+        Self {
+            sfr: Some(sfr),
+            ..Self::controller()
+        }
+    }
+
+    /// Bus only used through the byte-level interface, by a controller like an SMBus host block
+    pub fn controller() -> Self {
+        Self::new((0, 0), (0, 0))
+    }
+
+    pub fn attach<D: I2cDevice + 'static>(&mut self, device: D) {
+        self.devices.push(Box::new(device));
+    }
+
+    /// Byte-level START or repeated START for controllers that bypass the pins, like SMBus host
+    /// blocks. Returns true if a device acknowledged the address. Events are logged as on the pins.
+    pub fn begin(&mut self, address: u8, read: bool) -> bool {
+        self.selected = self.devices.iter().position(|device| device.responds(address));
+        if let Some(index) = self.selected {
+            self.devices[index].start(address, read);
+        }
+        let ack = self.selected.is_some();
+        self.log.push(I2cEvent::Start);
+        self.log.push(I2cEvent::Address { address, read, ack });
+        ack
+    }
+
+    /// Byte-level write to the addressed device, returns its acknowledge
+    pub fn send(&mut self, value: u8) -> bool {
+        let ack = match self.selected {
+            Some(index) => self.devices[index].write(value),
+            None => false,
+        };
+        self.log.push(I2cEvent::Write { value, ack });
+        ack
+    }
+
+    /// Byte-level read from the addressed device, acknowledged if more bytes follow
+    /// Reads 0xFF, the idle level of SDA, if no device is addressed.
+    pub fn receive(&mut self, ack: bool) -> u8 {
+        let value = match self.selected {
+            Some(index) => self.devices[index].read(),
+            None => 0xFF,
+        };
+        self.log.push(I2cEvent::Read { value, ack });
+        value
+    }
+
+    /// Byte-level STOP
+    pub fn end(&mut self) {
+        if let Some(index) = self.selected.take() {
+            self.devices[index].stop();
+        }
+        self.log.push(I2cEvent::Stop);
+    }
+
+    /// Byte-level transfer, writing `write` if not empty, then reading `read` bytes after a
+    /// repeated START. Returns `None` if the address or a written byte is not acknowledged.
+    pub fn transfer(&mut self, address: u8, write: &[u8], read: usize) -> Option<Vec<u8>> {
+        let result = self.transfer_with(address, write, read);
+        self.end();
+        result
+    }
+
+    fn transfer_with(&mut self, address: u8, write: &[u8], read: usize) -> Option<Vec<u8>> {
+        if ! write.is_empty() || read == 0 {
+            if ! self.begin(address, false) {
+                return None;
+            }
+            for &value in write.iter() {
+                if ! self.send(value) {
+                    return None;
+                }
+            }
+        }
+        if read == 0 {
+            return Some(Vec::new());
+        }
+
+        if ! self.begin(address, true) {
+            return None;
+        }
+        Some((0..read).map(|i| self.receive(i + 1 < read)).collect())
+    }
+
+    /// Register read of an SFR controller
+    pub(crate) fn load(&self, addr: Addr) -> Option<u8> {
+        match (self.sfr, addr) {
+            (Some(sfr), Addr::Reg(i)) if i == sfr.s1con => Some(sfr.control),
+            (Some(sfr), Addr::Reg(i)) if i == sfr.s1sta => Some(sfr.status),
+            (Some(sfr), Addr::Reg(i)) if i == sfr.s1dat => Some(sfr.data),
+            (Some(sfr), Addr::Reg(i)) if i == sfr.s1adr => Some(sfr.address),
+            _ => None,
+        }
+    }
+
+    /// Register write of an SFR controller, returns false if `addr` is not one of its registers
+    pub(crate) fn store(&mut self, addr: Addr, value: u8) -> bool {
+        let mut sfr = match (self.sfr, addr) {
+            (Some(sfr), Addr::Reg(_)) => sfr,
+            _ => return false,
+        };
+        match addr {
+            Addr::Reg(i) if i == sfr.s1con => {
+                // SI is only cleared by software, clearing it performs the next step
+                let cleared = sfr.control & I2cSfr::SI != 0 && value & I2cSfr::SI == 0;
+                let idle = sfr.status == I2cSfr::IDLE && value & I2cSfr::SI == 0;
+                sfr.control = (value & !I2cSfr::SI) | (sfr.control & value & I2cSfr::SI);
+                if sfr.control & I2cSfr::ENS1 == 0 {
+                    if sfr.status != I2cSfr::IDLE {
+                        self.end();
+                    }
+                    sfr.status = I2cSfr::IDLE;
+                } else if cleared || (idle && sfr.control & I2cSfr::STA != 0) {
+                    self.sfr_step(&mut sfr);
+                }
+            },
+            // The status register is read-only
+            Addr::Reg(i) if i == sfr.s1sta => (),
+            Addr::Reg(i) if i == sfr.s1dat => sfr.data = value,
+            Addr::Reg(i) if i == sfr.s1adr => sfr.address = value,
+            _ => return false,
+        }
+        self.sfr = Some(sfr);
+        true
+    }
+
+    /// Next master step of an SFR controller, reported in its status
+    fn sfr_step(&mut self, sfr: &mut I2cSfr) {
+        let control = sfr.control;
+        if control & I2cSfr::STO != 0 {
+            // STOP does not set SI, STO clears once it is sent
+            self.end();
+            sfr.control &= !I2cSfr::STO;
+            sfr.status = I2cSfr::IDLE;
+            return;
+        }
+
+        sfr.status = if control & I2cSfr::STA != 0 {
+            // The address is sent by the next step
+            if sfr.status == I2cSfr::IDLE { 0x08 } else { 0x10 }
+        } else {
+            match sfr.status {
+                0x08 | 0x10 => {
+                    let read = sfr.data & 1 != 0;
+                    match (read, self.begin(sfr.data >> 1, read)) {
+                        (false, true) => 0x18,
+                        (false, false) => 0x20,
+                        (true, true) => 0x40,
+                        (true, false) => 0x48,
+                    }
+                },
+                // Data may follow whether or not the address or last byte was acknowledged
+                0x18 | 0x20 | 0x28 | 0x30 => if self.send(sfr.data) { 0x28 } else { 0x30 },
+                0x40 | 0x50 => {
+                    let ack = control & I2cSfr::AA != 0;
+                    sfr.data = self.receive(ack);
+                    if ack { 0x50 } else { 0x58 }
+                },
+                // Read address or last read not acknowledged, only a STOP or repeated START continues
+                status => status,
+            }
+        };
+        sfr.control |= I2cSfr::SI;
+    }
+
+    /// Levels driven low by virtual drivers, SCL and SDA
+    pub(crate) fn drives(&self) -> (bool, bool) {
+        (self.device_scl_low || self.master_scl_low, self.device_sda_low || self.master_sda_low)
+    }
+
+    /// Queue host master operations, which take `half_period` cycles per clock phase
+    pub(crate) fn queue(&mut self, ops: &[I2cOp], half_period: u64) {
+        let master = self.master.get_or_insert_with(|| Master {
+            steps: VecDeque::new(),
+            half_period,
+        });
+        master.half_period = half_period;
+
+        let steps = &mut master.steps;
+        let clock = |steps: &mut VecDeque<MasterStep>| {
+            steps.extend(&[MasterStep::Scl(false), MasterStep::WaitScl, MasterStep::Scl(true)]);
+        };
+        for &op in ops.iter() {
+            match op {
+                I2cOp::Start => steps.extend(&[
+                    MasterStep::Sda(false),
+                    MasterStep::Scl(false),
+                    MasterStep::WaitScl,
+                    MasterStep::Sda(true),
+                    MasterStep::Scl(true),
+                ]),
+                I2cOp::Write(value) => {
+                    for i in (0..8).rev() {
+                        steps.push_back(MasterStep::Sda(value & (1 << i) == 0));
+                        clock(steps);
+                    }
+                    steps.push_back(MasterStep::Sda(false));
+                    clock(steps);
+                },
+                I2cOp::Read { ack } => {
+                    steps.push_back(MasterStep::Sda(false));
+                    for _ in 0..8 {
+                        clock(steps);
+                    }
+                    steps.push_back(MasterStep::Sda(ack));
+                    clock(steps);
+                    steps.push_back(MasterStep::Sda(false));
+                },
+                I2cOp::Stop => steps.extend(&[
+                    MasterStep::Sda(true),
+                    MasterStep::Scl(false),
+                    MasterStep::WaitScl,
+                    MasterStep::Sda(false),
+                ]),
+            }
+        }
+    }
+
+    /// Perform the next host master step, returns the cycles until the following one or `None`
+    /// when all operations are done
+    pub(crate) fn master_step(&mut self, scl: bool) -> Option<u64> {
+        let master = self.master.as_mut()?;
+        let step = *master.steps.front()?;
+        match step {
+            MasterStep::Sda(low) => self.master_sda_low = low,
+            MasterStep::Scl(low) => self.master_scl_low = low,
+            // Poll every cycle while the clock is stretched
+            MasterStep::WaitScl if ! scl => return Some(1),
+            MasterStep::WaitScl => (),
+        }
+        master.steps.pop_front();
+        Some(master.half_period)
+    }
+
+    /// Returns true if the host master has steps left
+    pub fn master_busy(&self) -> bool {
+        self.master.as_ref().is_some_and(|master| ! master.steps.is_empty())
+    }
+
+    /// Follow a change in line levels, returns cycles to stretch the clock for
+    pub(crate) fn update(&mut self, scl: bool, sda: bool) -> u64 {
+        let (last_scl, last_sda) = self.last;
+        let mut stretch = 0;
+
+        if scl && ! last_scl {
+            self.rising(sda);
+        } else if ! scl && last_scl {
+            stretch = self.falling();
+        } else if scl && last_sda && ! sda {
+            self.started();
+        } else if scl && ! last_sda && sda {
+            self.stopped();
+        }
+
+        self.last = (scl, sda);
+        stretch
+    }
+
+    fn started(&mut self) {
+        debug!(" ; i2c start");
+        self.log.push(I2cEvent::Start);
+        self.phase = Phase::Address;
+        self.bit = 0;
+        self.shift = 0;
+        self.transmit = None;
+        self.device_sda_low = false;
+    }
+
+    fn stopped(&mut self) {
+        debug!(" ; i2c stop");
+        self.log.push(I2cEvent::Stop);
+        if let Some(index) = self.selected.take() {
+            self.devices[index].stop();
+        }
+        self.phase = Phase::Idle;
+        self.transmit = None;
+        self.device_sda_low = false;
+    }
+
+    fn rising(&mut self, sda: bool) {
+        if self.phase == Phase::Idle {
+            return;
+        }
+
+        self.bit += 1;
+        if self.bit <= 8 {
+            self.shift = self.shift << 1 | sda as u8;
+        }
+
+        if self.bit == 8 {
+            // Byte received, decide on the acknowledge of the device
+            self.device_ack = match self.phase {
+                Phase::Address => {
+                    let address = self.shift >> 1;
+                    let read = self.shift & 1 != 0;
+                    self.selected = self.devices.iter().position(|device| device.responds(address));
+                    match self.selected {
+                        Some(index) => {
+                            self.devices[index].start(address, read);
+                            true
+                        },
+                        None => false,
+                    }
+                },
+                Phase::Write => match self.selected {
+                    Some(index) => self.devices[index].write(self.shift),
+                    None => false,
+                },
+                _ => false,
+            };
+        } else if self.bit == 9 {
+            let ack = ! sda;
+            let value = self.shift;
+            self.log.push(match self.phase {
+                Phase::Address => {
+                    self.reading = value & 1 != 0;
+                    I2cEvent::Address {
+                        address: value >> 1,
+                        read: self.reading,
+                        ack,
+                    }
+                },
+                Phase::Write => I2cEvent::Write { value, ack },
+                _ => I2cEvent::Read { value, ack },
+            });
+            // The transmitter continues only if the master acknowledged
+            if self.phase == Phase::Read && ! ack {
+                self.transmit = None;
+            }
+            debug!(" ; i2c {:?}", self.log.last().unwrap());
+        }
+    }
+
+    fn falling(&mut self) -> u64 {
+        if self.phase == Phase::Idle {
+            return 0;
+        }
+
+        match self.bit {
+            0 ..= 7 => {
+                if let Some(value) = self.transmit {
+                    // Next data bit, the first was set up after the acknowledge
+                    if self.bit > 0 {
+                        self.device_sda_low = value & (0x80 >> self.bit) == 0;
+                    }
+                }
+                0
+            },
+            8 => {
+                // Acknowledge a received byte, or release SDA for the master to acknowledge
+                self.device_sda_low = self.transmit.is_none() && self.device_ack;
+                0
+            },
+            _ => {
+                self.bit = 0;
+                self.shift = 0;
+                self.device_sda_low = false;
+                let acked = self.device_ack || self.transmit.is_some();
+                if self.phase == Phase::Address {
+                    self.phase = if self.reading { Phase::Read } else { Phase::Write };
+                }
+                self.transmit = None;
+
+                let index = match self.selected {
+                    Some(index) if acked => index,
+                    _ => return 0,
+                };
+                if self.phase == Phase::Read {
+                    let value = self.devices[index].read();
+                    self.transmit = Some(value);
+                    self.device_sda_low = value & 0x80 == 0;
+                }
+                self.devices[index].stretch()
+            },
+        }
+    }
+}
+
+/// Target that records what it receives and answers reads from a queue
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedTarget {
+    pub address: u8,
+    /// Bytes returned by reads, 0xFF once empty
+    pub responses: VecDeque<u8>,
+    /// Writes are not acknowledged when set
+    pub nack: bool,
+    /// Machine cycles to stretch the clock after each byte
+    pub stretch: u64,
+    pub log: Vec<I2cEvent>,
+}
+
+impl ScriptedTarget {
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            ..Self::default()
+        }
+    }
+}
+
+impl I2cDevice for ScriptedTarget {
+    fn responds(&self, address: u8) -> bool {
+        address == self.address
+    }
+
+    fn start(&mut self, address: u8, read: bool) {
+        self.log.push(I2cEvent::Start);
+        self.log.push(I2cEvent::Address { address, read, ack: true });
+    }
+
+    fn write(&mut self, value: u8) -> bool {
+        self.log.push(I2cEvent::Write { value, ack: ! self.nack });
+        ! self.nack
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.responses.pop_front().unwrap_or(0xFF);
+        self.log.push(I2cEvent::Read { value, ack: true });
+        value
+    }
+
+    fn stop(&mut self) {
+        self.log.push(I2cEvent::Stop);
+    }
+
+    fn stretch(&self) -> u64 {
+        self.stretch
+    }
+}
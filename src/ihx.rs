@@ -0,0 +1,58 @@
+/// Parse an Intel HEX file into a program memory image, with addresses moved up by `offset`
+/// Unwritten bytes in the image are 0xFF, like erased flash
+pub fn parse_ihx(source: &str, offset: u16) -> Result<Vec<u8>, String> {
+    let mut image = Vec::new();
+    let mut base = 0u32;
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |message: &str| format!("line {}: {}", index + 1, message);
+
+        let hex = line.strip_prefix(':').ok_or_else(|| err("missing start code"))?;
+        if hex.len() % 2 != 0 || ! hex.is_ascii() {
+            return Err(err("invalid hex"));
+        }
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| err("invalid hex"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(err("invalid record length"));
+        }
+        if bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(err("invalid checksum"));
+        }
+
+        let address = (bytes[1] as u32) << 8 | bytes[2] as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            // Data
+            0x00 => {
+                let start = base + address + offset as u32;
+                let end = start + data.len() as u32;
+                if end > 0x10000 {
+                    return Err(err("data beyond 64 KiB"));
+                }
+                if image.len() < end as usize {
+                    image.resize(end as usize, 0xFF);
+                }
+                image[start as usize..end as usize].copy_from_slice(data);
+            },
+            // End of file
+            0x01 => break,
+            // Extended segment and linear address
+            0x02 | 0x04 if data.len() == 2 => {
+                let value = (data[0] as u32) << 8 | data[1] as u32;
+                base = if bytes[3] == 0x02 { value << 4 } else { value << 16 };
+            },
+            // Start address
+            0x03 | 0x05 => (),
+            kind => return Err(err(&format!("unsupported record type {:02X}", kind))),
+        }
+    }
+
+    Ok(image)
+}
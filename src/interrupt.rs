@@ -0,0 +1,207 @@
+use crate::{Reg, Variant};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Interrupt {
+    /// External interrupt 0, flag IE0
+    External0,
+    /// Timer 0 overflow, flag TF0
+    Timer0,
+    /// External interrupt 1, flag IE1
+    External1,
+    /// Timer 1 overflow, flag TF1
+    Timer1,
+    /// Serial port, flags RI and TI
+    Serial,
+    /// Timer 2 overflow or capture, flags TF2 and EXF2
+    Timer2,
+    /// Requested by an attached peripheral, like an ADC
+    Peripheral(PeripheralInterrupt),
+}
+
+/// Vector and bit addresses of the enable and priority bits of a peripheral interrupt, which
+/// differ between chips
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeripheralInterrupt {
+    pub vector: u16,
+    pub enable: u8,
+    pub priority: u8,
+}
+
+impl Interrupt {
+    /// Core interrupts in polling order, peripheral interrupts are polled after them
+    pub const ALL: [Interrupt; 6] = [
+        Interrupt::External0,
+        Interrupt::Timer0,
+        Interrupt::External1,
+        Interrupt::Timer1,
+        Interrupt::Serial,
+        Interrupt::Timer2,
+    ];
+
+    /// Position in IE and IP, and of the vector
+    fn index(self) -> u8 {
+        match self {
+            Interrupt::External0 => 0,
+            Interrupt::Timer0 => 1,
+            Interrupt::External1 => 2,
+            Interrupt::Timer1 => 3,
+            Interrupt::Serial => 4,
+            Interrupt::Timer2 => 5,
+            Interrupt::Peripheral(_) => unreachable!(),
+        }
+    }
+
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::Peripheral(peripheral) => peripheral.vector,
+            _ => 0x03 + (self.index() as u16) * 8,
+        }
+    }
+
+    /// Bit address of the enable bit in IE
+    pub fn enable(self) -> u8 {
+        match self {
+            Interrupt::Peripheral(peripheral) => peripheral.enable,
+            _ => 0xA8 + self.index(),
+        }
+    }
+
+    /// Bit address of the priority bit in IP
+    pub fn priority(self) -> u8 {
+        match self {
+            Interrupt::Peripheral(peripheral) => peripheral.priority,
+            _ => 0xB8 + self.index(),
+        }
+    }
+
+    /// Bit addresses of the request flags, empty for peripheral interrupts, which are requested by
+    /// the peripheral
+    pub fn flags(self) -> &'static [u8] {
+        match self {
+            Interrupt::External0 => &[0x89],
+            Interrupt::Timer0 => &[0x8D],
+            Interrupt::External1 => &[0x8B],
+            Interrupt::Timer1 => &[0x8F],
+            Interrupt::Serial => &[0x98, 0x99],
+            Interrupt::Timer2 => &[0xCF, 0xCE],
+            Interrupt::Peripheral(_) => &[],
+        }
+    }
+
+    /// Bit address of the trigger mode bit in TCON for external interrupts, set for falling edge
+    /// and clear for low level
+    pub fn trigger(self) -> Option<u8> {
+        match self {
+            Interrupt::External0 => Some(0x88),
+            Interrupt::External1 => Some(0x8A),
+            _ => None,
+        }
+    }
+
+    /// Port and bit of the pin for external interrupts
+    pub fn pin(self) -> Option<(u8, u8)> {
+        match self {
+            Interrupt::External0 => Some((3, 2)),
+            Interrupt::External1 => Some((3, 3)),
+            _ => None,
+        }
+    }
+
+    /// Bit address of the request flag cleared by hardware when vectoring
+    /// External interrupt flags are only cleared in edge triggered mode
+    pub fn clear_on_vector(self) -> Option<u8> {
+        match self {
+            Interrupt::External0 => Some(0x89),
+            Interrupt::Timer0 => Some(0x8D),
+            Interrupt::External1 => Some(0x8B),
+            Interrupt::Timer1 => Some(0x8F),
+            Interrupt::Serial | Interrupt::Timer2 | Interrupt::Peripheral(_) => None,
+        }
+    }
+
+    pub fn available(self, variant: Variant) -> bool {
+        match self {
+            Interrupt::Timer2 => variant != Variant::I8051,
+            _ => true,
+        }
+    }
+}
+
+/// Interrupt controller state that is not visible in special function registers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Interrupts {
+    /// Interrupt in progress at low and high priority
+    pub active: [bool; 2],
+    /// Interrupts are not serviced for one instruction after reti
+    pub blocked: bool,
+}
+
+impl Interrupts {
+    /// Priority level that a request must exceed to be serviced
+    fn level(&self) -> Option<usize> {
+        if self.active[1] {
+            Some(1)
+        } else if self.active[0] {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Find the enabled and requested interrupt that should be serviced next
+    pub fn pending<R: Reg + ?Sized>(&self, reg: &R) -> Option<(Interrupt, usize)> {
+        if self.blocked {
+            return None;
+        }
+
+        let bit = |reg: &R, bit: u8| {
+            let (address, mask) = reg.bit(bit);
+            reg.load(address) & mask != 0
+        };
+
+        // EA
+        if ! bit(reg, 0xAF) {
+            return None;
+        }
+
+        let variant = reg.variant();
+        let mut next: Option<(Interrupt, usize)> = None;
+        for interrupt in Interrupt::ALL.iter().cloned().chain(reg.peripheral_request()) {
+            if ! interrupt.available(variant) || ! bit(reg, interrupt.enable()) {
+                continue;
+            }
+
+            let peripheral = matches!(interrupt, Interrupt::Peripheral(_));
+            if ! peripheral && ! interrupt.flags().iter().any(|&flag| bit(reg, flag)) {
+                continue;
+            }
+
+            let priority = bit(reg, interrupt.priority()) as usize;
+            if next.is_none_or(|(_, next_priority)| priority > next_priority) {
+                next = Some((interrupt, priority));
+            }
+        }
+
+        next.filter(|&(_, priority)| {
+            self.level().is_none_or(|level| priority > level)
+        })
+    }
+
+    /// Returns true if a low priority request from outside IE, such as a watchdog interrupt, can be serviced
+    pub fn accepts_low(&self) -> bool {
+        ! self.blocked && self.level().is_none()
+    }
+
+    pub fn enter(&mut self, priority: usize) {
+        self.active[priority] = true;
+    }
+
+    pub fn exit(&mut self) {
+        if self.active[1] {
+            self.active[1] = false;
+        } else {
+            self.active[0] = false;
+        }
+        self.blocked = true;
+    }
+}
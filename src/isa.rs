@@ -1,10 +1,69 @@
-use crate::{Addr, Mem, Reg};
+use crate::{Addr, Error, Mem, Reg, Reset};
+
+/// Machine cycles taken by each opcode
+pub const CYCLES: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0
+    2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 1
+    2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 2
+    2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 3
+    2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4
+    2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5
+    2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6
+    2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 7
+    2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // 8
+    2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9
+    2, 2, 1, 2, 4, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // A
+    2, 2, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // B
+    2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // C
+    2, 2, 1, 1, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, // D
+    2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // E
+    2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // F
+];
 
 pub trait Isa: Mem + Reg {
     fn pc(&self) -> u16;
 
     fn set_pc(&mut self, value: u16);
 
+    /// Called after each instruction or interrupt call with the machine cycles it took
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Called before each instruction, returns the vector of an interrupt to service
+    fn interrupt(&mut self) -> Option<u16> {
+        None
+    }
+
+    /// Called with the address of each instruction before its opcode is fetched
+    fn fetch(&mut self, _pc: u16) {}
+
+    /// Called by reti
+    fn interrupt_return(&mut self) {}
+
+    /// Returns true if the processor is stopped and instructions must not be executed
+    fn halted(&self) -> bool {
+        false
+    }
+
+    /// Called before each instruction after interrupts, returns true if the processor is in a low
+    /// power mode and the instruction must not be executed
+    fn sleep(&mut self) -> bool {
+        false
+    }
+
+    /// Called by reset to write the reset value of a register
+    fn reset_store(&mut self, addr: Addr, value: u8) {
+        self.store(addr, value);
+    }
+
+    /// Called at the end of reset to reset state outside of special function registers
+    fn reset_peripherals(&mut self, _source: Reset) {}
+
+    /// Called after each step, returns a fault raised by a memory access
+    fn fault(&mut self) -> Option<Error> {
+        None
+    }
+
     fn reljmp(&mut self, offset: i8) {
         let pc = self.pc().wrapping_add((offset as i16) as u16);
         self.set_pc(pc);
@@ -53,14 +112,122 @@ pub trait Isa: Mem + Reg {
         self.store(self.psw(), psw);
     }
 
-    fn reset(&mut self) {
+    fn carry(&self) -> bool {
+        self.load(self.psw()) & (1 << 7) != 0
+    }
+
+    fn set_carry(&mut self, carry: bool) {
+        let psw = self.load(self.psw());
+        if carry {
+            self.store(self.psw(), psw | (1 << 7));
+        } else {
+            self.store(self.psw(), psw & !(1 << 7));
+        }
+    }
+
+    fn set_overflow(&mut self, overflow: bool) {
+        let psw = self.load(self.psw());
+        if overflow {
+            self.store(self.psw(), psw | (1 << 2));
+        } else {
+            self.store(self.psw(), psw & !(1 << 2));
+        }
+    }
+
+    /// Set the parity flag from the accumulator
+    fn update_parity(&mut self) {
+        let parity = self.load(self.a()).count_ones() & 1 != 0;
+        let psw = self.load(self.psw());
+        if parity {
+            self.store(self.psw(), psw | 1);
+        } else {
+            self.store(self.psw(), psw & !1);
+        }
+    }
+
+    fn load_bit(&mut self, bit: u8) -> bool {
+        let (address, mask) = self.bit(bit);
+        self.load(address) & mask != 0
+    }
+
+    /// Source of arithmetic and logic instructions, immediate data if the low nibble is 4
+    fn source(&mut self, op: u8) -> u8 {
+        if (op & 0xF) == 4 {
+            let value = self.load_pc();
+            debug!(" #0x{:02X}", value);
+            value
+        } else {
+            let operand = self.operand(op);
+            self.load(operand)
+        }
+    }
+
+    /// Add `value` and `carry` to a
+    fn add_a(&mut self, value: u8, carry: bool) {
+        let old = self.load(self.a());
+        let carry = carry as i16;
+
+        // Set carry if sum is greater than 0xFF
+        let sum = old as i16 + value as i16 + carry;
+        // Set auxiliary carry if low nibble sum is greater than 0xF
+        let aux_carry = ((old & 0xF) as i16 + (value & 0xF) as i16 + carry) > 0xF;
+        // Set overflow flag if signed result is not within range
+        let signed = (old as i8) as i16 + (value as i8) as i16 + carry;
+        let overflow = !(-128..=127).contains(&signed);
+        self.update_psw(sum > 0xFF, aux_carry, overflow);
+
+        let new = sum as u8;
+        self.store(self.a(), new);
+        debug!(" ; 0x{:02X} += 0x{:02X} + {} => 0x{:02X}", old, value, carry, new);
+    }
+
+    /// Subtract `value` and `borrow` from a
+    fn subb_a(&mut self, value: u8, borrow: bool) {
+        let old = self.load(self.a());
+        let borrow = borrow as i16;
+
+        // Set carry if value and borrow being subtracted is greater than a
+        let difference = old as i16 - value as i16 - borrow;
+        // Set auxiliary carry if low nibble needs a borrow
+        let aux_carry = ((old & 0xF) as i16 - (value & 0xF) as i16 - borrow) < 0;
+        // Set overflow flag if signed result is not within range
+        let signed = (old as i8) as i16 - (value as i8) as i16 - borrow;
+        let overflow = !(-128..=127).contains(&signed);
+        self.update_psw(difference < 0, aux_carry, overflow);
+
+        let new = difference as u8;
+        self.store(self.a(), new);
+        debug!(" ; 0x{:02X} -= 0x{:02X} + {} => 0x{:02X}", old, value, borrow, new);
+    }
+
+    /// Reset special function registers and update reset status flags for `source`
+    /// Internal and external RAM are preserved, even for power-on reset
+    fn reset(&mut self, source: Reset) {
+        debug!("reset {:?}\n", source);
+
         self.set_pc(0);
 
-        for i in 0x00..=0xFF {
-            self.store(Addr::Reg(i), 0);
+        let variant = self.variant();
+        let status = variant.reset_status().map(|(address, mask)| {
+            (address, mask, self.load(address) & mask)
+        });
+
+        for i in 0x80..=0xFF {
+            if ! variant.has_sfr(i) {
+                continue;
+            }
+            if let Some(value) = variant.sfr_reset(i) {
+                self.reset_store(Addr::Reg(i), value);
+            }
+        }
+
+        if let Some((address, mask, old)) = status {
+            let value = variant.reset_status_value(source).unwrap_or(old);
+            let other = self.load(address) & !mask;
+            self.reset_store(address, other | (value & mask));
         }
 
-        self.store(self.sp(), 7);
+        self.reset_peripherals(source);
     }
 
     fn operand(&mut self, op: u8) -> Addr {
@@ -89,9 +256,31 @@ pub trait Isa: Mem + Reg {
         }
     }
 
-    fn step(&mut self) {
+    /// Execute one instruction or interrupt call
+    /// On an unknown opcode, the program counter is left at the opcode
+    fn step(&mut self) -> Result<(), Error> {
+        if self.halted() {
+            return Ok(());
+        }
+
+        if let Some(vector) = self.interrupt() {
+            debug!("  interrupt 0x{:04X}\n", vector);
+            let pc = self.pc();
+            self.push_sp(pc as u8);
+            self.push_sp((pc >> 8) as u8);
+            self.set_pc(vector);
+            self.tick(2);
+            return self.fault().map_or(Ok(()), Err);
+        }
+
+        if self.sleep() {
+            return self.fault().map_or(Ok(()), Err);
+        }
+
         debug!("  0x{:04X}: ", self.pc());
 
+        let pc = self.pc();
+        self.fetch(pc);
         let op = self.load_pc();
         match op {
             /* nop */
@@ -150,19 +339,19 @@ pub trait Isa: Mem + Reg {
             0x04 ..= 0x0F => {
                 debug!("inc");
                 let operand = self.operand(op);
-                let old = self.load(operand);
+                let old = self.load_rmw(operand);
                 let new = old.wrapping_add(1);
                 self.store(operand, new);
                 debug!(" ; 0x{:02X} => 0x{:02X}", old, new);
             },
 
-            /* jbs bit, offset */
+            /* jbc bit, offset */
             0x10 => {
                 let bit = self.load_pc();
                 let offset = self.load_pc();
-                debug!("jbs 0x{:02X}, 0x{:02X}", bit, offset);
+                debug!("jbc 0x{:02X}, 0x{:02X}", bit, offset);
                 let (address, mask) = self.bit(bit);
-                let value = self.load(address);
+                let value = self.load_rmw(address);
                 if value & mask != 0 {
                     //TODO: value debug
                     self.store(address, value & !mask);
@@ -206,7 +395,7 @@ pub trait Isa: Mem + Reg {
             0x14 ..= 0x1F => {
                 debug!("dec");
                 let operand = self.operand(op);
-                let old = self.load(operand);
+                let old = self.load_rmw(operand);
                 let new = old.wrapping_sub(1);
                 self.store(operand, new);
                 debug!(" ; 0x{:02X} => 0x{:02X}", old, new);
@@ -251,29 +440,8 @@ pub trait Isa: Mem + Reg {
             /* add a, operand */
             0x24 ..= 0x2F => {
                 debug!("add a,");
-                let value = if (op & 0xF) == 4 {
-                    let value = self.load_pc();
-                    debug!(" #0x{:02X}", value);
-                    value
-                } else {
-                    let operand = self.operand(op);
-                    self.load(operand)
-                } as i16;
-
-                let old = self.load(self.a()) as i16;
-
-                // Set carry if sum is greater than 0xFF
-                let carry = (value + old) > 0xFF;
-                // Set auxiliary carry if low nibble sum is greater than 0xF
-                let aux_carry = ((value & 0xF) + (old & 0xF)) > 0xF;
-                // Set overflow flag if signed result is not within range
-                let signed = (value as i8) as i16 + (old as i8) as i16;
-                let overflow =  signed > 127 || signed < -128;
-                self.update_psw(carry, aux_carry, overflow);
-
-                let new = (old + value) as u8;
-                self.store(self.a(), new);
-                debug!(" ; 0x{:02X} += 0x{:02X} => 0x{:02X}", old, value, new);
+                let value = self.source(op);
+                self.add_a(value, false);
             },
 
             /* jnb bit, offset */
@@ -291,13 +459,13 @@ pub trait Isa: Mem + Reg {
 
             /* reti */
             0x32 => {
-                //TODO: interrupt enable/disable flag
                 debug!("reti");
                 let pc = {
                     (self.pop_sp() as u16) << 8 |
                     (self.pop_sp() as u16)
                 };
                 self.set_pc(pc);
+                self.interrupt_return();
             },
 
             /* rlc a */
@@ -322,33 +490,9 @@ pub trait Isa: Mem + Reg {
             /* addc a, operand */
             0x34 ..= 0x3F => {
                 debug!("addc a,");
-                let mut value = if (op & 0xF) == 4 {
-                    let value = self.load_pc();
-                    debug!(" #0x{:02X}", value);
-                    value
-                } else {
-                    let operand = self.operand(op);
-                    self.load(operand)
-                } as i16;
-
-                if self.load(self.psw()) & (1 << 7) != 0 {
-                    value += 1;
-                }
-
-                let old = self.load(self.a()) as i16;
-
-                // Set carry if sum is greater than 0xFF
-                let carry = (value + old) > 0xFF;
-                // Set auxiliary carry if low nibble sum is greater than 0xF
-                let aux_carry = ((value & 0xF) + (old & 0xF)) > 0xF;
-                // Set overflow flag if signed result is not within range
-                let signed = (value as i8) as i16 + (old as i8) as i16;
-                let overflow = signed > 127 || signed < -128;
-                self.update_psw(carry, aux_carry, overflow);
-
-                let new = (old + value) as u8;
-                self.store(self.a(), new);
-                debug!(" ; 0x{:02X} += 0x{:02X} => 0x{:02X}", old, value, new);
+                let value = self.source(op);
+                let carry = self.carry();
+                self.add_a(value, carry);
             },
 
             /* jc offset */
@@ -367,7 +511,7 @@ pub trait Isa: Mem + Reg {
                 debug!("orl 0x{:02X}, a", address);
 
                 let value = self.load(self.a());
-                let old = self.load(Addr::Reg(address));
+                let old = self.load_rmw(Addr::Reg(address));
                 let new = old | value;
                 self.store(Addr::Reg(address), new);
                 debug!(" ; 0x{:02X} |= 0x{:02X} => 0x{:02X}", old, value, new);
@@ -379,7 +523,7 @@ pub trait Isa: Mem + Reg {
                 let value = self.load_pc();
                 debug!("orl 0x{:02X}, #0x{:02X}", address, value);
 
-                let old = self.load(Addr::Reg(address));
+                let old = self.load_rmw(Addr::Reg(address));
                 let new = old | value;
                 self.store(Addr::Reg(address), new);
                 debug!(" ; 0x{:02X} |= 0x{:02X} => 0x{:02X}", old, value, new);
@@ -388,14 +532,7 @@ pub trait Isa: Mem + Reg {
             /* orl a, operand */
             0x44 ..= 0x4F => {
                 debug!("orl a,");
-                let value = if (op & 0xF) == 4 {
-                    let value = self.load_pc();
-                    debug!(" #0x{:02X}", value);
-                    value
-                } else {
-                    let operand = self.operand(op);
-                    self.load(operand)
-                };
+                let value = self.source(op);
 
                 let old = self.load(self.a());
                 let new = old | value;
@@ -419,7 +556,7 @@ pub trait Isa: Mem + Reg {
                 debug!("anl 0x{:02X}, a", address);
 
                 let value = self.load(self.a());
-                let old = self.load(Addr::Reg(address));
+                let old = self.load_rmw(Addr::Reg(address));
                 let new = old & value;
                 self.store(Addr::Reg(address), new);
                 debug!(" ; 0x{:02X} &= 0x{:02X} => 0x{:02X}", old, value, new);
@@ -431,7 +568,7 @@ pub trait Isa: Mem + Reg {
                 let value = self.load_pc();
                 debug!("anl 0x{:02X}, #0x{:02X}", address, value);
 
-                let old = self.load(Addr::Reg(address));
+                let old = self.load_rmw(Addr::Reg(address));
                 let new = old & value;
                 self.store(Addr::Reg(address), new);
                 debug!(" ; 0x{:02X} &= 0x{:02X} => 0x{:02X}", old, value, new);
@@ -440,14 +577,7 @@ pub trait Isa: Mem + Reg {
             /* anl a, operand */
             0x54 ..= 0x5F => {
                 debug!("anl a,");
-                let value = if (op & 0xF) == 4 {
-                    let value = self.load_pc();
-                    debug!(" #0x{:02X}", value);
-                    value
-                } else {
-                    let operand = self.operand(op);
-                    self.load(operand)
-                };
+                let value = self.source(op);
 
                 let old = self.load(self.a());
                 let new = old & value;
@@ -471,7 +601,7 @@ pub trait Isa: Mem + Reg {
                 debug!("xrl 0x{:02X}, a", address);
 
                 let value = self.load(self.a());
-                let old = self.load(Addr::Reg(address));
+                let old = self.load_rmw(Addr::Reg(address));
                 let new = old ^ value;
                 self.store(Addr::Reg(address), new);
                 debug!(" ; 0x{:02X} ^= 0x{:02X} => 0x{:02X}", old, value, new);
@@ -483,7 +613,7 @@ pub trait Isa: Mem + Reg {
                 let value = self.load_pc();
                 debug!("xrl 0x{:02X}, #0x{:02X}", address, value);
 
-                let old = self.load(Addr::Reg(address));
+                let old = self.load_rmw(Addr::Reg(address));
                 let new = old ^ value;
                 self.store(Addr::Reg(address), new);
                 debug!(" ; 0x{:02X} ^= 0x{:02X} => 0x{:02X}", old, value, new);
@@ -492,14 +622,7 @@ pub trait Isa: Mem + Reg {
             /* xrl a, operand */
             0x64 ..= 0x6F => {
                 debug!("xrl a,");
-                let value = if (op & 0xF) == 4 {
-                    let value = self.load_pc();
-                    debug!(" #0x{:02X}", value);
-                    value
-                } else {
-                    let operand = self.operand(op);
-                    self.load(operand)
-                };
+                let value = self.source(op);
 
                 let old = self.load(self.a());
                 let new = old ^ value;
@@ -517,6 +640,14 @@ pub trait Isa: Mem + Reg {
                 }
             },
 
+            /* orl c, bit */
+            0x72 => {
+                let bit = self.load_pc();
+                debug!("orl c, 0x{:02X}", bit);
+                let value = self.carry() | self.load_bit(bit);
+                self.set_carry(value);
+            },
+
             /* jmp @a+dptr */
             0x73 => {
                 debug!("jmp @a+dptr");
@@ -536,6 +667,14 @@ pub trait Isa: Mem + Reg {
                 self.store(operand, value);
             },
 
+            /* anl c, bit */
+            0x82 => {
+                let bit = self.load_pc();
+                debug!("anl c, 0x{:02X}", bit);
+                let value = self.carry() & self.load_bit(bit);
+                self.set_carry(value);
+            },
+
             /* sjmp offset */
             0x80 => {
                 let offset = self.load_pc();
@@ -553,6 +692,27 @@ pub trait Isa: Mem + Reg {
                 debug!(" ; 0x{:04X}: 0x{:02X}", address, value);
             },
 
+            /* div ab */
+            0x84 => {
+                debug!("div ab");
+                let a = self.load(self.a());
+                let b = self.load(self.b());
+
+                self.set_carry(false);
+                match a.checked_div(b) {
+                    Some(quotient) => {
+                        self.set_overflow(false);
+                        self.store(self.a(), quotient);
+                        self.store(self.b(), a % b);
+                    },
+                    None => {
+                        // Result is undefined, a and b are left unchanged
+                        self.set_overflow(true);
+                    }
+                }
+                debug!(" ; 0x{:02X} / 0x{:02X}", a, b);
+            },
+
             /* mov address, address */
             0x85 => {
                 let src = self.load_pc();
@@ -586,7 +746,7 @@ pub trait Isa: Mem + Reg {
                 let bit = self.load_pc();
                 debug!("mov 0x{:02X}, c", bit);
                 let (address, mask) = self.bit(bit);
-                let old = self.load(address);
+                let old = self.load_rmw(address);
                 let new = if self.load(self.psw()) & (1 << 7) == 0 {
                     old & !mask
                 } else {
@@ -608,36 +768,28 @@ pub trait Isa: Mem + Reg {
                 debug!(" ; 0x{:04X}: 0x{:02X}", address, value);
             },
 
-            /* sub a, operand */
+            /* subb a, operand */
             0x94 ..= 0x9F => {
                 debug!("subb a,");
-                let mut value = if (op & 0xF) == 4 {
-                    let value = self.load_pc();
-                    debug!(" #0x{:02X}", value);
-                    value
-                } else {
-                    let operand = self.operand(op);
-                    self.load(operand)
-                } as i16;
-
-                if self.load(self.psw()) & (1 << 7) != 0 {
-                    value += 1;
-                }
-
-                let old = self.load(self.a()) as i16;
+                let value = self.source(op);
+                let carry = self.carry();
+                self.subb_a(value, carry);
+            },
 
-                // Set carry if value being subtracted is greater than a
-                let carry = value > old;
-                // Set auxiliary carry if low nibble of value being subtraced is greater than low nibble of a
-                let aux_carry = (value & 0xF) > (old & 0xF);
-                // Set overflow flag if signed result is not within range
-                let signed = (old as i8) as i16 - (value as i8) as i16;
-                let overflow = signed > 127 || signed < -128;
-                self.update_psw(carry, aux_carry, overflow);
+            /* orl c, /bit */
+            0xA0 => {
+                let bit = self.load_pc();
+                debug!("orl c, /0x{:02X}", bit);
+                let value = self.carry() | ! self.load_bit(bit);
+                self.set_carry(value);
+            },
 
-                let new = (old - value) as u8;
-                self.store(self.a(), new);
-                debug!(" ; 0x{:02X} -= 0x{:02X} => 0x{:02X}", old, value, new);
+            /* mov c, bit */
+            0xA2 => {
+                let bit = self.load_pc();
+                debug!("mov c, 0x{:02X}", bit);
+                let value = self.load_bit(bit);
+                self.set_carry(value);
             },
 
             /* inc dptr */
@@ -659,7 +811,8 @@ pub trait Isa: Mem + Reg {
                 let b = self.load(self.b());
 
                 let value = (a as u16) * (b as u16);
-                self.update_psw(false, false, value > 255);
+                self.set_carry(false);
+                self.set_overflow(value > 255);
 
                 self.store(self.a(), value as u8);
                 self.store(self.b(), (value >> 8) as u8);
@@ -677,6 +830,32 @@ pub trait Isa: Mem + Reg {
                 debug!(" ; 0x{:02X}", value);
             },
 
+            /* anl c, /bit */
+            0xB0 => {
+                let bit = self.load_pc();
+                debug!("anl c, /0x{:02X}", bit);
+                let value = self.carry() & ! self.load_bit(bit);
+                self.set_carry(value);
+            },
+
+            /* cpl bit */
+            0xB2 => {
+                let bit = self.load_pc();
+                debug!("cpl 0x{:02X}", bit);
+                let (address, mask) = self.bit(bit);
+                let old = self.load_rmw(address);
+                let new = old ^ mask;
+                self.store(address, new);
+                debug!(" ; 0x{:02X} => 0x{:02X}", old, new);
+            },
+
+            /* cpl c */
+            0xB3 => {
+                debug!("cpl c");
+                let carry = self.carry();
+                self.set_carry(! carry);
+            },
+
             /* cjne operand, #data, offset */
             0xB4 ..= 0xBF => {
                 debug!("cjne");
@@ -724,7 +903,7 @@ pub trait Isa: Mem + Reg {
                 let bit = self.load_pc();
                 debug!("clr 0x{:02X}", bit);
                 let (address, mask) = self.bit(bit);
-                let old = self.load(address);
+                let old = self.load_rmw(address);
                 let new = old & !mask;
                 self.store(address, new);
                 debug!(" ; 0x{:02X} => 0x{:02X}", old, new);
@@ -771,7 +950,7 @@ pub trait Isa: Mem + Reg {
                 let bit = self.load_pc();
                 debug!("setb 0x{:02X}", bit);
                 let (address, mask) = self.bit(bit);
-                let old = self.load(address);
+                let old = self.load_rmw(address);
                 let new = old | mask;
                 self.store(address, new);
                 debug!(" ; 0x{:02X} => 0x{:02X}", old, new);
@@ -784,13 +963,34 @@ pub trait Isa: Mem + Reg {
                 self.store(self.psw(), value | (1 << 7));
             },
 
+            /* da a */
+            0xD4 => {
+                debug!("da a");
+                let old = self.load(self.a());
+                let psw = self.load(self.psw());
+                let mut value = old as u16;
+                if value & 0xF > 9 || psw & (1 << 6) != 0 {
+                    value += 0x06;
+                }
+                if value > 0xFF || (value >> 4) & 0xF > 9 || psw & (1 << 7) != 0 {
+                    value += 0x60;
+                }
+                // Carry is set if the result is greater than 0x99, but never cleared
+                if value > 0xFF {
+                    self.set_carry(true);
+                }
+                let new = value as u8;
+                self.store(self.a(), new);
+                debug!(" ; 0x{:02X} => 0x{:02X}", old, new);
+            },
+
             /* djnz operand, offset */
             0xD5 | 0xD8 ..= 0xDF => {
                 debug!("djnz");
                 let operand = self.operand(op);
                 let offset = self.load_pc();
                 debug!(", 0x{:02X}", offset);
-                let value = self.load(operand).wrapping_sub(1);
+                let value = self.load_rmw(operand).wrapping_sub(1);
                 self.store(operand, value);
                 //TODO: value debug
                 if value != 0 {
@@ -798,6 +998,18 @@ pub trait Isa: Mem + Reg {
                 }
             },
 
+            /* xchd a, @rX */
+            0xD6 ..= 0xD7 => {
+                let r = op - 0xD6;
+                debug!("xchd a, @r{}", r);
+                let address = Addr::IRam(self.load(self.r(r)));
+                let a = self.load(self.a());
+                let value = self.load(address);
+                self.store(self.a(), (a & 0xF0) | (value & 0x0F));
+                self.store(address, (value & 0xF0) | (a & 0x0F));
+                debug!(" ; 0x{:02X} <=> 0x{:02X}", a, value);
+            },
+
             /* movx a, @dptr */
             0xE0 => {
                 debug!("movx a, @dptr");
@@ -805,7 +1017,7 @@ pub trait Isa: Mem + Reg {
                     (self.load(self.dptr(false)) as u16) |
                     (self.load(self.dptr(true)) as u16) << 8
                 };
-                let value = self.load(Addr::XRam(address));
+                let value = self.read(Addr::XRam(address));
                 self.store(self.a(), value);
                 debug!(" ; 0x{:04X}: 0x{:02X}", address, value);
             },
@@ -814,11 +1026,12 @@ pub trait Isa: Mem + Reg {
             0xE2 ..= 0xE3 => {
                 let r = op - 0xE2;
                 debug!("movx a, @r{}", r);
+                // High byte of address comes from the P2 latch
                 let address = {
                     (self.load(self.r(r)) as u16) |
-                    (self.load(self.p(2)) as u16) << 8
+                    (self.load_rmw(self.p(2)) as u16) << 8
                 };
-                let value = self.load(Addr::XRam(address));
+                let value = self.read(Addr::XRam(address));
                 self.store(self.a(), value);
                 debug!(" ; 0x{:04X}: 0x{:02X}", address, value);
             },
@@ -854,9 +1067,10 @@ pub trait Isa: Mem + Reg {
             0xF2 ..= 0xF3 => {
                 let r = op - 0xF2;
                 debug!("movx @r{}, a",r );
+                // High byte of address comes from the P2 latch
                 let address = {
                     (self.load(self.r(r)) as u16) |
-                    (self.load(self.p(2)) as u16) << 8
+                    (self.load_rmw(self.p(2)) as u16) << 8
                 };
                 let value = self.load(self.a());
                 self.store(Addr::XRam(address), value);
@@ -883,9 +1097,17 @@ pub trait Isa: Mem + Reg {
             },
 
             /* unknown opcode */
-            _ => panic!("Unknown opcode: 0x{:02X}", op),
+            _ => {
+                debug!("unknown 0x{:02X}\n", op);
+                self.set_pc(pc);
+                return Err(Error::UnknownOpcode { pc, op });
+            },
         }
 
         debug!("\n");
+
+        self.update_parity();
+        self.tick(CYCLES[op as usize] as u64);
+        self.fault().map_or(Ok(()), Err)
     }
 }
@@ -0,0 +1,793 @@
+use crate::{Addr, I2cBus};
+
+/// ITE embedded controller part
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IteChip {
+    It8587,
+    It5570,
+}
+
+impl IteChip {
+    /// ECHIPID1, ECHIPID2 and ECHIPVER
+    fn id(&self) -> [u8; 3] {
+        match self {
+            IteChip::It8587 => [0x85, 0x87, 0x01],
+            IteChip::It5570 => [0x55, 0x70, 0x02],
+        }
+    }
+}
+
+/// Host interface channel, the keyboard controller or a power management channel
+/// The host writes the data or command port and reads the data port, firmware reads what the host
+/// wrote and writes replies.
+#[derive(Clone, Debug, Default)]
+pub struct HostInterface {
+    status: u8,
+    data_in: u8,
+    data_out: u8,
+    /// Control register, stored for firmware
+    pub control: u8,
+    /// Flags owned by hardware, firmware writes to the status register leave them unchanged
+    hardware: u8,
+}
+
+impl HostInterface {
+    pub const OBF: u8 = 1 << 0;
+    pub const IBF: u8 = 1 << 1;
+    /// Last host write was to the command port
+    pub const CMD: u8 = 1 << 3;
+    /// Keyboard controller output is mouse data
+    pub const AOBF: u8 = 1 << 5;
+
+    fn new(hardware: u8) -> Self {
+        Self {
+            hardware,
+            ..Self::default()
+        }
+    }
+
+    /// Status register as read by the host
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    /// Host write to the data port, or the command port if `command` is true
+    pub fn host_write(&mut self, command: bool, value: u8) {
+        self.data_in = value;
+        let status = self.status | Self::IBF;
+        self.status = if command { status | Self::CMD } else { status & !Self::CMD };
+    }
+
+    /// Host read of the data port, `None` if the output buffer is empty
+    pub fn host_read(&mut self) -> Option<u8> {
+        if self.status & Self::OBF == 0 {
+            return None;
+        }
+        self.status &= !(Self::OBF | Self::AOBF);
+        Some(self.data_out)
+    }
+
+    /// Firmware read of the input buffer, clearing IBF
+    fn read_input(&mut self) {
+        self.status &= !Self::IBF;
+    }
+
+    /// Firmware write to the output buffer
+    fn write_output(&mut self, value: u8, aux: bool) {
+        self.data_out = value;
+        let status = self.status | Self::OBF;
+        self.status = if aux { status | Self::AOBF } else { status & !Self::AOBF };
+    }
+
+    fn write_status(&mut self, value: u8) {
+        self.status = self.status & self.hardware | value & !self.hardware;
+    }
+}
+
+/// Transfer continued by firmware clearing BDS
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Transfer {
+    /// SMBus block read or write with bytes left
+    Block { read: bool, left: u8 },
+    /// I2C compatible transfer, ended by LABY
+    I2c { read: bool },
+}
+
+/// SMBus host controller channel
+/// Transactions complete as soon as they are started, with devices on `bus`. Block and I2C
+/// compatible transfers move one byte through HOBDB each time firmware clears BDS.
+pub struct Smbus {
+    pub bus: I2cBus,
+    pub hosta: u8,
+    pub hoctl: u8,
+    pub hocmd: u8,
+    pub trasla: u8,
+    pub d0reg: u8,
+    pub d1reg: u8,
+    pub hobdb: u8,
+    pub hoctl2: u8,
+    transfer: Option<Transfer>,
+}
+
+impl Smbus {
+    pub const HOBY: u8 = 1 << 0;
+    pub const FINTR: u8 = 1 << 1;
+    pub const DVER: u8 = 1 << 2;
+    pub const BDS: u8 = 1 << 7;
+
+    pub const KILL: u8 = 1 << 1;
+    pub const LABY: u8 = 1 << 5;
+    pub const SRT: u8 = 1 << 6;
+
+    pub const SMHEN: u8 = 1 << 0;
+    pub const I2C_EN: u8 = 1 << 1;
+
+    fn new() -> Self {
+        Self {
+            bus: I2cBus::controller(),
+            hosta: 0,
+            hoctl: 0,
+            hocmd: 0,
+            trasla: 0,
+            d0reg: 0,
+            d1reg: 0,
+            hobdb: 0,
+            hoctl2: 0,
+            transfer: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        let bus = std::mem::replace(&mut self.bus, I2cBus::controller());
+        *self = Self::new();
+        self.bus = bus;
+    }
+
+    /// End the transaction, with an error if not `ok`
+    fn finish(&mut self, ok: bool) {
+        self.bus.end();
+        self.transfer = None;
+        self.hosta = self.hosta & !Self::HOBY | Self::FINTR;
+        if ! ok {
+            self.hosta |= Self::DVER;
+        }
+    }
+
+    /// Byte moved through HOBDB, the transfer continues when firmware clears BDS
+    fn byte_done(&mut self, transfer: Transfer) {
+        self.transfer = Some(transfer);
+        self.hosta |= Self::HOBY | Self::BDS;
+    }
+
+    fn start(&mut self, hoctl: u8) {
+        let address = self.trasla >> 1;
+        let read = self.trasla & 1 != 0;
+        let bus = &mut self.bus;
+        let protocol = hoctl >> 2 & 0b111;
+        debug!(" ; smbus protocol {} address 0x{:02X}", protocol, address);
+        match protocol {
+            // Quick command
+            0b000 => {
+                let ok = bus.begin(address, read);
+                self.finish(ok);
+            },
+            // Send or receive byte
+            0b001 => {
+                let ok = bus.begin(address, read) && (read || bus.send(self.hocmd));
+                if ok && read {
+                    self.d0reg = bus.receive(false);
+                }
+                self.finish(ok);
+            },
+            // Byte and word data, process call
+            0b010 ..= 0b100 => {
+                let word = protocol != 0b010;
+                let call = protocol == 0b100;
+                let mut ok = bus.begin(address, false) && bus.send(self.hocmd);
+                if ! read || call {
+                    ok = ok && bus.send(self.d0reg) && (! word || bus.send(self.d1reg));
+                }
+                if (read || call) && ok && bus.begin(address, true) {
+                    self.d0reg = bus.receive(word);
+                    if word {
+                        self.d1reg = bus.receive(false);
+                    }
+                } else if read || call {
+                    ok = false;
+                }
+                self.finish(ok);
+            },
+            // Block, the count is in D0REG
+            0b101 => {
+                if ! (bus.begin(address, false) && bus.send(self.hocmd)) {
+                    return self.finish(false);
+                }
+                if read {
+                    if ! bus.begin(address, true) {
+                        return self.finish(false);
+                    }
+                    self.d0reg = bus.receive(true);
+                    if self.d0reg == 0 {
+                        return self.finish(true);
+                    }
+                    self.hobdb = bus.receive(self.d0reg > 1);
+                } else if ! (bus.send(self.d0reg) && (self.d0reg == 0 || bus.send(self.hobdb))) {
+                    return self.finish(false);
+                } else if self.d0reg <= 1 {
+                    return self.finish(true);
+                }
+                let left = self.d0reg - 1;
+                if read && left == 0 {
+                    self.hosta |= Self::BDS;
+                    return self.finish(true);
+                }
+                self.byte_done(Transfer::Block { read, left });
+            },
+            // Extended command, I2C compatible when enabled
+            0b111 if self.hoctl2 & Self::I2C_EN != 0 => {
+                if ! bus.begin(address, read) {
+                    return self.finish(false);
+                }
+                if read {
+                    let last = hoctl & Self::LABY != 0;
+                    self.hobdb = bus.receive(! last);
+                    if last {
+                        self.hosta |= Self::BDS;
+                        return self.finish(true);
+                    }
+                } else if ! bus.send(self.hobdb) {
+                    return self.finish(false);
+                }
+                self.byte_done(Transfer::I2c { read });
+            },
+            _ => self.finish(false),
+        }
+    }
+
+    /// Firmware cleared BDS, move the next byte
+    fn next(&mut self) {
+        let last = self.hoctl & Self::LABY != 0;
+        match self.transfer {
+            Some(Transfer::Block { read, left }) => {
+                let ok = if read {
+                    self.hobdb = self.bus.receive(left > 1);
+                    true
+                } else {
+                    self.bus.send(self.hobdb)
+                };
+                if ! ok || left == 1 {
+                    self.hosta |= Self::BDS;
+                    self.finish(ok);
+                } else {
+                    self.byte_done(Transfer::Block { read, left: left - 1 });
+                }
+            },
+            Some(Transfer::I2c { read }) => {
+                let ok = if read {
+                    self.hobdb = self.bus.receive(! last);
+                    true
+                } else {
+                    self.bus.send(self.hobdb)
+                };
+                if ! ok || last {
+                    self.hosta |= Self::BDS;
+                    self.finish(ok);
+                } else {
+                    self.byte_done(Transfer::I2c { read });
+                }
+            },
+            None => (),
+        }
+    }
+
+    fn load(&self, offset: u8) -> u8 {
+        match offset {
+            0 => self.hosta,
+            1 => self.hoctl,
+            2 => self.hocmd,
+            3 => self.trasla,
+            4 => self.d0reg,
+            5 => self.d1reg,
+            _ => self.hobdb,
+        }
+    }
+
+    fn store(&mut self, offset: u8, value: u8) {
+        match offset {
+            0 => {
+                // Write one to clear
+                self.hosta &= !value;
+                if value & Self::BDS != 0 {
+                    self.next();
+                }
+            },
+            1 => {
+                self.hoctl = value & !(Self::SRT | Self::KILL);
+                if value & Self::KILL != 0 {
+                    if self.transfer.is_some() {
+                        self.bus.end();
+                    }
+                    self.transfer = None;
+                    self.hosta &= !Self::HOBY;
+                } else if value & Self::SRT != 0 && self.hoctl2 & Self::SMHEN != 0 {
+                    self.start(value);
+                }
+            },
+            2 => self.hocmd = value,
+            3 => self.trasla = value,
+            4 => self.d0reg = value,
+            5 => self.d1reg = value,
+            _ => self.hobdb = value,
+        }
+    }
+}
+
+/// PECI host controller, with a processor answering at address 0x30
+/// GetTemp returns `temperature`, other reads return a success completion code followed by zeros.
+#[derive(Clone, Debug)]
+pub struct Peci {
+    /// GetTemp reading in 1/64 degrees Celsius relative to the maximum junction temperature
+    pub temperature: i16,
+    pub registers: [u8; 16],
+    read: Vec<u8>,
+    read_index: usize,
+}
+
+impl Peci {
+    pub const HOSTAR: u8 = 0x00;
+    pub const HOCTLR: u8 = 0x01;
+    pub const HOCMDR: u8 = 0x02;
+    pub const HOTRADDR: u8 = 0x03;
+    pub const HOWRLR: u8 = 0x04;
+    pub const HORDLR: u8 = 0x05;
+    pub const HOWRDR: u8 = 0x06;
+    pub const HORDDR: u8 = 0x07;
+
+    pub const HOBY: u8 = 1 << 0;
+    pub const FINISH: u8 = 1 << 1;
+    pub const BUSERR: u8 = 1 << 6;
+    pub const START: u8 = 1 << 0;
+
+    fn new() -> Self {
+        Self {
+            // 20 degrees below the maximum
+            temperature: -20 * 64,
+            registers: [0; 16],
+            read: Vec::new(),
+            read_index: 0,
+        }
+    }
+
+    fn start(&mut self) {
+        let length = self.registers[Self::HORDLR as usize] as usize;
+        let command = self.registers[Self::HOCMDR as usize];
+        debug!(" ; peci command 0x{:02X}", command);
+        self.read = vec![0; length];
+        self.read_index = 0;
+        if self.registers[Self::HOTRADDR as usize] != 0x30 {
+            self.registers[Self::HOSTAR as usize] |= Self::BUSERR | Self::FINISH;
+            return;
+        }
+        match command {
+            // GetTemp
+            0x01 => {
+                for (byte, &value) in self.read.iter_mut().zip(self.temperature.to_le_bytes().iter()) {
+                    *byte = value;
+                }
+            },
+            // Ping has no data
+            0x00 => (),
+            _ => if let Some(code) = self.read.first_mut() {
+                *code = 0x40;
+            },
+        }
+        self.registers[Self::HOSTAR as usize] |= Self::FINISH;
+    }
+
+    fn load(&self, offset: u8) -> u8 {
+        if offset == Self::HORDDR {
+            return self.read.get(self.read_index).cloned().unwrap_or(0);
+        }
+        self.registers[offset as usize]
+    }
+
+    /// Firmware read, HORDDR moves on to the next byte
+    fn read(&mut self, offset: u8) {
+        if offset == Self::HORDDR {
+            self.read_index += 1;
+        }
+    }
+
+    fn store(&mut self, offset: u8, value: u8) {
+        match offset {
+            Self::HOSTAR => self.registers[offset as usize] &= !value,
+            Self::HOCTLR => {
+                self.registers[offset as usize] = value & !Self::START;
+                if value & Self::START != 0 {
+                    self.start();
+                }
+            },
+            _ => self.registers[offset as usize] = value,
+        }
+    }
+}
+
+/// Scratch SRAM blocks, as XRAM base and size, mapped into code space by SCAR0 to SCAR4
+const SCRATCH: [(u16, u16); 5] = [(0x0000, 0x0800), (0x0800, 0x0400), (0x0C00, 0x0200), (0x0E00, 0x0100), (0x0F00, 0x0100)];
+
+/// Fan tachometer counter frequency, each count is one period of this clock over two pulses per
+/// revolution
+const TACH_HZ: u32 = 143_750;
+
+/// ITE embedded controller peripherals mapped into XRAM from 0x1000
+/// Modeled registers are the scratch ROM mapping, host interface, GPIO, PWM and tachometers, ADC,
+/// SMBus, chip ID and PECI. Other addresses in the range are plain memory.
+pub struct Ite {
+    pub chip: IteChip,
+    /// GPIO ports A to M as 0 to 12
+    pub gpdr: [u8; 13],
+    pub gpcr: [[u8; 8]; 13],
+    pub gpot: [u8; 13],
+    /// Levels driven onto GPIO pins from outside
+    pub gpio_inputs: [u8; 13],
+    /// Keyboard controller, host ports 0x60 and 0x64
+    pub kbc: HostInterface,
+    /// Power management channels, the first on host ports 0x62 and 0x66
+    pub pmc: [HostInterface; 2],
+    pub smbus: [Smbus; 3],
+    /// PWM registers from 0x1800
+    pub pwm: [u8; 0x50],
+    /// Fan speeds in RPM measured by the tachometers
+    pub fan_rpm: [u32; 2],
+    /// ADC registers from 0x1900
+    pub adc: [u8; 0x50],
+    /// 10-bit ADC readings of channels 0 to 7
+    pub adc_inputs: [u16; 8],
+    pub peci: Peci,
+    /// Scratch ROM address registers, SCAR0 to SCAR4 low, middle and high
+    pub scar: [[u8; 3]; 5],
+    /// Host I/O port base of the two H2RAM shared memory windows, set by the host's LPC
+    /// configuration
+    pub h2ram_io: [u16; 2],
+    /// Pending scratch SRAM copy, as code address, XRAM address and length
+    dma: Option<(u16, u16, u16)>,
+}
+
+impl Ite {
+    pub fn new(chip: IteChip) -> Self {
+        let mut ite = Self {
+            chip,
+            gpdr: [0; 13],
+            gpcr: [[0; 8]; 13],
+            gpot: [0; 13],
+            gpio_inputs: [0xFF; 13],
+            kbc: HostInterface::new(HostInterface::OBF | HostInterface::IBF | HostInterface::CMD | HostInterface::AOBF),
+            pmc: [
+                HostInterface::new(HostInterface::OBF | HostInterface::IBF | HostInterface::CMD),
+                HostInterface::new(HostInterface::OBF | HostInterface::IBF | HostInterface::CMD),
+            ],
+            smbus: [Smbus::new(), Smbus::new(), Smbus::new()],
+            pwm: [0; 0x50],
+            fan_rpm: [0; 2],
+            adc: [0; 0x50],
+            adc_inputs: [0; 8],
+            peci: Peci::new(),
+            scar: [[0; 3]; 5],
+            h2ram_io: [0x0E00, 0x0C00],
+            dma: None,
+        };
+        ite.reset();
+        ite
+    }
+
+    /// Return registers to their reset values, devices and external inputs are kept
+    pub fn reset(&mut self) {
+        self.gpdr = [0; 13];
+        // Inputs with pull-ups
+        self.gpcr = [[0x84; 8]; 13];
+        self.gpot = [0; 13];
+        self.kbc = HostInterface::new(self.kbc.hardware);
+        for pmc in self.pmc.iter_mut() {
+            *pmc = HostInterface::new(pmc.hardware);
+        }
+        for smbus in self.smbus.iter_mut() {
+            smbus.reset();
+        }
+        self.pwm = [0; 0x50];
+        // CTR0, full scale duty cycle
+        self.pwm[0x01] = 0xFF;
+        self.adc = [0; 0x50];
+        let temperature = self.peci.temperature;
+        self.peci = Peci::new();
+        self.peci.temperature = temperature;
+        // Mapping disabled
+        self.scar = [[0, 0, 0x80]; 5];
+        self.dma = None;
+    }
+
+    /// Level of GPIO pin `bit` of port `port`, A is 0
+    /// Output pins read their data bit, open-drain ones only when not pulled low from outside.
+    pub fn gpio_pin(&self, port: usize, bit: u8) -> bool {
+        let mask = 1 << bit;
+        let input = self.gpio_inputs[port] & mask != 0;
+        if self.gpcr[port][bit as usize] >> 6 == 0b01 {
+            let data = self.gpdr[port] & mask != 0;
+            if self.gpot[port] & mask != 0 {
+                data && input
+            } else {
+                data
+            }
+        } else {
+            input
+        }
+    }
+
+    /// Pin levels of port `port`
+    fn gpio_pins(&self, port: usize) -> u8 {
+        (0..8).fold(0, |pins, bit| pins | (self.gpio_pin(port, bit) as u8) << bit)
+    }
+
+    /// Duty cycle of PWM channel `channel` from 0 to 1, against cycle time CTR0
+    pub fn pwm_duty(&self, channel: usize) -> f64 {
+        let ctr = self.pwm[0x01].max(1) as f64;
+        (self.pwm[0x02 + channel] as f64 / ctr).min(1.0)
+    }
+
+    /// Tachometer count of fan `fan`, zero when stopped
+    fn tach(&self, fan: usize) -> u16 {
+        (TACH_HZ * 30).checked_div(self.fan_rpm[fan]).map_or(0, |count| count.min(0xFFFF) as u16)
+    }
+
+    /// XRAM address of scratch SRAM mapped at code address `addr`
+    pub fn scratch(&self, addr: u16) -> Option<usize> {
+        SCRATCH.iter().zip(self.scar.iter()).find_map(|(&(base, size), scar)| {
+            if scar[2] & 0x80 != 0 {
+                return None;
+            }
+            let start = (scar[1] as u16) << 8 | scar[0] as u16;
+            let offset = addr.wrapping_sub(start);
+            if offset < size {
+                Some((base + offset) as usize)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Remove and return a pending copy of code into scratch SRAM, as code address, XRAM address and
+    /// length
+    pub(crate) fn take_dma(&mut self) -> Option<(u16, u16, u16)> {
+        self.dma.take()
+    }
+
+    /// Host read of I/O port `port`, `None` if it is not decoded
+    /// The data port reads the last output byte even when OBF is clear, as on hardware.
+    pub fn io_read(&mut self, port: u16) -> Option<u8> {
+        let channel = match port {
+            0x60 | 0x64 => &mut self.kbc,
+            0x62 | 0x66 => &mut self.pmc[0],
+            0x68 | 0x6C => &mut self.pmc[1],
+            _ => return None,
+        };
+        Some(if port & 0x04 != 0 {
+            channel.status()
+        } else {
+            channel.host_read().unwrap_or(channel.data_out)
+        })
+    }
+
+    /// Host write of I/O port `port`, returns false if it is not decoded
+    pub fn io_write(&mut self, port: u16, value: u8) -> bool {
+        let channel = match port {
+            0x60 | 0x64 => &mut self.kbc,
+            0x62 | 0x66 => &mut self.pmc[0],
+            0x68 | 0x6C => &mut self.pmc[1],
+            _ => return false,
+        };
+        debug!(" ; host write 0x{:02X} to port 0x{:02X}", value, port);
+        channel.host_write(port & 0x04 != 0, value);
+        true
+    }
+
+    /// XRAM address of host I/O port `port` in an enabled H2RAM window
+    /// HRAMWC enables windows, HRAMWxBA holds the XRAM base over 16 and HRAMWxAAS the size as
+    /// 16 bytes shifted left, all read from `xram` as plain memory.
+    pub fn h2ram(&self, xram: &[u8], port: u16) -> Option<usize> {
+        (0..2).find_map(|window| {
+            if xram[0x105A] & (1 << window) == 0 {
+                return None;
+            }
+            let base = (xram[0x105B + window] as usize) << 4;
+            let size = 16usize << (xram[0x105D + window] & 0b111);
+            let offset = port.wrapping_sub(self.h2ram_io[window]) as usize;
+            if offset < size {
+                Some(base + offset)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// SMBus channel and register offset at `addr`
+    fn smbus_register(addr: u16) -> Option<(usize, u8)> {
+        match addr {
+            0x1C00 ..= 0x1C06 => Some((0, (addr - 0x1C00) as u8)),
+            0x1C10 => Some((0, 7)),
+            0x1C11 ..= 0x1C17 => Some((1, (addr - 0x1C11) as u8)),
+            0x1C21 => Some((1, 7)),
+            0x1C29 ..= 0x1C2F => Some((2, (addr - 0x1C29) as u8)),
+            0x1C31 => Some((2, 7)),
+            _ => None,
+        }
+    }
+
+    /// GPIO control register at `addr`, as port and pin
+    fn gpcr_register(addr: u16) -> Option<(usize, usize)> {
+        match addr {
+            0x1610 ..= 0x165F => Some(((addr - 0x1610) as usize / 8, (addr & 7) as usize)),
+            0x1690 ..= 0x16A7 => Some(((addr - 0x1690) as usize / 8 + 10, (addr & 7) as usize)),
+            _ => None,
+        }
+    }
+
+    /// ADC channel whose data register, low or high byte, is at `addr`
+    fn adc_data(addr: u16) -> Option<(usize, bool)> {
+        const DATA: [u16; 8] = [0x1918, 0x1907, 0x190A, 0x190D, 0x1939, 0x193C, 0x193F, 0x1942];
+        DATA.iter().enumerate().find_map(|(channel, &low)| match addr.wrapping_sub(low) {
+            0 => Some((channel, false)),
+            1 => Some((channel, true)),
+            _ => None,
+        })
+    }
+
+    /// Read of a modeled register, `None` for plain memory
+    pub fn load(&self, addr: Addr) -> Option<u8> {
+        let addr = match addr {
+            Addr::XRam(addr) => addr,
+            _ => return None,
+        };
+        if let Some((channel, offset)) = Self::smbus_register(addr) {
+            let smbus = &self.smbus[channel];
+            return Some(if offset == 7 { smbus.hoctl2 } else { smbus.load(offset) });
+        }
+        if let Some((port, pin)) = Self::gpcr_register(addr) {
+            return Some(self.gpcr[port][pin]);
+        }
+        if let Some((channel, high)) = Self::adc_data(addr) {
+            let value = self.adc_inputs[channel].min(0x3FF);
+            return Some(if high { (value >> 8) as u8 } else { value as u8 });
+        }
+        Some(match addr {
+            0x1040 ..= 0x104E => {
+                let index = (addr - 0x1040) as usize;
+                self.scar[index / 3][index % 3]
+            },
+            // KBHICR, KBHISR, KBHIKDOR, KBHIMDOR, KBHIDIR
+            0x1300 => self.kbc.control,
+            0x1304 => self.kbc.status(),
+            0x1306 | 0x1308 => self.kbc.data_out,
+            0x130A => self.kbc.data_in,
+            // PMxSTS, PMxDO, PMxDOSCI, PMxDOSMI, PMxDI, PMxDISCI, PMxCTL
+            0x1500 ..= 0x1506 | 0x1510 ..= 0x1516 => {
+                let pmc = &self.pmc[(addr >> 4 & 1) as usize];
+                match addr & 0xF {
+                    0 => pmc.status(),
+                    1 ..= 3 => pmc.data_out,
+                    4 | 5 => pmc.data_in,
+                    _ => pmc.control,
+                }
+            },
+            // GPDR, read as pin levels for inputs
+            0x1601 ..= 0x160D => {
+                let port = (addr - 0x1601) as usize;
+                let outputs = (0..8).fold(0, |mask, bit| {
+                    mask | ((self.gpcr[port][bit] >> 6 == 0b01) as u8) << bit
+                });
+                self.gpdr[port] & outputs | self.gpio_pins(port) & !outputs
+            },
+            // GPDMR, pin levels
+            0x1661 ..= 0x166D => self.gpio_pins((addr - 0x1661) as usize),
+            0x1671 ..= 0x167D => self.gpot[(addr - 0x1671) as usize],
+            // F1TLRR, F1TMRR, F2TLRR, F2TMRR
+            0x181E ..= 0x1821 => {
+                let tach = self.tach((addr - 0x181E) as usize / 2);
+                if addr & 1 == 0 { tach as u8 } else { (tach >> 8) as u8 }
+            },
+            0x1800 ..= 0x184F => self.pwm[(addr - 0x1800) as usize],
+            // VCHxCTL of enabled ADC read with data valid
+            0x1904 | 0x1906 | 0x1909 | 0x190C | 0x1938 | 0x193B | 0x193E | 0x1941 => {
+                let enabled = self.adc[0x01] & 1 != 0;
+                self.adc[(addr - 0x1900) as usize] | if enabled { 0x80 } else { 0 }
+            },
+            0x1900 ..= 0x194F => self.adc[(addr - 0x1900) as usize],
+            // ECHIPID1, ECHIPID2, ECHIPVER
+            0x2000 ..= 0x2002 => self.chip.id()[(addr - 0x2000) as usize],
+            0x3000 ..= 0x300F => self.peci.load((addr - 0x3000) as u8),
+            _ => return None,
+        })
+    }
+
+    /// Side effects of a firmware read of `addr`, after `load` returned its value
+    pub fn read(&mut self, addr: Addr) {
+        let addr = match addr {
+            Addr::XRam(addr) => addr,
+            _ => return,
+        };
+        match addr {
+            0x130A => self.kbc.read_input(),
+            0x1504 | 0x1505 | 0x1514 | 0x1515 => self.pmc[(addr >> 4 & 1) as usize].read_input(),
+            0x3000 ..= 0x300F => self.peci.read((addr - 0x3000) as u8),
+            _ => (),
+        }
+    }
+
+    /// Write of a modeled register, returns false for plain memory
+    pub fn store(&mut self, addr: Addr, value: u8) -> bool {
+        let addr = match addr {
+            Addr::XRam(addr) => addr,
+            _ => return false,
+        };
+        if let Some((channel, offset)) = Self::smbus_register(addr) {
+            let smbus = &mut self.smbus[channel];
+            if offset == 7 {
+                // Disabling I2C compatible mode ends a transfer
+                if smbus.transfer.is_some() && value & Smbus::I2C_EN == 0 {
+                    smbus.finish(true);
+                }
+                smbus.hoctl2 = value;
+            } else {
+                smbus.store(offset, value);
+            }
+            return true;
+        }
+        if let Some((port, pin)) = Self::gpcr_register(addr) {
+            self.gpcr[port][pin] = value;
+            return true;
+        }
+        match addr {
+            0x1040 ..= 0x104E => {
+                let index = (addr - 0x1040) as usize;
+                let scar = &mut self.scar[index / 3];
+                scar[index % 3] = value;
+                // Writing the high byte with the mapping enabled copies code into the scratch SRAM
+                if index % 3 == 2 && value & 0x80 == 0 {
+                    let (base, size) = SCRATCH[index / 3];
+                    let start = (scar[1] as u16) << 8 | scar[0] as u16;
+                    debug!(" ; scratch {} 0x{:04X}", index / 3, start);
+                    self.dma = Some((start, base, size));
+                }
+            },
+            0x1300 => self.kbc.control = value,
+            0x1304 => self.kbc.write_status(value),
+            0x1306 => self.kbc.write_output(value, false),
+            0x1308 => self.kbc.write_output(value, true),
+            0x1500 ..= 0x1506 | 0x1510 ..= 0x1516 => {
+                let pmc = &mut self.pmc[(addr >> 4 & 1) as usize];
+                match addr & 0xF {
+                    0 => pmc.write_status(value),
+                    1 ..= 3 => pmc.write_output(value, false),
+                    4 | 5 => (),
+                    _ => pmc.control = value,
+                }
+            },
+            0x1601 ..= 0x160D => self.gpdr[(addr - 0x1601) as usize] = value,
+            0x1661 ..= 0x166D => (),
+            0x1671 ..= 0x167D => self.gpot[(addr - 0x1671) as usize] = value,
+            0x181E ..= 0x1821 => (),
+            0x1800 ..= 0x184F => self.pwm[(addr - 0x1800) as usize] = value,
+            // Data valid is written as one to clear
+            0x1904 | 0x1906 | 0x1909 | 0x190C | 0x1938 | 0x193B | 0x193E | 0x1941 => {
+                self.adc[(addr - 0x1900) as usize] = value & 0x7F;
+            },
+            0x1900 ..= 0x194F => {
+                if Self::adc_data(addr).is_none() {
+                    self.adc[(addr - 0x1900) as usize] = value;
+                }
+            },
+            0x2000 ..= 0x2002 => (),
+            0x3000 ..= 0x300F => self.peci.store((addr - 0x3000) as u8, value),
+            _ => return false,
+        }
+        true
+    }
+}
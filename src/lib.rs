@@ -8,67 +8,1004 @@ macro_rules! debug {
     ($($arg:tt)*) => (());
 }
 
+use std::cell::RefCell;
+
+use self::timer::TimerMode;
+
+pub use self::adc::{Adc, AdcAlign, AdcMap, Analog};
+mod adc;
+
 pub use self::addr::Addr;
 mod addr;
 
-pub use self::isa::Isa;
+pub use self::battery::{sbs, SmartBattery};
+mod battery;
+
+pub use self::diagnostic::Diagnostic;
+mod diagnostic;
+
+pub use self::eeprom::Eeprom;
+mod eeprom;
+
+pub use self::error::Error;
+mod error;
+
+pub use self::flash::{spi_flash, SpiFlash};
+mod flash;
+
+pub use self::gpio::{Gpio, Level, Port, PortChange};
+mod gpio;
+
+pub use self::host::{Host, HostError, HostServer, PortPair};
+mod host;
+
+pub use self::i2c::{I2cBus, I2cDevice, I2cEvent, I2cOp, I2cSfr, ScriptedTarget};
+mod i2c;
+
+pub use self::ihx::parse_ihx;
+mod ihx;
+
+pub use self::interrupt::{Interrupt, Interrupts, PeripheralInterrupt};
+mod interrupt;
+
+pub use self::isa::{Isa, CYCLES};
 mod isa;
 
+pub use self::ite::{HostInterface, Ite, IteChip, Peci, Smbus};
+mod ite;
+
+pub use self::matrix::{KeyMatrix, MatrixLines};
+mod matrix;
+
 pub use self::mem::Mem;
 mod mem;
 
+pub use self::pacer::Pacer;
+mod pacer;
+
+pub use self::parse::{parse_number, parse_time};
+mod parse;
+
+pub use self::pattern::Pattern;
+mod pattern;
+
+pub use self::power::Power;
+mod power;
+
+pub use self::ps2::{
+    Ps2Controller, Ps2Device, Ps2Event, Ps2Keyboard, Ps2Link, Ps2Port, Ps2Touchpad,
+};
+mod ps2;
+
+#[cfg(unix)]
+pub use self::pty::Pty;
+#[cfg(unix)]
+mod pty;
+
 pub use self::reg::Reg;
 mod reg;
 
+pub use self::reset::Reset;
+mod reset;
+
+pub use self::run::{Budget, StopReason};
+mod run;
+
+pub use self::scheduler::{Callback, EventId, Scheduler};
+mod scheduler;
+
+pub use self::script::{Outcome, Report, Script};
+mod script;
+
+pub use self::spi::{SpiBus, SpiDevice, SpiMaster, SpiMode, SpiSfr, SpiTarget};
+mod spi;
+
+mod timer;
+
+pub use self::trace::{diff, import_ucsim, trace_step, Divergence, TraceEntry, TraceReg};
+mod trace;
+
+pub use self::semihost::{Exit, Semihost};
+mod semihost;
+
+pub use self::uart::Uart;
+mod uart;
+
+pub use self::variant::Variant;
+mod variant;
+
+pub use self::watchdog::{Watchdog, WatchdogAction};
+mod watchdog;
+
 pub struct Mcu {
     pub pc: u16,
     pub iram: Box<[u8]>,
     pub sfr: Box<[u8]>,
     pub pmem: Box<[u8]>,
     pub xram: Box<[u8]>,
+    pub variant: Variant,
+    /// Record diagnostics for accesses to memory not present on this variant
+    pub strict: bool,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    /// Address of the instruction being executed
+    instruction: u16,
+    /// Cycle where the current run ends, low power modes are not fast-forwarded past it
+    deadline: u64,
+    /// Machine cycles since power-on
+    pub cycles: u64,
+    /// Instructions fetched since power-on, interrupt entries and low power steps are not counted
+    pub instructions: u64,
+    pub interrupts: Interrupts,
+    pub watchdog: Option<Watchdog>,
+    /// Processor stopped by a watchdog with the halt action or a semihosting exit
+    pub halted: bool,
+    /// Machine cycles spent in idle mode
+    pub idle_cycles: u64,
+    pub gpio: Gpio,
+    pub scheduler: Scheduler,
+    pub uart: Uart,
+    /// Oscillator frequency in Hz
+    pub frequency: u64,
+    /// Memory writes are recorded here when set
+    pub writes: Option<Vec<(Addr, u8)>>,
+    /// Fault raised by a memory access, returned by the current step
+    fault: Option<Error>,
+    /// Program addresses where `run` stops before executing
+    pub breakpoints: Vec<u16>,
+    pub semihost: Option<Semihost>,
+    /// I2C buses on GPIO pins or an SFR controller, indexed by the value returned from `attach_i2c`
+    /// or `attach_i2c_sfr`
+    pub i2c: Vec<I2cBus>,
+    /// SPI buses, bit-banged or on an SFR controller
+    pub spi: Vec<SpiBus>,
+    /// PS/2 channels, indexed by the value returned from `attach_ps2`
+    pub ps2: Vec<Ps2Port>,
+    /// Keyboard matrix scanned by firmware
+    pub matrix: Option<KeyMatrix>,
+    /// ADC with host supplied channel levels
+    pub adc: Option<Adc>,
+    /// ITE embedded controller peripherals in XRAM
+    pub ite: Option<Ite>,
 }
 
 impl Mcu {
     pub fn new(pmem: Box<[u8]>) -> Self {
+        Self::with_variant(Variant::default(), pmem)
+    }
+
+    pub fn with_variant(variant: Variant, pmem: Box<[u8]>) -> Self {
         Self {
             pc: 0,
-            iram: vec![0; 256].into_boxed_slice(),
+            iram: vec![0; variant.iram_size()].into_boxed_slice(),
             sfr: vec![0; 128].into_boxed_slice(),
-            pmem: pmem,
+            pmem,
             xram: vec![0; 65536].into_boxed_slice(),
+            variant,
+            strict: false,
+            diagnostics: RefCell::new(Vec::new()),
+            instruction: 0,
+            instructions: 0,
+            deadline: u64::MAX,
+            cycles: 0,
+            interrupts: Interrupts::default(),
+            watchdog: None,
+            halted: false,
+            idle_cycles: 0,
+            gpio: Gpio::new(),
+            scheduler: Scheduler::new(),
+            uart: Uart::new(),
+            frequency: 11_059_200,
+            writes: None,
+            fault: None,
+            breakpoints: Vec::new(),
+            semihost: None,
+            i2c: Vec::new(),
+            spi: Vec::new(),
+            ps2: Vec::new(),
+            matrix: None,
+            adc: None,
+            ite: None,
+        }
+    }
+
+    /// Power-on reset, internal and external RAM are undefined
+    /// If `seed` is provided, RAM is filled with pseudo-random data to expose uninitialized variables,
+    /// otherwise it is cleared
+    pub fn power_on(&mut self, seed: Option<u64>) {
+        match seed {
+            Some(mut state) => {
+                // xorshift64, state must not be zero
+                state |= 1;
+                for byte in self.iram.iter_mut().chain(self.xram.iter_mut()) {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *byte = state as u8;
+                }
+            },
+            None => {
+                for byte in self.iram.iter_mut().chain(self.xram.iter_mut()) {
+                    *byte = 0;
+                }
+            }
+        }
+
+        self.cycles = 0;
+        self.instructions = 0;
+        self.reset(Reset::PowerOn);
+    }
+
+    /// Address of the instruction fetched last
+    pub fn instruction(&self) -> u16 {
+        self.instruction
+    }
+
+    pub fn power(&self) -> Power {
+        let pcon = self.load(self.pcon());
+        if pcon & (1 << 1) != 0 {
+            Power::PowerDown
+        } else if pcon & (1 << 0) != 0 {
+            Power::Idle
+        } else {
+            Power::Active
+        }
+    }
+
+    /// Machine cycles until the next peripheral event
+    pub fn next_event(&self) -> Option<u64> {
+        let watchdog = self.watchdog.as_ref().and_then(|watchdog| watchdog.remaining());
+        let event = self.scheduler.next().map(|cycle| cycle.saturating_sub(self.cycles));
+        // Reception with REN set, RI clear and the baud rate clock running
+        let scon = self.sfr[0x98 - 0x80];
+        let uart = if self.uart.rx.is_empty() || scon & (1 << 4) == 0 || scon & (1 << 0) != 0 ||
+            self.uart_frame_cycles().is_none()
+        {
+            None
+        } else {
+            Some(self.uart.next_rx.saturating_sub(self.cycles))
+        };
+        let timer = self.timer_overflow();
+        [watchdog, event, uart, timer].iter().flatten().min().cloned()
+    }
+
+    /// Timer 0 or 1 is enabled by TRx and the gate
+    fn timer_running(&self, timer: usize, mode: TimerMode) -> bool {
+        let run = self.sfr[0x88 - 0x80] & (1 << (4 + 2 * timer)) != 0;
+        run && (! mode.gate || self.pin(3, 2 + timer as u8))
+    }
+
+    /// Add `counts` to timer 0 or 1 and set its overflow flag
+    fn count_timer(&mut self, timer: usize, mode: TimerMode, counts: u64) {
+        let split = TimerMode::new(self.sfr[0x89 - 0x80], 0).mode == 3;
+        // Timer 1 holds its count in mode 3
+        if timer == 1 && mode.mode == 3 {
+            return;
+        }
+        let (tl, th) = (0x8A + timer - 0x80, 0x8C + timer - 0x80);
+        let (mut low, mut high) = (self.sfr[tl], self.sfr[th]);
+        let overflows = timer::advance(mode.mode, &mut low, &mut high, counts);
+        self.sfr[tl] = low;
+        self.sfr[th] = high;
+        // TF1 belongs to TH0 while timer 0 is split, timer 1 then only clocks the serial port
+        if overflows > 0 && ! (timer == 1 && split) {
+            debug!(" ; timer {} overflow", timer);
+            self.sfr[0x88 - 0x80] |= 1 << (5 + 2 * timer);
+        }
+    }
+
+    /// Count machine cycles on timers 0 and 1
+    fn tick_timers(&mut self, cycles: u64) {
+        let tmod = self.sfr[0x89 - 0x80];
+        for timer in 0..2 {
+            let mode = TimerMode::new(tmod, timer);
+            if ! mode.counter && self.timer_running(timer, mode) {
+                self.count_timer(timer, mode, cycles);
+            }
+        }
+        // TH0 is a second 8 bit timer in mode 3, run by TR1 and overflowing into TF1
+        let split = TimerMode::new(tmod, 0).mode == 3 && self.sfr[0x88 - 0x80] & (1 << 6) != 0;
+        if split && timer::reload(&mut self.sfr[0x8C - 0x80], 0, cycles) > 0 {
+            self.sfr[0x88 - 0x80] |= 1 << 7;
+        }
+    }
+
+    /// Machine cycles until a timer sets its overflow flag
+    fn timer_overflow(&self) -> Option<u64> {
+        let tmod = self.sfr[0x89 - 0x80];
+        let split = TimerMode::new(tmod, 0).mode == 3;
+        let mut next = None;
+        for timer in 0..2 {
+            let mode = TimerMode::new(tmod, timer);
+            if mode.counter || ! self.timer_running(timer, mode) || (timer == 1 && split) {
+                continue;
+            }
+            let (tl, th) = (self.sfr[0x8A + timer - 0x80], self.sfr[0x8C + timer - 0x80]);
+            let cycles = timer::until_overflow(mode.mode, tl, th);
+            next = Some(next.map_or(cycles, |next: u64| next.min(cycles)));
+        }
+        if split && self.sfr[0x88 - 0x80] & (1 << 6) != 0 {
+            let cycles = 0x100 - self.sfr[0x8C - 0x80] as u64;
+            next = Some(next.map_or(cycles, |next: u64| next.min(cycles)));
+        }
+        next
+    }
+
+    /// Machine cycles to receive one frame at the baud rate set by the serial port mode, SMOD and
+    /// the timer clocking the port, or None while that timer is stopped
+    fn uart_frame_cycles(&self) -> Option<u64> {
+        let clocks_per_cycle = self.variant.clocks_per_cycle();
+        let smod = self.sfr[0x87 - 0x80] >> 7;
+        let (bits, bit_clocks) = match self.sfr[0x98 - 0x80] >> 6 {
+            0 => (8, clocks_per_cycle),
+            2 => (11, 64 >> smod),
+            mode => {
+                let t2con = self.sfr[0xC8 - 0x80];
+                let bit_clocks = if self.variant.has_sfr(0xC8) && t2con & (1 << 5) != 0 {
+                    // Timer 2 counts at half the oscillator and the port divides its overflows by 16
+                    if t2con & (1 << 2) == 0 {
+                        return None;
+                    }
+                    let rcap2 = (self.sfr[0xCB - 0x80] as u64) << 8 | self.sfr[0xCA - 0x80] as u64;
+                    32 * (0x10000 - rcap2)
+                } else {
+                    let timer = TimerMode::new(self.sfr[0x89 - 0x80], 1);
+                    if timer.counter || timer.mode == 3 || ! self.timer_running(1, timer) {
+                        return None;
+                    }
+                    let period = match timer.mode {
+                        0 => 0x2000,
+                        1 => 0x10000,
+                        _ => 0x100 - self.sfr[0x8D - 0x80] as u64,
+                    };
+                    (period * clocks_per_cycle * 32) >> smod
+                };
+                (if mode == 1 { 10 } else { 11 }, bit_clocks)
+            },
+        };
+        Some((bits * bit_clocks).div_ceil(clocks_per_cycle))
+    }
+
+    /// Machine cycles until the end of the current run
+    fn remaining(&self) -> u64 {
+        self.deadline.saturating_sub(self.cycles)
+    }
+
+    /// Machine cycles in `ns` nanoseconds of emulated time
+    pub fn ns_to_cycles(&self, ns: u64) -> u64 {
+        let clocks = (ns as u128) * (self.frequency as u128) / 1_000_000_000;
+        (clocks / self.variant.clocks_per_cycle() as u128) as u64
+    }
+
+    /// Nanoseconds of emulated time in `cycles` machine cycles
+    pub fn cycles_to_ns(&self, cycles: u64) -> u64 {
+        let clocks = (cycles as u128) * (self.variant.clocks_per_cycle() as u128);
+        (clocks * 1_000_000_000 / self.frequency as u128) as u64
+    }
+
+    /// Host read of I/O port `port` through the embedded controller, 0xFF if nothing decodes it
+    pub fn io_read(&mut self, port: u16) -> u8 {
+        let ite = match self.ite.as_mut() {
+            Some(ite) => ite,
+            None => return 0xFF,
+        };
+        match ite.h2ram(&self.xram, port) {
+            Some(offset) => self.xram[offset],
+            None => ite.io_read(port).unwrap_or(0xFF),
+        }
+    }
+
+    /// Host write of I/O port `port` through the embedded controller, ignored if nothing decodes it
+    pub fn io_write(&mut self, port: u16, value: u8) {
+        let ite = match self.ite.as_mut() {
+            Some(ite) => ite,
+            None => return,
+        };
+        match ite.h2ram(&self.xram, port) {
+            Some(offset) => self.xram[offset] = value,
+            None => {
+                ite.io_write(port, value);
+            },
+        }
+    }
+
+    /// Drive pin `bit` of port `port` from outside, `None` releases the pin
+    pub fn drive_pin(&mut self, port: u8, bit: u8, level: Option<bool>) {
+        let old = self.gpio.ports[port as usize].pins();
+        self.gpio.drive(port as usize, bit, level, self.cycles);
+        self.pins_changed(port as usize, old);
+    }
+
+    /// Drive pin `bit` of port `port` to `level` at `cycle`
+    pub fn schedule_pin(&mut self, cycle: u64, port: u8, bit: u8, level: Option<bool>) -> EventId {
+        self.schedule_at(cycle, move |mcu| mcu.drive_pin(port, bit, level))
+    }
+
+    /// Drive pin `bit` of port `port` low at `cycle` and release it `width` cycles later
+    pub fn pulse_pin(&mut self, cycle: u64, port: u8, bit: u8, width: u64) {
+        self.schedule_pin(cycle, port, bit, Some(false));
+        self.schedule_pin(cycle + width, port, bit, None);
+    }
+
+    /// Run `callback` at the end of the instruction during which `cycle` is reached
+    pub fn schedule_at<F: FnOnce(&mut Mcu) + 'static>(&mut self, cycle: u64, callback: F) -> EventId {
+        self.scheduler.schedule(cycle, Box::new(callback))
+    }
+
+    /// Run `callback` after `cycles` machine cycles
+    pub fn schedule_in<F: FnOnce(&mut Mcu) + 'static>(&mut self, cycles: u64, callback: F) -> EventId {
+        self.schedule_at(self.cycles + cycles, callback)
+    }
+
+    /// Remove a pending event, returns false if it already ran or was cancelled
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    /// Add an I2C bus on pins `scl` and `sda`, as port and bit, returns its index
+    pub fn attach_i2c(&mut self, scl: (u8, u8), sda: (u8, u8)) -> usize {
+        self.i2c.push(I2cBus::new(scl, sda));
+        let index = self.i2c.len() - 1;
+        self.sync_i2c(index);
+        index
+    }
+
+    /// Add an I2C bus driven by an SFR controller, returns its index
+    pub fn attach_i2c_sfr(&mut self, sfr: I2cSfr) -> usize {
+        self.i2c.push(I2cBus::with_sfr(sfr));
+        self.i2c.len() - 1
+    }
+
+    /// Clock out `ops` as a host master on bus `bus`, with `half_period` cycles per clock phase
+    /// Operations queue behind any still in progress. The result is decoded into the bus log.
+    pub fn i2c_master(&mut self, bus: usize, half_period: u64, ops: &[I2cOp]) {
+        let busy = self.i2c[bus].master_busy();
+        self.i2c[bus].queue(ops, half_period);
+        if ! busy {
+            self.schedule_in(0, move |mcu| mcu.i2c_master_step(bus));
+        }
+    }
+
+    fn i2c_master_step(&mut self, index: usize) {
+        let (port, bit) = self.i2c[index].scl;
+        let scl = self.pin(port, bit);
+        if let Some(delay) = self.i2c[index].master_step(scl) {
+            self.sync_i2c(index);
+            self.schedule_in(delay, move |mcu| mcu.i2c_master_step(index));
+        }
+    }
+
+    /// Let bus `index` follow its lines and apply its drivers until the levels settle
+    fn sync_i2c(&mut self, index: usize) {
+        let ((scl_port, scl_bit), (sda_port, sda_bit)) = (self.i2c[index].scl, self.i2c[index].sda);
+        loop {
+            let levels = (self.pin(scl_port, scl_bit), self.pin(sda_port, sda_bit));
+            let stretch = self.i2c[index].update(levels.0, levels.1);
+            if stretch > 0 {
+                debug!(" ; i2c stretch {}", stretch);
+                self.i2c[index].device_scl_low = true;
+                self.schedule_in(stretch, move |mcu| {
+                    mcu.i2c[index].device_scl_low = false;
+                    mcu.sync_i2c(index);
+                });
+            }
+
+            let (scl_low, sda_low) = self.i2c[index].drives();
+            for &(port, bit, low) in [(scl_port, scl_bit, scl_low), (sda_port, sda_bit, sda_low)].iter() {
+                let old = self.gpio.ports[port as usize].pins();
+                self.gpio.drive(port as usize, bit, if low { Some(false) } else { None }, self.cycles);
+                self.latch_edges(port as usize, old);
+            }
+            if (self.pin(scl_port, scl_bit), self.pin(sda_port, sda_bit)) == levels {
+                break;
+            }
+        }
+    }
+
+    /// Add an SPI bus, returns its index
+    pub fn attach_spi(&mut self, bus: SpiBus) -> usize {
+        self.spi.push(bus);
+        let index = self.spi.len() - 1;
+        self.sync_spi(index);
+        index
+    }
+
+    /// Let bus `index` follow chip selects and bit-banged lines, and drive MISO
+    fn sync_spi(&mut self, index: usize) {
+        let bus = &self.spi[index];
+        let cs: Vec<bool> = bus.devices.iter().map(|target| self.pin(target.cs.0, target.cs.1)).collect();
+        let pins = match bus.master {
+            SpiMaster::Pins { sck, mosi, miso } => Some((sck, mosi, miso)),
+            SpiMaster::Sfr(_) => None,
+        };
+        let lines = pins.map(|(sck, mosi, _)| (self.pin(sck.0, sck.1), self.pin(mosi.0, mosi.1)));
+        let level = self.spi[index].update(&cs, lines);
+        if let Some((_, _, (port, bit))) = pins {
+            let old = self.gpio.ports[port as usize].pins();
+            self.gpio.drive(port as usize, bit, level, self.cycles);
+            self.latch_edges(port as usize, old);
+        }
+    }
+
+    /// Add a PS/2 channel, returns its index
+    pub fn attach_ps2(&mut self, port: Ps2Port) -> usize {
+        self.ps2.push(port);
+        let index = self.ps2.len() - 1;
+        self.sync_ps2(index);
+        index
+    }
+
+    /// Start sending bytes the device of channel `index` queued, after input was injected into it
+    pub fn ps2_poll(&mut self, index: usize) {
+        self.sync_ps2(index);
+    }
+
+    /// Clock and data levels of channel `index`, if it is on pins
+    fn ps2_lines(&self, index: usize) -> Option<(bool, bool)> {
+        match self.ps2[index].link {
+            Ps2Link::Pins { clock, data } => Some((self.pin(clock.0, clock.1), self.pin(data.0, data.1))),
+            Ps2Link::Controller(_) => None,
+        }
+    }
+
+    fn ps2_step(&mut self, index: usize) {
+        let lines = self.ps2_lines(index);
+        match self.ps2[index].step(lines) {
+            Some(delay) => {
+                self.drive_ps2(index);
+                self.schedule_in(delay, move |mcu| mcu.ps2_step(index));
+            },
+            None => self.sync_ps2(index),
+        }
+    }
+
+    /// Apply the drivers of channel `index` and start a frame if one is ready
+    fn sync_ps2(&mut self, index: usize) {
+        self.drive_ps2(index);
+        let lines = self.ps2_lines(index);
+        if let Some(delay) = self.ps2[index].start(lines) {
+            self.schedule_in(delay, move |mcu| mcu.ps2_step(index));
+        }
+    }
+
+    fn drive_ps2(&mut self, index: usize) {
+        let pins = match self.ps2[index].link {
+            Ps2Link::Pins { clock, data } => [clock, data],
+            Ps2Link::Controller(_) => return,
+        };
+        let (clock_low, data_low) = self.ps2[index].drives();
+        for (&(port, bit), &low) in pins.iter().zip([clock_low, data_low].iter()) {
+            let old = self.gpio.ports[port as usize].pins();
+            self.gpio.drive(port as usize, bit, if low { Some(false) } else { None }, self.cycles);
+            self.latch_edges(port as usize, old);
+        }
+    }
+
+    /// Attach a keyboard matrix, replacing any other
+    pub fn attach_matrix(&mut self, matrix: KeyMatrix) {
+        self.matrix = Some(matrix);
+        self.sync_matrix();
+    }
+
+    /// Press or release the key of the keyboard matrix at `row` and `column`
+    pub fn set_key(&mut self, row: usize, column: usize, pressed: bool) {
+        let cycle = self.cycles;
+        let matrix = self.matrix.as_mut().expect("no keyboard matrix attached");
+        let bouncing = matrix.bouncing(cycle);
+        matrix.set(row, column, pressed, cycle);
+        if ! bouncing && matrix.bouncing(cycle) {
+            self.schedule_in(matrix::CHATTER, |mcu| mcu.matrix_bounce());
+        }
+        self.sync_matrix();
+    }
+
+    fn matrix_bounce(&mut self) {
+        self.sync_matrix();
+        if self.matrix.as_ref().is_some_and(|matrix| matrix.bouncing(self.cycles)) {
+            self.schedule_in(matrix::CHATTER, |mcu| mcu.matrix_bounce());
+        }
+    }
+
+    /// Drive the row input pins of the keyboard matrix from its column output pins
+    fn sync_matrix(&mut self) {
+        let (outputs, inputs) = match self.matrix.as_ref().map(|matrix| &matrix.lines) {
+            Some(MatrixLines::Pins { outputs, inputs }) => (outputs.clone(), inputs.clone()),
+            _ => return,
+        };
+        let low = outputs.iter().enumerate()
+            .filter(|(_, &(port, bit))| ! self.pin(port, bit))
+            .fold(0, |low, (column, _)| low | 1 << column);
+        let rows = self.matrix.as_ref().unwrap().rows_low(low, self.cycles);
+        for (row, &(port, bit)) in inputs.iter().enumerate() {
+            let old = self.gpio.ports[port as usize].pins();
+            let level = if rows & (1 << row) != 0 { Some(false) } else { None };
+            self.gpio.drive(port as usize, bit, level, self.cycles);
+            self.latch_edges(port as usize, old);
+        }
+    }
+
+    /// Start a conversion if firmware requested one
+    fn sync_adc(&mut self) {
+        let ns = self.cycles_to_ns(self.cycles);
+        let clocks_per_cycle = self.variant.clocks_per_cycle();
+        let adc = match self.adc.as_mut() {
+            Some(adc) => adc,
+            None => return,
+        };
+        if let Some((conversion, channel)) = adc.start() {
+            let (code, cycles) = (adc.sample(channel, ns), adc.map.clocks.div_ceil(clocks_per_cycle));
+            self.schedule_in(cycles, move |mcu| {
+                if let Some(adc) = mcu.adc.as_mut() {
+                    adc.finish(conversion, channel, code);
+                }
+                mcu.sync_adc();
+            });
+        }
+    }
+
+    /// Execute until `cycle` is reached, idle time is fast-forwarded to event boundaries
+    pub fn run_until(&mut self, cycle: u64) -> Result<(), Error> {
+        let deadline = self.deadline;
+        self.deadline = cycle.min(deadline);
+        let result = self.run_until_deadline(cycle);
+        self.deadline = deadline;
+        result
+    }
+
+    fn run_until_deadline(&mut self, cycle: u64) -> Result<(), Error> {
+        while self.cycles < cycle && ! self.halted {
+            match self.power() {
+                Power::PowerDown if self.scheduler.is_empty() && ! self.power_down_wake() => break,
+                Power::Idle if self.next_event().is_none() && self.interrupt_pending() => (),
+                Power::Idle if self.next_event().is_none() => {
+                    // Nothing can end idle mode before `cycle`
+                    let cycles = cycle - self.cycles;
+                    self.idle_cycles += cycles;
+                    self.tick(cycles);
+                    continue;
+                },
+                _ => (),
+            }
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Execute until `budget` is exhausted, a breakpoint is reached, or `stop` returns true after a
+    /// step. A breakpoint at the current address is ignored so that a stopped run can be resumed.
+    pub fn run<F: FnMut(&mut Mcu) -> bool>(&mut self, budget: Budget, stop: F) -> StopReason {
+        let start = self.cycles;
+        let (steps, cycles) = match budget {
+            Budget::Instructions(steps) => (steps, u64::MAX),
+            Budget::Cycles(cycles) => (u64::MAX, cycles),
+            Budget::Ns(ns) => (u64::MAX, self.ns_to_cycles(ns)),
+        };
+
+        let deadline = self.deadline;
+        self.deadline = start.saturating_add(cycles).min(deadline);
+        let reason = self.run_steps(steps, cycles, stop);
+        self.deadline = deadline;
+        reason
+    }
+
+    fn run_steps<F: FnMut(&mut Mcu) -> bool>(&mut self, steps: u64, cycles: u64, mut stop: F) -> StopReason {
+        let start = self.cycles;
+        let mut step = 0;
+        loop {
+            if let Some(exit) = self.semihost.as_ref().and_then(|semihost| semihost.exit.clone()) {
+                return StopReason::Exit(exit);
+            }
+            if self.halted {
+                return StopReason::Halted;
+            }
+            match self.power() {
+                Power::PowerDown if self.scheduler.is_empty() && ! self.power_down_wake() => {
+                    return StopReason::PowerDown;
+                },
+                Power::Idle if self.next_event().is_none() && ! self.interrupt_pending() => {
+                    return StopReason::Idle;
+                },
+                Power::Active if step > 0 && self.breakpoints.contains(&self.pc) => {
+                    return StopReason::Breakpoint(self.pc);
+                },
+                _ => (),
+            }
+            if step >= steps || self.cycles - start >= cycles {
+                return StopReason::Budget;
+            }
+
+            if let Err(err) = self.step() {
+                return StopReason::Error(err);
+            }
+            step += 1;
+
+            if stop(self) {
+                return StopReason::Predicate;
+            }
+        }
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.interrupts.pending(self).is_some()
+    }
+
+    /// An external interrupt is pending that ends power-down mode
+    fn power_down_wake(&self) -> bool {
+        let external = matches!(
+            self.interrupts.pending(self),
+            Some((Interrupt::External0 | Interrupt::External1, _))
+        );
+        external && self.variant.power_down_wake()
+    }
+
+    /// Follow a change of pin levels on port `port`, from `old`
+    fn pins_changed(&mut self, port: usize, old: u8) {
+        self.latch_edges(port, old);
+        for index in 0..self.i2c.len() {
+            let bus = &self.i2c[index];
+            if bus.sfr.is_none() && (bus.scl.0 as usize == port || bus.sda.0 as usize == port) {
+                self.sync_i2c(index);
+            }
+        }
+        for index in 0..self.spi.len() {
+            if self.spi[index].uses_port(port as u8) {
+                self.sync_spi(index);
+            }
+        }
+        for index in 0..self.ps2.len() {
+            if self.ps2[index].uses_port(port as u8) {
+                self.sync_ps2(index);
+            }
+        }
+        if self.matrix.as_ref().is_some_and(|matrix| matrix.uses_port(port as u8)) {
+            self.sync_matrix();
+        }
+    }
+
+    /// Latch falling edges on external interrupt pins into TCON, and count them on the T0 and T1
+    /// counter inputs
+    fn latch_edges(&mut self, port: usize, old: u8) {
+        let new = self.gpio.ports[port].pins();
+        if port == 3 {
+            let tmod = self.sfr[0x89 - 0x80];
+            for timer in 0..2 {
+                let mode = TimerMode::new(tmod, timer);
+                let falling = old & !new & (1 << (4 + timer)) != 0;
+                if falling && mode.counter && self.timer_running(timer, mode) {
+                    self.count_timer(timer, mode, 1);
+                }
+            }
+        }
+        for &interrupt in [Interrupt::External0, Interrupt::External1].iter() {
+            let (pin_port, pin_bit) = interrupt.pin().unwrap();
+            let falling = old & !new & (1 << pin_bit) != 0;
+            if pin_port as usize != port || ! falling || ! self.bit_set(interrupt.trigger().unwrap()) {
+                continue;
+            }
+            debug!(" ; {:?} edge", interrupt);
+            for &flag in interrupt.flags() {
+                self.set_bit(flag, true);
+            }
+        }
+    }
+
+    /// Update flags of level triggered external interrupts from the pins
+    fn sample_pins(&mut self) {
+        for &interrupt in [Interrupt::External0, Interrupt::External1].iter() {
+            if self.bit_set(interrupt.trigger().unwrap()) {
+                continue;
+            }
+            let (port, bit) = interrupt.pin().unwrap();
+            let low = ! self.pin(port, bit);
+            for &flag in interrupt.flags() {
+                self.set_bit(flag, low);
+            }
+        }
+    }
+
+    fn bit_set(&self, bit: u8) -> bool {
+        let (address, mask) = self.bit(bit);
+        self.load(address) & mask != 0
+    }
+
+    fn set_bit(&mut self, bit: u8, value: bool) {
+        let (address, mask) = self.bit(bit);
+        let old = self.load(address);
+        let new = if value { old | mask } else { old & !mask };
+        if new != old {
+            self.store(address, new);
+        }
+    }
+
+    /// Level of pin `bit` of port `port`
+    pub fn pin(&self, port: u8, bit: u8) -> bool {
+        self.gpio.pin(port as usize, bit)
+    }
+
+    fn wake(&mut self) {
+        let pcon = self.load(self.pcon());
+        self.store(self.pcon(), pcon & !0b11);
+    }
+
+    /// Remove and return diagnostics recorded in strict mode
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.replace(Vec::new())
+    }
+
+    fn report(&self, diagnostic: Diagnostic) {
+        if self.strict {
+            debug!(" ; {:?}", diagnostic);
+            self.diagnostics.borrow_mut().push(diagnostic);
+        }
+    }
+
+    fn check_sfr(&self, i: u8) {
+        if ! self.variant.has_sfr(i) {
+            self.report(Diagnostic::Sfr { pc: self.instruction, addr: i });
+        }
+    }
+
+    fn check_iram(&self, i: u8) -> bool {
+        if (i as usize) < self.iram.len() {
+            true
+        } else {
+            self.report(Diagnostic::IRam { pc: self.instruction, addr: i });
+            false
         }
     }
 }
 
 impl Mem for Mcu {
     fn load(&self, addr: Addr) -> u8 {
+        if let Some(value) = self.semihost.as_ref().and_then(|semihost| semihost.load(addr)) {
+            return value;
+        }
+        if let Some(value) = self.spi.iter().find_map(|bus| bus.load(addr)) {
+            return value;
+        }
+        if let Some(value) = self.i2c.iter().find_map(|bus| bus.load(addr)) {
+            return value;
+        }
+        if let Some(value) = self.ps2.iter().find_map(|port| port.load(addr)) {
+            return value;
+        }
+        if let Some(value) = self.matrix.as_ref().and_then(|matrix| matrix.load(addr, self.cycles)) {
+            return value;
+        }
+        if let Some(value) = self.adc.as_ref().and_then(|adc| adc.load(addr)) {
+            return value;
+        }
+        if let Some(value) = self.ite.as_ref().and_then(|ite| ite.load(addr)) {
+            return value;
+        }
+
         match addr {
             Addr::Reg(i) => if i < 0x80 {
                 self.iram[i as usize]
+            } else if let Some(port) = Gpio::port(i) {
+                self.gpio.ports[port].pins()
+            } else if i == 0x99 {
+                self.uart.rbuf
             } else {
+                self.check_sfr(i);
                 self.sfr[i as usize - 0x80]
             }
-            Addr::IRam(i) => self.iram[i as usize],
-            Addr::PMem(i) => self.pmem[i as usize],
+            Addr::IRam(i) => if self.check_iram(i) {
+                self.iram[i as usize]
+            } else {
+                // Undefined, reads as a floating bus
+                0xFF
+            },
+            // Scratch SRAM mapped over code, or unprogrammed flash beyond the ROM image
+            Addr::PMem(i) => match self.ite.as_ref().and_then(|ite| ite.scratch(i)) {
+                Some(offset) => self.xram[offset],
+                None => self.pmem.get(i as usize).cloned().unwrap_or(0xFF),
+            },
             Addr::XRam(i) => self.xram[i as usize],
         }
     }
 
+    fn load_rmw(&self, addr: Addr) -> u8 {
+        match addr {
+            Addr::Reg(i) => match Gpio::port(i) {
+                Some(port) => self.gpio.ports[port].latch,
+                None => self.load(addr),
+            },
+            _ => self.load(addr),
+        }
+    }
+
+    fn read(&mut self, addr: Addr) -> u8 {
+        let value = self.load(addr);
+        if let Some(ite) = self.ite.as_mut() {
+            ite.read(addr);
+        }
+        value
+    }
+
     fn store(&mut self, addr: Addr, value: u8) {
+        if let Some(writes) = self.writes.as_mut() {
+            writes.push((addr, value));
+        }
+
+        if let Some(semihost) = self.semihost.as_mut() {
+            if semihost.store(addr, value, self.cycles) {
+                if semihost.exit.is_some() {
+                    self.halted = true;
+                }
+                return;
+            }
+        }
+
+        if self.spi.iter_mut().any(|bus| bus.store(addr, value)) {
+            return;
+        }
+        if self.i2c.iter_mut().any(|bus| bus.store(addr, value)) {
+            return;
+        }
+        if let Some(index) = self.ps2.iter_mut().position(|port| port.store(addr, value)) {
+            self.sync_ps2(index);
+            return;
+        }
+        if self.matrix.as_mut().is_some_and(|matrix| matrix.store(addr, value)) {
+            return;
+        }
+        if self.adc.as_mut().is_some_and(|adc| adc.store(addr, value)) {
+            self.sync_adc();
+            return;
+        }
+        if let Some(ite) = self.ite.as_mut() {
+            if ite.store(addr, value) {
+                if let Some((code, xram, length)) = ite.take_dma() {
+                    for i in 0..length {
+                        let byte = self.pmem.get(code.wrapping_add(i) as usize).cloned().unwrap_or(0xFF);
+                        self.xram[(xram + i) as usize] = byte;
+                    }
+                }
+                return;
+            }
+        }
+
         match addr {
             Addr::Reg(i) => if i < 0x80 {
                 self.iram[i as usize] = value
+            } else if let Some(port) = Gpio::port(i) {
+                let old = self.gpio.ports[port].pins();
+                self.gpio.write(port, value, self.cycles);
+                self.pins_changed(port, old);
+            } else if i == 0x99 {
+                debug!(" ; uart tx 0x{:02X}", value);
+                self.uart.tx.push(value);
+                // Transmission completes immediately, set TI
+                self.sfr[0x98 - 0x80] |= 1 << 1;
             } else {
+                self.check_sfr(i);
                 self.sfr[i as usize - 0x80] = value
             }
-            Addr::IRam(i) => self.iram[i as usize] = value,
-            Addr::PMem(_) => panic!("pmem cannot be written"),
+            Addr::IRam(i) => if self.check_iram(i) {
+                self.iram[i as usize] = value
+            },
+            Addr::PMem(i) => if self.fault.is_none() {
+                self.fault = Some(Error::PMemWrite { addr: i });
+            },
             Addr::XRam(i) => self.xram[i as usize] = value,
         }
+
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.store(addr, value);
+        }
     }
 }
 
-impl Reg for Mcu {}
+impl Reg for Mcu {
+    fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    fn peripheral_request(&self) -> Option<Interrupt> {
+        let adc = self.adc.as_ref().filter(|adc| adc.done())?;
+        adc.map.interrupt.map(Interrupt::Peripheral)
+    }
+}
 
 impl Isa for Mcu {
     fn pc(&self) -> u16 {
@@ -78,4 +1015,158 @@ impl Isa for Mcu {
     fn set_pc(&mut self, value: u16) {
         self.pc = value;
     }
+
+    fn fetch(&mut self, pc: u16) {
+        self.instruction = pc;
+        self.instructions += 1;
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+
+        // The oscillator is stopped in power-down mode
+        if self.power() != Power::PowerDown {
+            self.tick_timers(cycles);
+        }
+
+        while let Some(callback) = self.scheduler.pop(self.cycles) {
+            callback(self);
+        }
+
+        // Receive when REN is set and RI is clear, one byte per frame at the baud rate
+        let scon = self.sfr[0x98 - 0x80];
+        let receiving = scon & (1 << 4) != 0 && scon & (1 << 0) == 0 && self.cycles >= self.uart.next_rx;
+        if let Some(frame) = self.uart_frame_cycles().filter(|_| receiving) {
+            if let Some(value) = self.uart.rx.pop_front() {
+                debug!("  uart rx 0x{:02X}\n", value);
+                self.uart.rbuf = value;
+                self.uart.next_rx = self.cycles + frame;
+                self.sfr[0x98 - 0x80] = scon | (1 << 0);
+            }
+        }
+
+        let power = self.power();
+        let action = match &mut self.watchdog {
+            Some(watchdog) if power != Power::PowerDown => watchdog.tick(cycles, self.cycles),
+            _ => None,
+        };
+        match action {
+            Some(WatchdogAction::Reset) => self.reset(Reset::Watchdog),
+            Some(WatchdogAction::Halt) => self.halted = true,
+            Some(WatchdogAction::Interrupt(_)) | None => (),
+        }
+    }
+
+    fn interrupt(&mut self) -> Option<u16> {
+        if self.interrupts.blocked {
+            self.interrupts.blocked = false;
+            return None;
+        }
+
+        self.sample_pins();
+
+        let power = self.power();
+        if power == Power::PowerDown && ! self.variant.power_down_wake() {
+            return None;
+        }
+
+        if let Some((interrupt, priority)) = self.interrupts.pending(self) {
+            if power == Power::PowerDown {
+                match interrupt {
+                    Interrupt::External0 | Interrupt::External1 => (),
+                    _ => return None,
+                }
+            }
+            if power != Power::Active {
+                debug!("  wake {:?}\n", power);
+                self.wake();
+            }
+            if let Some(flag) = interrupt.clear_on_vector() {
+                let edge = interrupt.trigger().is_none_or(|trigger| self.bit_set(trigger));
+                if edge {
+                    self.set_bit(flag, false);
+                }
+            }
+            self.interrupts.enter(priority);
+            return Some(interrupt.vector());
+        }
+
+        // Watchdog interrupt, only masked by EA
+        if power != Power::PowerDown && self.load(Addr::Reg(0xA8)) & 0x80 != 0 && self.interrupts.accepts_low() {
+            if let Some(watchdog) = &mut self.watchdog {
+                if let (true, WatchdogAction::Interrupt(vector)) = (watchdog.pending, watchdog.action) {
+                    watchdog.pending = false;
+                    self.interrupts.enter(0);
+                    self.wake();
+                    return Some(vector);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn interrupt_return(&mut self) {
+        self.interrupts.exit();
+    }
+
+    fn halted(&self) -> bool {
+        self.halted
+    }
+
+    fn sleep(&mut self) -> bool {
+        match self.power() {
+            Power::Active => false,
+            Power::Idle => {
+                // Fast-forward to the next event that could end idle mode, or the end of the run
+                let cycles = self.next_event().unwrap_or(1).min(self.remaining()).max(1);
+                debug!("  idle {} cycles\n", cycles);
+                self.idle_cycles += cycles;
+                self.tick(cycles);
+                true
+            },
+            Power::PowerDown => {
+                // Clocks are stopped, but time passes for scheduled events
+                if let Some(cycle) = self.scheduler.next() {
+                    let cycles = cycle.saturating_sub(self.cycles).min(self.remaining()).max(1);
+                    self.tick(cycles);
+                }
+                true
+            },
+        }
+    }
+
+    fn reset_store(&mut self, addr: Addr, value: u8) {
+        // Reset values go straight to the register file, attached devices, the watchdog and the
+        // write log only see firmware writes
+        match addr {
+            Addr::Reg(i) if i >= 0x80 => match Gpio::port(i) {
+                Some(port) => self.gpio.write(port, value, self.cycles),
+                None => self.sfr[i as usize - 0x80] = value,
+            },
+            Addr::XRam(i) => self.xram[i as usize] = value,
+            _ => self.store(addr, value),
+        }
+    }
+
+    fn reset_peripherals(&mut self, _source: Reset) {
+        self.interrupts = Interrupts::default();
+        self.halted = false;
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.reset();
+        }
+        if let Some(semihost) = &mut self.semihost {
+            semihost.reset();
+        }
+        if let Some(ite) = &mut self.ite {
+            ite.reset();
+        }
+        if let Some(adc) = &mut self.adc {
+            adc.reset();
+        }
+    }
+
+    fn fault(&mut self) -> Option<Error> {
+        self.fault.take()
+    }
 }
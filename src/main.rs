@@ -1,29 +1,544 @@
-use area8051::{Addr, Isa, Mcu, Mem};
-use std::{env, fs};
+use area8051::{
+    diff, import_ucsim, parse_ihx, parse_number, parse_time, Addr, Budget, Exit, HostServer, Ite, IteChip, Mcu, Script,
+    Semihost, StopReason, TraceEntry, Variant,
+};
+#[cfg(unix)]
+use area8051::Pty;
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    process,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+const USAGE: &str = "\
+usage: area8051 [options] <rom>
+
+options:
+  --format bin|ihx       ROM format, defaults to ihx for .ihx and .hex files
+  --offset <address>     load the ROM at this program memory address
+  --variant <name>       8051, 8052, at89s52 or ite (default)
+  --ec <chip>            it8587 or it5570, map the peripherals of this ITE embedded controller
+  --host <address>       serve host I/O port reads and writes of the embedded controller over TCP,
+                         like 127.0.0.1:8051
+  --max-cycles <cycles>  stop after this many machine cycles
+  --timeout <time>       stop after this much emulated time, like 10ms
+  --frequency <hz>       oscillator frequency, 11059200 by default
+  --trace <path>         write each executed instruction in the reference trace format
+  --coverage <path>      write execution counts of each instruction address
+  --uart <target>        stdio (default), file:<path> or pty, which prints the terminal path
+  --baud <rate>          pace the PTY UART in real time at this baud rate
+  --exit-addr <address>  semihosting command register, xram:<address>, sfr:<address> or none,
+                         xram:0xFFFF by default
+  --script <path>        run a stimulus script and report its expectations
+  --diff <path>          compare execution with a reference trace, or a ucsim log ending in .ucsim
+  --help                 print this message
+
+Stdin is received by the UART when it uses stdio, otherwise by the semihosting console.
+
+exit status:
+  the status firmware exited with through the semihosting device, or
+  0  idle or power-down with nothing left to wake the processor, without a cycle or time limit
+  1  assertion, script or trace comparison failed
+  2  invalid arguments or input files
+  3  emulator fault, such as an unknown opcode or watchdog halt
+  4  cycle or time limit reached
+  5  idle or power-down with nothing left to wake the processor, before the cycle or time limit";
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_FAULT: i32 = 3;
+const EXIT_TIMEOUT: i32 = 4;
+const EXIT_STOPPED: i32 = 5;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Bin,
+    Ihx,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum UartTarget {
+    Stdio,
+    File(String),
+    Pty,
+}
+
+#[derive(Clone, Debug)]
+struct Options {
+    rom: String,
+    format: Option<Format>,
+    offset: u16,
+    variant: Variant,
+    ec: Option<IteChip>,
+    host: Option<String>,
+    max_cycles: Option<u64>,
+    timeout: Option<u64>,
+    frequency: Option<u64>,
+    trace: Option<String>,
+    coverage: Option<String>,
+    uart: UartTarget,
+    baud: Option<u32>,
+    exit_addr: Option<Addr>,
+    script: Option<String>,
+    diff: Option<String>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("area8051: {}\n\n{}", message, USAGE);
+    process::exit(EXIT_USAGE);
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    let value = parse_number(s)?;
+    if value > 0xFFFF {
+        return Err(format!("address '{}' is beyond 64 KiB", s));
+    }
+    Ok(value as u16)
+}
+
+fn parse_variant(s: &str) -> Result<Variant, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "8051" | "i8051" => Ok(Variant::I8051),
+        "8052" | "i8052" => Ok(Variant::I8052),
+        "at89s52" => Ok(Variant::At89s52),
+        "ite" => Ok(Variant::Ite),
+        _ => Err(format!("unknown variant '{}'", s)),
+    }
+}
+
+fn parse_ec(s: &str) -> Result<IteChip, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "it8587" | "it8587e" => Ok(IteChip::It8587),
+        "it5570" | "it5570e" => Ok(IteChip::It5570),
+        _ => Err(format!("unknown embedded controller '{}'", s)),
+    }
+}
+
+fn parse_exit_addr(s: &str) -> Result<Option<Addr>, String> {
+    if s == "none" {
+        return Ok(None);
+    }
+    let invalid = || format!("invalid exit address '{}'", s);
+    let (space, address) = s.split_once(':').ok_or_else(invalid)?;
+    let address = parse_number(address)?;
+    match space {
+        "xram" if (1..=0xFFFF).contains(&address) => Ok(Some(Addr::XRam(address as u16))),
+        "sfr" if (0x81..=0xFF).contains(&address) => Ok(Some(Addr::Reg(address as u8))),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        format: None,
+        offset: 0,
+        variant: Variant::default(),
+        ec: None,
+        host: None,
+        max_cycles: None,
+        timeout: None,
+        frequency: None,
+        trace: None,
+        coverage: None,
+        uart: UartTarget::Stdio,
+        baud: None,
+        exit_addr: Some(Addr::XRam(0xFFFF)),
+        script: None,
+        diff: None,
+    };
+    let mut rom = None;
+
+    while let Some(arg) = args.next() {
+        if ! arg.starts_with("--") {
+            if rom.is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            rom = Some(arg);
+            continue;
+        }
+
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        if name == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        let value = match inline.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("missing value for {}", name)),
+        };
+
+        match name.as_str() {
+            "--format" => options.format = Some(match value.as_str() {
+                "bin" => Format::Bin,
+                "ihx" | "hex" => Format::Ihx,
+                _ => return Err(format!("unknown format '{}'", value)),
+            }),
+            "--offset" => options.offset = parse_address(&value)?,
+            "--variant" => options.variant = parse_variant(&value)?,
+            "--ec" => options.ec = Some(parse_ec(&value)?),
+            "--host" => options.host = Some(value),
+            "--max-cycles" => options.max_cycles = Some(parse_number(&value)?),
+            "--timeout" => options.timeout = Some(parse_time(&value)?),
+            "--frequency" => {
+                let frequency = parse_number(&value)?;
+                if frequency == 0 {
+                    return Err("frequency must not be zero".to_string());
+                }
+                options.frequency = Some(frequency);
+            },
+            "--trace" => options.trace = Some(value),
+            "--coverage" => options.coverage = Some(value),
+            "--uart" => options.uart = match value.as_str() {
+                "stdio" => UartTarget::Stdio,
+                "pty" => UartTarget::Pty,
+                _ => match value.strip_prefix("file:") {
+                    Some(path) => UartTarget::File(path.to_string()),
+                    None => return Err(format!("invalid uart target '{}'", value)),
+                },
+            },
+            "--baud" => {
+                let baud = parse_number(&value)?;
+                if baud == 0 || baud > u32::MAX as u64 {
+                    return Err(format!("invalid baud rate '{}'", value));
+                }
+                options.baud = Some(baud as u32);
+            },
+            "--exit-addr" => options.exit_addr = parse_exit_addr(&value)?,
+            "--script" => options.script = Some(value),
+            "--diff" => options.diff = Some(value),
+            _ => return Err(format!("unknown option '{}'", name)),
+        }
+    }
+
+    options.rom = rom.ok_or("rom file not provided")?;
+    if options.ec.is_some() && options.variant != Variant::Ite {
+        return Err("--ec requires the ite variant".to_string());
+    }
+    if options.host.is_some() && options.ec.is_none() {
+        return Err("--host requires --ec".to_string());
+    }
+    if options.script.is_some() && options.diff.is_some() {
+        return Err("--script and --diff cannot be combined".to_string());
+    }
+    if options.baud.is_some() && options.uart != UartTarget::Pty {
+        return Err("--baud requires --uart pty".to_string());
+    }
+    if options.script.is_some() && options.uart == UartTarget::Pty {
+        return Err("--script and --uart pty cannot be combined".to_string());
+    }
+    if let Some(Addr::Reg(cmd)) = options.exit_addr {
+        // DATA is below CMD
+        if options.variant.has_sfr(cmd) || options.variant.has_sfr(cmd - 1) {
+            return Err(format!("exit address sfr:0x{:02X} overlaps an implemented register", cmd));
+        }
+    }
+    Ok(options)
+}
+
+fn load_rom(options: &Options) -> Result<Vec<u8>, String> {
+    let format = options.format.unwrap_or_else(|| {
+        let lower = options.rom.to_ascii_lowercase();
+        if lower.ends_with(".ihx") || lower.ends_with(".hex") {
+            Format::Ihx
+        } else {
+            Format::Bin
+        }
+    });
+
+    let data = fs::read(&options.rom).map_err(|err| format!("failed to read {}: {}", options.rom, err))?;
+    match format {
+        Format::Bin => {
+            let offset = options.offset as usize;
+            if offset + data.len() > 0x10000 {
+                return Err("rom does not fit in 64 KiB at this offset".to_string());
+            }
+            let mut pmem = vec![0xFF; offset];
+            pmem.extend(data);
+            Ok(pmem)
+        },
+        Format::Ihx => {
+            let source = String::from_utf8(data).map_err(|_| "ihx file is not text".to_string())?;
+            parse_ihx(&source, options.offset).map_err(|err| format!("{}: {}", options.rom, err))
+        },
+    }
+}
+
+#[cfg(not(unix))]
+struct Pty;
+
+#[cfg(not(unix))]
+impl Pty {
+    fn open(_baud: Option<u32>) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Other, "only supported on Unix"))
+    }
+
+    fn path(&self) -> &str {
+        ""
+    }
+
+    fn poll(&mut self, _uart: &mut area8051::Uart) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self, _uart: &mut area8051::Uart) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Forward stdin from a thread so that it can be polled without blocking
+fn stdin_channel() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0; 256];
+        while let Ok(count) = stdin.read(&mut buf) {
+            if count == 0 || buf[..count].iter().any(|&b| sender.send(b).is_err()) {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn create(path: &str) -> BufWriter<File> {
+    match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => usage_error(&format!("failed to create {}: {}", path, err)),
+    }
+}
+
+fn read_to_string(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => usage_error(&format!("failed to read {}: {}", path, err)),
+    }
+}
 
 fn main() {
-    let file = env::args().nth(1).expect("rom file not provided");
-    let pmem = fs::read(file).expect("failed to read rom file");
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|err| usage_error(&err));
+    let pmem = load_rom(&options).unwrap_or_else(|err| usage_error(&err));
 
-    let mut mcu = Mcu::new(pmem.into_boxed_slice());
+    let mut mcu = Mcu::with_variant(options.variant, pmem.into_boxed_slice());
+    if let Some(frequency) = options.frequency {
+        mcu.frequency = frequency;
+    }
+    mcu.ite = options.ec.map(Ite::new);
 
-    mcu.reset();
+    mcu.power_on(None);
 
-    loop {
-        mcu.step();
+    // Reference trace to compare execution against
+    if let Some(path) = &options.diff {
+        let mut trace = read_to_string(path);
+        if path.ends_with(".ucsim") {
+            trace = import_ucsim(&trace).unwrap_or_else(|err| usage_error(&format!("{}: {}", path, err)));
+        }
+        match diff(&mut mcu, &trace) {
+            Ok(count) => {
+                eprintln!("{} instructions match", count);
+                process::exit(0);
+            },
+            Err(divergence) => {
+                eprintln!("divergence at {}", divergence);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    let stdin = if options.uart == UartTarget::Stdio {
+        Some(stdin_channel())
+    } else {
+        None
+    };
+
+    // Semihosting device, firmware exits by writing 1 to its command register
+    if let Some(addr) = options.exit_addr {
+        let base = match addr {
+            Addr::Reg(i) => Addr::Reg(i - 1),
+            Addr::XRam(i) => Addr::XRam(i - 1),
+            _ => unreachable!(),
+        };
+        let mut semihost = Semihost::new(base).unwrap_or_else(|err| usage_error(&err));
+        if stdin.is_none() {
+            semihost.reader = Some(Box::new(io::stdin()));
+        }
+        mcu.semihost = Some(semihost);
+    }
+
+    let mut uart_out: Option<Box<dyn Write>> = match &options.uart {
+        UartTarget::Stdio => Some(Box::new(io::stdout())),
+        UartTarget::File(path) => Some(Box::new(create(path))),
+        UartTarget::Pty => None,
+    };
+    let mut pty = match options.uart {
+        UartTarget::Pty => match Pty::open(options.baud) {
+            Ok(pty) => {
+                eprintln!("uart: {}", pty.path());
+                Some(pty)
+            },
+            Err(err) => usage_error(&format!("failed to open PTY: {}", err)),
+        },
+        _ => None,
+    };
+
+    let mut host = options.host.as_ref().map(|addr| {
+        match HostServer::bind(addr.as_str()) {
+            Ok(server) => {
+                if let Ok(addr) = server.local_addr() {
+                    eprintln!("host: {}", addr);
+                }
+                server
+            },
+            Err(err) => usage_error(&format!("failed to listen on {}: {}", addr, err)),
+        }
+    });
+
+    // Stimulus script
+    if let Some(path) = &options.script {
+        let script = Script::parse(&read_to_string(path)).unwrap_or_else(|err| {
+            usage_error(&format!("failed to parse script: {}", err))
+        });
+
+        let report = script.run(&mut mcu);
+        if let Some(uart_out) = &mut uart_out {
+            uart_out.write_all(&report.uart).ok();
+            uart_out.flush().ok();
+        }
+        for outcome in report.outcomes.iter() {
+            eprintln!(
+                "{}: line {}: {} (cycle {})",
+                if outcome.passed { "pass" } else { "FAIL" },
+                outcome.line,
+                outcome.text,
+                outcome.cycle
+            );
+        }
+        if let Some(err) = report.error {
+            eprintln!("error: {}", err);
+            process::exit(EXIT_FAULT);
+        }
+        process::exit(if report.passed() { 0 } else { EXIT_FAILURE });
+    }
+
+    let budget = {
+        let timeout = options.timeout.map(|ns| mcu.ns_to_cycles(ns));
+        match (options.max_cycles, timeout) {
+            (Some(a), Some(b)) => Budget::Cycles(a.min(b)),
+            (a, b) => Budget::Cycles(a.or(b).unwrap_or(u64::MAX)),
+        }
+    };
+
+    let mut trace = options.trace.as_deref().map(create);
+    let mut coverage = options.coverage.as_ref().map(|_| vec![0u64; 0x10000]);
+    if trace.is_some() {
+        mcu.writes = Some(Vec::new());
+    }
+    let mut instructions = mcu.instructions;
+    let mut steps = 0u64;
+
+    let reason = mcu.run(budget, |mcu| {
+        // Interrupt entries and steps in low power modes do not fetch an instruction
+        let fetched = mcu.instructions != instructions;
+        instructions = mcu.instructions;
+        if let Some(coverage) = coverage.as_mut().filter(|_| fetched) {
+            coverage[mcu.instruction() as usize] += 1;
+        }
+        if let Some(trace) = &mut trace {
+            let writes = mcu.writes.replace(Vec::new()).unwrap_or_default();
+            if fetched {
+                writeln!(trace, "{}", TraceEntry::capture(mcu, mcu.instruction(), &writes)).ok();
+            }
+        }
 
         // Serial bus
-        let s = mcu.load(Addr::Reg(0x98));
-        let b = mcu.load(Addr::Reg(0x99));
-        if b > 0 {
-            print!("{}", b as char);
-            mcu.store(Addr::Reg(0x98), s | (1 << 1));
-            mcu.store(Addr::Reg(0x99), 0);
+        steps += 1;
+        if let Some(pty) = &mut pty {
+            // Polling is a system call, avoid it on most steps
+            if ! mcu.uart.tx.is_empty() || steps.is_multiple_of(256) {
+                if let Err(err) = pty.poll(&mut mcu.uart) {
+                    eprintln!("uart: {}", err);
+                    process::exit(EXIT_FAULT);
+                }
+            }
+        }
+        if let Some(stdin) = &stdin {
+            mcu.uart.rx.extend(stdin.try_iter());
+        }
+        if let Some(uart_out) = &mut uart_out {
+            let tx = mcu.uart.take();
+            if ! tx.is_empty() {
+                uart_out.write_all(&tx).ok();
+                uart_out.flush().ok();
+            }
+        }
+
+        // Host I/O ports
+        if let Some(host) = &mut host {
+            if steps.is_multiple_of(256) {
+                if let Err(err) = host.poll(mcu) {
+                    eprintln!("host: {}", err);
+                    process::exit(EXIT_FAULT);
+                }
+            }
+        }
+
+        // Semihosting console
+        if let Some(semihost) = &mut mcu.semihost {
+            let output = semihost.take();
+            if ! output.is_empty() {
+                let mut stdout = io::stdout();
+                stdout.write_all(&output).ok();
+                stdout.flush().ok();
+            }
         }
 
-        // Shutdown signal at 0xFFFF
-        if mcu.load(Addr::XRam(0xFFFF)) > 0 {
-            break;
+        false
+    });
+
+    if let Some(pty) = &mut pty {
+        pty.flush(&mut mcu.uart).ok();
+    }
+    if let Some(mut trace) = trace {
+        trace.flush().ok();
+    }
+    if let (Some(path), Some(coverage)) = (&options.coverage, coverage) {
+        let mut file = create(path);
+        for (pc, count) in coverage.iter().enumerate().filter(|(_, &count)| count > 0) {
+            writeln!(file, "{:04X} {}", pc, count).ok();
         }
+        file.flush().ok();
+    }
+
+    match reason {
+        StopReason::Exit(Exit::Status(status)) => process::exit(status as i32),
+        StopReason::Exit(Exit::AssertFailed(message)) => {
+            eprintln!("assertion failed at cycle {}: {}", mcu.cycles, message);
+            process::exit(EXIT_FAILURE);
+        },
+        StopReason::Idle | StopReason::PowerDown => {
+            eprintln!("{:?} at cycle {}", mcu.power(), mcu.cycles);
+            if options.max_cycles.is_some() || options.timeout.is_some() {
+                process::exit(EXIT_STOPPED);
+            }
+        },
+        StopReason::Predicate | StopReason::Breakpoint(_) => (),
+        StopReason::Budget => {
+            eprintln!("limit reached at cycle {}, pc 0x{:04X}", mcu.cycles, mcu.pc);
+            process::exit(EXIT_TIMEOUT);
+        },
+        StopReason::Halted => {
+            eprintln!("halted at cycle {}", mcu.cycles);
+            process::exit(EXIT_FAULT);
+        },
+        StopReason::Error(err) => {
+            eprintln!("error at cycle {}: {}", mcu.cycles, err);
+            process::exit(EXIT_FAULT);
+        },
     }
 }
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use crate::Addr;
+
+/// Machine cycles each contact level lasts while a key bounces
+pub(crate) const CHATTER: u64 = 16;
+
+/// Scan lines of a keyboard matrix
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MatrixLines {
+    /// KSO column outputs and KSI row inputs on GPIO pins, each as port and bit
+    Pins { outputs: Vec<(u8, u8)>, inputs: Vec<(u8, u8)> },
+    /// Scan registers, column output latches with 8 columns each and the row input register
+    Registers { outputs: Vec<Addr>, input: Addr },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Key {
+    pressed: bool,
+    /// Cycle of the last press or release
+    changed: u64,
+}
+
+/// Keyboard matrix, a key at a row and column connects a KSI row input to a KSO column output
+/// Firmware drives one column low at a time and reads the rows, those with a closed key on that
+/// column read low.
+#[derive(Clone, Debug)]
+pub struct KeyMatrix {
+    pub lines: MatrixLines,
+    /// Without diodes, keys at three corners of a rectangle make the fourth read as pressed
+    pub ghosting: bool,
+    /// Machine cycles a contact chatters at random after each press or release
+    pub bounce: u64,
+    keys: BTreeMap<(usize, usize), Key>,
+    /// Column output latches of the registers
+    latches: Vec<u8>,
+}
+
+impl KeyMatrix {
+    pub fn new(lines: MatrixLines) -> Self {
+        let latches = match &lines {
+            MatrixLines::Registers { outputs, .. } => vec![0xFF; outputs.len()],
+            MatrixLines::Pins { .. } => Vec::new(),
+        };
+        let matrix = Self {
+            lines,
+            ghosting: false,
+            bounce: 0,
+            keys: BTreeMap::new(),
+            latches,
+        };
+        assert!(matrix.rows() <= 32 && matrix.columns() <= 32, "matrix larger than 32 by 32");
+        matrix
+    }
+
+    /// Keyboard scan registers of the ITE embedded controller, KSOL, KSOH1 and KSOH2 for columns 0
+    /// to 23, of which the chips have 18, and KSI for 8 rows
+    pub fn ite() -> Self {
+        Self::new(MatrixLines::Registers {
+            outputs: vec![Addr::XRam(0x1D00), Addr::XRam(0x1D01), Addr::XRam(0x1D03)],
+            input: Addr::XRam(0x1D04),
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        match &self.lines {
+            MatrixLines::Pins { inputs, .. } => inputs.len(),
+            MatrixLines::Registers { .. } => 8,
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        match &self.lines {
+            MatrixLines::Pins { outputs, .. } => outputs.len(),
+            MatrixLines::Registers { outputs, .. } => outputs.len() * 8,
+        }
+    }
+
+    pub fn pressed(&self, row: usize, column: usize) -> bool {
+        self.keys.get(&(row, column)).is_some_and(|key| key.pressed)
+    }
+
+    /// Press or release the key at `row` and `column` at `cycle`
+    pub(crate) fn set(&mut self, row: usize, column: usize, pressed: bool, cycle: u64) {
+        assert!(row < self.rows() && column < self.columns(), "no key at row {} column {}", row, column);
+        if self.pressed(row, column) != pressed {
+            debug!(" ; key {} {} {}", row, column, if pressed { "pressed" } else { "released" });
+            self.keys.insert((row, column), Key { pressed, changed: cycle });
+        }
+    }
+
+    /// Any contact still bouncing at `cycle`
+    pub(crate) fn bouncing(&self, cycle: u64) -> bool {
+        self.keys.values().any(|key| cycle < key.changed + self.bounce)
+    }
+
+    /// Contact of the key at `row` and `column` closed at `cycle`
+    fn closed(&self, row: usize, column: usize, cycle: u64) -> bool {
+        let key = match self.keys.get(&(row, column)) {
+            Some(key) => key,
+            None => return false,
+        };
+        if cycle >= key.changed + self.bounce {
+            return key.pressed;
+        }
+        // Chatter, the same for each slice of a bounce
+        let slice = (cycle - key.changed) / CHATTER;
+        let mut x = (row as u64) << 48 ^ (column as u64) << 32 ^ key.changed;
+        x ^= slice.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        x ^= x >> 33;
+        x = x.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        x ^= x >> 33;
+        x & 1 != 0
+    }
+
+    /// Rows read low at `cycle`, as a mask, with the columns in `low` driven low
+    pub(crate) fn rows_low(&self, low: u32, cycle: u64) -> u32 {
+        let closed: Vec<(usize, usize)> = self.keys.keys()
+            .filter(|&&(row, column)| self.closed(row, column, cycle))
+            .cloned()
+            .collect();
+        let mut columns = low;
+        let mut rows = 0;
+        loop {
+            for &(row, column) in closed.iter() {
+                if columns & (1 << column) != 0 {
+                    rows |= 1 << row;
+                }
+            }
+            if ! self.ghosting {
+                return rows;
+            }
+            // Current also flows back through closed keys into other columns
+            let mut reached = columns;
+            for &(row, column) in closed.iter() {
+                if rows & (1 << row) != 0 {
+                    reached |= 1 << column;
+                }
+            }
+            if reached == columns {
+                return rows;
+            }
+            columns = reached;
+        }
+    }
+
+    /// Columns driven low by the output registers
+    pub(crate) fn latched_low(&self) -> u32 {
+        self.latches.iter().enumerate().fold(0, |low, (i, &latch)| low | (!latch as u32) << (8 * i))
+    }
+
+    /// Pins the matrix depends on, to know when to update it
+    pub(crate) fn uses_port(&self, port: u8) -> bool {
+        match &self.lines {
+            MatrixLines::Pins { outputs, .. } => outputs.iter().any(|pin| pin.0 == port),
+            MatrixLines::Registers { .. } => false,
+        }
+    }
+
+    /// Register read at `cycle`
+    pub(crate) fn load(&self, addr: Addr, cycle: u64) -> Option<u8> {
+        let (outputs, input) = match &self.lines {
+            MatrixLines::Registers { outputs, input } => (outputs, *input),
+            MatrixLines::Pins { .. } => return None,
+        };
+        if addr == input {
+            return Some(!self.rows_low(self.latched_low(), cycle) as u8);
+        }
+        outputs.iter().position(|&output| output == addr).map(|i| self.latches[i])
+    }
+
+    /// Register write, returns false if `addr` is not one of the output registers
+    pub(crate) fn store(&mut self, addr: Addr, value: u8) -> bool {
+        let outputs = match &self.lines {
+            MatrixLines::Registers { outputs, .. } => outputs,
+            MatrixLines::Pins { .. } => return false,
+        };
+        match outputs.iter().position(|&output| output == addr) {
+            Some(i) => {
+                self.latches[i] = value;
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -2,5 +2,17 @@ use crate::Addr;
 
 pub trait Mem {
     fn load(&self, addr: Addr) -> u8;
+
+    /// Load for a read-modify-write instruction, which reads port latches instead of pins
+    fn load_rmw(&self, addr: Addr) -> u8 {
+        self.load(addr)
+    }
+
+    /// Load by a firmware instruction, which may change device state, like a status flag cleared by
+    /// reading a data register. `load` has no side effects, for debuggers and trace output.
+    fn read(&mut self, addr: Addr) -> u8 {
+        self.load(addr)
+    }
+
     fn store(&mut self, addr: Addr, value: u8);
 }
@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// Releases bytes no faster than a serial line at a baud rate
+#[derive(Clone, Debug)]
+pub struct Pacer {
+    /// Time of one byte, a start bit, eight data bits and a stop bit
+    interval: Option<Duration>,
+    /// Time the line is free for the next byte
+    next: Instant,
+    /// All bytes offered so far were released, so the line is idle once `next` passes
+    idle: bool,
+}
+
+impl Pacer {
+    /// Pace at `baud`, or release everything at once without one
+    pub fn new(baud: Option<u32>) -> Self {
+        Self {
+            interval: baud.map(|baud| Duration::from_nanos(10_000_000_000 / baud.max(1) as u64)),
+            next: Instant::now(),
+            idle: true,
+        }
+    }
+
+    /// Number of bytes that may be released now, out of `available`
+    pub fn take(&mut self, available: usize) -> usize {
+        self.take_at(Instant::now(), available)
+    }
+
+    /// Number of bytes that may be released at `now`, out of `available`
+    pub fn take_at(&mut self, now: Instant, available: usize) -> usize {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return available,
+        };
+        // Do not accumulate credit while the line is idle, only while bytes wait
+        if self.idle && self.next < now {
+            self.next = now;
+        }
+        let mut count = 0;
+        while count < available && self.next <= now {
+            self.next += interval;
+            count += 1;
+        }
+        self.idle = count == available;
+        count
+    }
+}
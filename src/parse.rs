@@ -0,0 +1,19 @@
+/// Parse a decimal number, or a hexadecimal number prefixed with `0x`
+pub fn parse_number(s: &str) -> Result<u64, String> {
+    let result = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    result.map_err(|_| format!("invalid number '{}'", s))
+}
+
+/// Parse a time like `10ms` into nanoseconds, the unit is one of `ns`, `us`, `ms` or `s`
+pub fn parse_time(s: &str) -> Result<u64, String> {
+    let units = [("ns", 1), ("us", 1_000), ("ms", 1_000_000), ("s", 1_000_000_000)];
+    let (number, scale) = units.iter()
+        .find_map(|&(unit, scale)| s.strip_suffix(unit).map(|number| (number, scale)))
+        .ok_or_else(|| format!("invalid time '{}', expected a unit of ns, us, ms or s", s))?;
+    let value = parse_number(number)?;
+    value.checked_mul(scale).ok_or_else(|| format!("time '{}' is too long", s))
+}
@@ -0,0 +1,175 @@
+/// Minimal regular expression over bytes
+/// Supports literals, `.`, classes like `[a-z]` and `[^0-9]`, escapes `\d`, `\w`, `\s`, `\n`, `\r`,
+/// `\t` and `\xHH`, the repetitions `*`, `+` and `?`, and the anchors `^` and `$`
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    start: bool,
+    end: bool,
+    items: Vec<(Atom, Repeat)>,
+}
+
+#[derive(Clone, Debug)]
+enum Atom {
+    Any,
+    Byte(u8),
+    /// Inclusive ranges, negated if the flag is set
+    Class(Vec<(u8, u8)>, bool),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Repeat {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+impl Atom {
+    fn matches(&self, byte: u8) -> bool {
+        match self {
+            Atom::Any => byte != b'\n',
+            Atom::Byte(value) => byte == *value,
+            Atom::Class(ranges, negated) => {
+                ranges.iter().any(|&(low, high)| byte >= low && byte <= high) != *negated
+            },
+        }
+    }
+}
+
+fn escape(c: u8, chars: &mut std::slice::Iter<u8>) -> Result<Atom, String> {
+    Ok(match c {
+        b'd' => Atom::Class(vec![(b'0', b'9')], false),
+        b'w' => Atom::Class(vec![(b'0', b'9'), (b'A', b'Z'), (b'a', b'z'), (b'_', b'_')], false),
+        b's' => Atom::Class(vec![(b' ', b' '), (b'\t', b'\r')], false),
+        b'n' => Atom::Byte(b'\n'),
+        b'r' => Atom::Byte(b'\r'),
+        b't' => Atom::Byte(b'\t'),
+        b'x' => {
+            let hex: Vec<u8> = chars.take(2).cloned().collect();
+            let hex = std::str::from_utf8(&hex).map_err(|err| err.to_string())?;
+            let value = u8::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 2)
+                .ok_or_else(|| format!("invalid escape \\x{}", hex))?;
+            Atom::Byte(value)
+        },
+        _ => Atom::Byte(c),
+    })
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut bytes = pattern.as_bytes();
+        let start = bytes.first() == Some(&b'^');
+        if start {
+            bytes = &bytes[1..];
+        }
+        // A `$` after an odd number of backslashes is escaped
+        let backslashes = bytes.iter().rev().skip(1).take_while(|&&c| c == b'\\').count();
+        let end = bytes.last() == Some(&b'$') && backslashes % 2 == 0;
+        if end {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+
+        let mut items: Vec<(Atom, Repeat)> = Vec::new();
+        let mut chars = bytes.iter();
+        while let Some(&c) = chars.next() {
+            let atom = match c {
+                b'.' => Atom::Any,
+                b'\\' => {
+                    let c = *chars.next().ok_or("trailing backslash")?;
+                    escape(c, &mut chars)?
+                },
+                b'[' => {
+                    let mut ranges = Vec::new();
+                    let mut negated = false;
+                    let mut first = true;
+                    loop {
+                        let c = *chars.next().ok_or("unterminated class")?;
+                        let low = match c {
+                            b']' if ! first => break,
+                            b'^' if first => {
+                                negated = true;
+                                continue;
+                            },
+                            b'\\' => {
+                                let c = *chars.next().ok_or("trailing backslash")?;
+                                match escape(c, &mut chars)? {
+                                    Atom::Byte(value) => value,
+                                    Atom::Class(class, _) => {
+                                        ranges.extend(class);
+                                        first = false;
+                                        continue;
+                                    },
+                                    Atom::Any => unreachable!(),
+                                }
+                            },
+                            _ => c,
+                        };
+                        first = false;
+                        let mut lookahead = chars.clone();
+                        match (lookahead.next(), lookahead.next()) {
+                            (Some(b'-'), Some(&high)) if high != b']' => {
+                                chars = lookahead;
+                                ranges.push((low, high));
+                            },
+                            _ => ranges.push((low, low)),
+                        }
+                    }
+                    Atom::Class(ranges, negated)
+                },
+                b'*' | b'+' | b'?' => {
+                    let item = items.last_mut().ok_or("repetition without atom")?;
+                    if item.1 != Repeat::One {
+                        return Err("nested repetition".to_string());
+                    }
+                    item.1 = match c {
+                        b'*' => Repeat::ZeroOrMore,
+                        b'+' => Repeat::OneOrMore,
+                        _ => Repeat::ZeroOrOne,
+                    };
+                    continue;
+                },
+                _ => Atom::Byte(c),
+            };
+            items.push((atom, Repeat::One));
+        }
+
+        Ok(Self { start, end, items })
+    }
+
+    fn matches_at(&self, items: &[(Atom, Repeat)], data: &[u8]) -> bool {
+        let (atom, repeat) = match items.first() {
+            Some(item) => item,
+            None => return ! self.end || data.is_empty(),
+        };
+        let rest = &items[1..];
+
+        let (min, max) = match repeat {
+            Repeat::One => (1, 1),
+            Repeat::ZeroOrOne => (0, 1),
+            Repeat::ZeroOrMore => (0, usize::MAX),
+            Repeat::OneOrMore => (1, usize::MAX),
+        };
+
+        let count = data.iter()
+            .take(max)
+            .take_while(|&&byte| atom.matches(byte))
+            .count();
+        if count < min {
+            return false;
+        }
+
+        // Greedy, backtrack to shorter repetitions
+        (min..=count).rev().any(|i| self.matches_at(rest, &data[i..]))
+    }
+
+    /// Returns true if the pattern matches anywhere in `data`
+    pub fn is_match(&self, data: &[u8]) -> bool {
+        if self.start {
+            self.matches_at(&self.items, data)
+        } else {
+            (0..=data.len()).any(|i| self.matches_at(&self.items, &data[i..]))
+        }
+    }
+}
@@ -0,0 +1,10 @@
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Power {
+    /// Executing instructions
+    Active,
+    /// PCON.IDL set, processor stopped until an interrupt or reset, peripherals running
+    Idle,
+    /// PCON.PD set, processor and peripherals stopped until reset or, if the variant allows it,
+    /// an external interrupt
+    PowerDown,
+}
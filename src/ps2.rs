@@ -0,0 +1,600 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::Addr;
+
+/// Device on a PS/2 channel, exchanging whole bytes with the host
+pub trait Ps2Device {
+    /// Byte received from the host, usually a command or its argument
+    fn receive(&mut self, value: u8);
+
+    /// Next byte to send to the host, if any
+    fn send(&mut self) -> Option<u8>;
+}
+
+/// Devices shared with the host, so tests can inject input while attached
+impl<T: Ps2Device> Ps2Device for Rc<RefCell<T>> {
+    fn receive(&mut self, value: u8) {
+        self.borrow_mut().receive(value)
+    }
+
+    fn send(&mut self) -> Option<u8> {
+        self.borrow_mut().send()
+    }
+}
+
+/// Device responses
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+const SELF_TEST_PASSED: u8 = 0xAA;
+
+/// Keyboard using scan code set 2 by default
+/// Commands from the host flush bytes not yet sent, like the output buffer of real keyboards.
+#[derive(Clone, Debug)]
+pub struct Ps2Keyboard {
+    /// Lock LEDs, scroll lock in bit 0, num lock in bit 1 and caps lock in bit 2
+    pub leds: u8,
+    pub scancode_set: u8,
+    /// Typematic rate and delay as set by the host
+    pub typematic: u8,
+    /// Scanning enabled, keys are only reported while set
+    pub enabled: bool,
+    output: VecDeque<u8>,
+    /// Command waiting for its argument
+    command: Option<u8>,
+    last: u8,
+}
+
+impl Ps2Keyboard {
+    pub fn new() -> Self {
+        Self {
+            leds: 0,
+            scancode_set: 2,
+            typematic: 0x2B,
+            enabled: true,
+            output: VecDeque::new(),
+            command: None,
+            last: 0,
+        }
+    }
+
+    /// Queue raw scan code bytes, reported even with scanning disabled
+    pub fn scancodes(&mut self, bytes: &[u8]) {
+        self.output.extend(bytes);
+    }
+
+    /// Report the make code of a key, with the 0xE0 prefix in the high byte for extended keys
+    pub fn press(&mut self, code: u16) {
+        if self.enabled {
+            if code > 0xFF {
+                self.output.push_back((code >> 8) as u8);
+            }
+            self.output.push_back(code as u8);
+        }
+    }
+
+    /// Report the break code of a key in scan code set 2
+    pub fn release(&mut self, code: u16) {
+        if self.enabled {
+            if code > 0xFF {
+                self.output.push_back((code >> 8) as u8);
+            }
+            self.output.extend(&[0xF0, code as u8]);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.leds = 0;
+        self.scancode_set = 2;
+        self.typematic = 0x2B;
+        self.enabled = true;
+    }
+}
+
+impl Default for Ps2Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ps2Device for Ps2Keyboard {
+    fn receive(&mut self, value: u8) {
+        self.output.clear();
+        if let Some(command) = self.command.take() {
+            match command {
+                0xED => self.leds = value & 0b111,
+                0xF0 if value == 0 => {
+                    self.output.extend(&[ACK, self.scancode_set]);
+                    return;
+                },
+                0xF0 if value <= 3 => self.scancode_set = value,
+                0xF3 => self.typematic = value & 0x7F,
+                _ => {
+                    self.output.push_back(RESEND);
+                    return;
+                },
+            }
+            self.output.push_back(ACK);
+            return;
+        }
+
+        debug!(" ; ps2 keyboard command 0x{:02X}", value);
+        match value {
+            0xED | 0xF0 | 0xF3 => {
+                self.command = Some(value);
+                self.output.push_back(ACK);
+            },
+            // Echo
+            0xEE => self.output.push_back(0xEE),
+            // Identify, as an MF2 keyboard
+            0xF2 => self.output.extend(&[ACK, 0xAB, 0x83]),
+            0xF4 => {
+                self.enabled = true;
+                self.output.push_back(ACK);
+            },
+            0xF5 | 0xF6 => {
+                self.reset();
+                self.enabled = value == 0xF6;
+                self.output.push_back(ACK);
+            },
+            0xFE => self.output.push_back(self.last),
+            0xFF => {
+                self.reset();
+                self.output.extend(&[ACK, SELF_TEST_PASSED]);
+            },
+            _ => self.output.push_back(RESEND),
+        }
+    }
+
+    fn send(&mut self) -> Option<u8> {
+        let value = self.output.pop_front()?;
+        if value != RESEND {
+            self.last = value;
+        }
+        Some(value)
+    }
+}
+
+/// Touchpad reporting through the standard PS/2 mouse protocol, in stream mode
+#[derive(Clone, Debug)]
+pub struct Ps2Touchpad {
+    /// Movement reports enabled
+    pub reporting: bool,
+    /// Samples per second
+    pub sample_rate: u8,
+    /// Counts per millimeter as 1 << resolution
+    pub resolution: u8,
+    /// 2:1 scaling
+    pub scaling: bool,
+    /// Buttons as in the first packet byte, left in bit 0, right in bit 1 and middle in bit 2
+    pub buttons: u8,
+    output: VecDeque<u8>,
+    command: Option<u8>,
+    last: u8,
+}
+
+impl Ps2Touchpad {
+    pub fn new() -> Self {
+        Self {
+            reporting: false,
+            sample_rate: 100,
+            resolution: 2,
+            scaling: false,
+            buttons: 0,
+            output: VecDeque::new(),
+            command: None,
+            last: 0,
+        }
+    }
+
+    /// Movement packet, with deltas saturated to 9 bit signed values and their overflow flags
+    fn packet(&self, dx: i16, dy: i16) -> [u8; 3] {
+        let clamp = |delta: i16| delta.clamp(-256, 255);
+        let overflow = |delta: i16| clamp(delta) != delta;
+        let (x, y) = (clamp(dx), clamp(dy));
+        let flags = self.buttons & 0b111 | 1 << 3
+            | ((x < 0) as u8) << 4 | ((y < 0) as u8) << 5
+            | (overflow(dx) as u8) << 6 | (overflow(dy) as u8) << 7;
+        [flags, x as u8, y as u8]
+    }
+
+    /// Report movement with the buttons in `buttons`, if reporting is enabled
+    /// Positive `dy` is upwards.
+    pub fn motion(&mut self, dx: i16, dy: i16, buttons: u8) {
+        self.buttons = buttons & 0b111;
+        if self.reporting {
+            let packet = self.packet(dx, dy);
+            self.output.extend(&packet);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reporting = false;
+        self.sample_rate = 100;
+        self.resolution = 2;
+        self.scaling = false;
+    }
+}
+
+impl Default for Ps2Touchpad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ps2Device for Ps2Touchpad {
+    fn receive(&mut self, value: u8) {
+        self.output.clear();
+        if let Some(command) = self.command.take() {
+            match command {
+                0xE8 if value <= 3 => self.resolution = value,
+                0xF3 => self.sample_rate = value,
+                _ => {
+                    self.output.push_back(RESEND);
+                    return;
+                },
+            }
+            self.output.push_back(ACK);
+            return;
+        }
+
+        debug!(" ; ps2 touchpad command 0x{:02X}", value);
+        match value {
+            0xE6 | 0xE7 => {
+                self.scaling = value == 0xE7;
+                self.output.push_back(ACK);
+            },
+            0xE8 | 0xF3 => {
+                self.command = Some(value);
+                self.output.push_back(ACK);
+            },
+            // Status request, buttons are ordered left, middle, right from bit 2
+            0xE9 => {
+                let buttons = (self.buttons & 1) << 2 | (self.buttons & 4) >> 1 | (self.buttons & 2) >> 1;
+                let status = (self.reporting as u8) << 5 | (self.scaling as u8) << 4 | buttons;
+                self.output.extend(&[ACK, status, self.resolution, self.sample_rate]);
+            },
+            // Stream mode
+            0xEA => self.output.push_back(ACK),
+            // Read data
+            0xEB => {
+                let packet = self.packet(0, 0);
+                self.output.push_back(ACK);
+                self.output.extend(&packet);
+            },
+            // Device ID of a standard mouse
+            0xF2 => self.output.extend(&[ACK, 0x00]),
+            0xF4 => {
+                self.reporting = true;
+                self.output.push_back(ACK);
+            },
+            0xF5 => {
+                self.reporting = false;
+                self.output.push_back(ACK);
+            },
+            0xF6 => {
+                self.reset();
+                self.output.push_back(ACK);
+            },
+            0xFE => self.output.push_back(self.last),
+            0xFF => {
+                self.reset();
+                self.output.extend(&[ACK, SELF_TEST_PASSED, 0x00]);
+            },
+            _ => self.output.push_back(RESEND),
+        }
+    }
+
+    fn send(&mut self) -> Option<u8> {
+        let value = self.output.pop_front()?;
+        if value != RESEND {
+            self.last = value;
+        }
+        Some(value)
+    }
+}
+
+/// PS/2 controller with control, status and data registers, as special function registers or in
+/// XRAM
+/// With PSHE set, the controller receives a byte from the device whenever TDS is clear, and with
+/// TRMS also set, writing the data register sends it to the device. TDS is set when a frame is
+/// done, writing ones to the status register clears them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ps2Controller {
+    /// Register addresses
+    pub ctl: Addr,
+    pub sts: Addr,
+    pub dat: Addr,
+    pub control: u8,
+    pub status: u8,
+    pub data: u8,
+}
+
+impl Ps2Controller {
+    /// Control register bits, hardware mode enable and transmit mode
+    pub const PSHE: u8 = 1 << 2;
+    pub const TRMS: u8 = 1 << 4;
+    /// Status register bit, transaction done
+    pub const TDS: u8 = 1 << 3;
+
+    pub fn new(ctl: Addr, sts: Addr, dat: Addr) -> Self {
+        Self {
+            ctl,
+            sts,
+            dat,
+            control: 0,
+            status: 0,
+            data: 0,
+        }
+    }
+
+    /// Channel `channel`, 0 to 2, of the ITE embedded controller, PSCTL, PSSTS and PSDAT
+    pub fn ite(channel: u16) -> Self {
+        Self::new(Addr::XRam(0x1700 + channel), Addr::XRam(0x1708 + channel), Addr::XRam(0x170C + channel))
+    }
+}
+
+/// Host side of a PS/2 channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ps2Link {
+    /// Open-drain clock and data lines on GPIO pins, each as port and bit
+    Pins { clock: (u8, u8), data: (u8, u8) },
+    /// Hardware controller
+    Controller(Ps2Controller),
+}
+
+/// Frame on a PS/2 channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ps2Event {
+    /// Byte sent by the device
+    ToHost(u8),
+    /// Byte received by the device
+    ToDevice(u8),
+    /// Sending stopped by the host holding the clock low, the byte is sent again later
+    Inhibited(u8),
+    /// Byte from the host with a bad parity or stop bit, the device asked for it again
+    FrameError(u8),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Idle,
+    /// Sending `value` to the host, at step `step` of three per bit
+    Send { value: u8, step: u8 },
+    /// Receiving from the host, at half clock period `step`, with the bits sampled so far
+    Receive { step: u8, bits: u16 },
+    /// Frame through a controller, done at the next step
+    Frame { value: u8, to_device: bool },
+}
+
+/// Odd parity bit of `value`
+fn parity(value: u8) -> bool {
+    value.count_ones().is_multiple_of(2)
+}
+
+/// PS/2 channel between a host and a device, which generates the clock
+/// Frames are a start bit, 8 data bits least significant first, odd parity and a stop bit. Device
+/// to host, data changes while the clock is high and the host samples it on the falling edge. Host
+/// to device, the host requests to send by holding the clock low, pulling data low and releasing
+/// the clock, then changes data while the clock is low and the device acknowledges the stop bit.
+pub struct Ps2Port {
+    pub link: Ps2Link,
+    pub device: Box<dyn Ps2Device>,
+    /// Machine cycles per clock period
+    pub period: u64,
+    pub log: Vec<Ps2Event>,
+    state: State,
+    /// Byte taken from the device and not yet sent
+    pending: Option<u8>,
+    /// Byte written by a controller in transmit mode and not yet sent
+    transmit: Option<u8>,
+    clock_low: bool,
+    data_low: bool,
+}
+
+impl Ps2Port {
+    pub fn new<D: Ps2Device + 'static>(link: Ps2Link, device: D) -> Self {
+        Self {
+            link,
+            device: Box::new(device),
+            // About 12 kHz with the default oscillator
+            period: 76,
+            log: Vec::new(),
+            state: State::Idle,
+            pending: None,
+            transmit: None,
+            clock_low: false,
+            data_low: false,
+        }
+    }
+
+    /// Pins the channel depends on, to know when to update it
+    pub(crate) fn uses_port(&self, port: u8) -> bool {
+        match self.link {
+            Ps2Link::Pins { clock, data } => clock.0 == port || data.0 == port,
+            Ps2Link::Controller(_) => false,
+        }
+    }
+
+    /// Lines pulled low by the device, as clock and data
+    pub(crate) fn drives(&self) -> (bool, bool) {
+        (self.clock_low, self.data_low)
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pending.is_none() {
+            self.pending = self.device.send();
+        }
+        self.pending
+    }
+
+    /// Begin a frame if the channel is idle and one can start, given the `(clock, data)` levels of
+    /// the lines. Returns the cycles until the first step.
+    pub(crate) fn start(&mut self, lines: Option<(bool, bool)>) -> Option<u64> {
+        if self.state != State::Idle {
+            return None;
+        }
+        match (self.link, lines) {
+            (Ps2Link::Pins { .. }, Some((clock, data))) => {
+                if ! clock {
+                    // Inhibited by the host
+                    None
+                } else if ! data {
+                    debug!(" ; ps2 request to send");
+                    self.state = State::Receive { step: 0, bits: 0 };
+                    Some(self.period / 2)
+                } else {
+                    let value = self.next()?;
+                    self.state = State::Send { value, step: 0 };
+                    Some(self.period)
+                }
+            },
+            (Ps2Link::Controller(controller), _) => {
+                let enabled = controller.control & Ps2Controller::PSHE != 0;
+                let transmit = controller.control & Ps2Controller::TRMS != 0;
+                if ! enabled || controller.status & Ps2Controller::TDS != 0 {
+                    return None;
+                }
+                if transmit {
+                    let value = self.transmit.take()?;
+                    self.state = State::Frame { value, to_device: true };
+                    Some(self.period * 12)
+                } else {
+                    let value = self.next()?;
+                    self.pending = None;
+                    self.state = State::Frame { value, to_device: false };
+                    Some(self.period * 11)
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Advance the current frame, given the `(clock, data)` levels of the lines, returns the cycles
+    /// until the next step or `None` when the channel is idle again
+    pub(crate) fn step(&mut self, lines: Option<(bool, bool)>) -> Option<u64> {
+        let half = self.period / 2;
+        match self.state {
+            State::Idle => None,
+            State::Send { value, step } => {
+                let bit = step / 3;
+                if bit == 11 {
+                    self.data_low = false;
+                    self.pending = None;
+                    self.state = State::Idle;
+                    self.log.push(Ps2Event::ToHost(value));
+                    return None;
+                }
+                self.state = State::Send { value, step: step + 1 };
+                match step % 3 {
+                    0 => {
+                        // The host may hold the clock low to stop the frame before the stop bit
+                        if lines.is_some_and(|(clock, _)| ! clock) {
+                            debug!(" ; ps2 inhibited sending 0x{:02X}", value);
+                            self.data_low = false;
+                            self.state = State::Idle;
+                            self.log.push(Ps2Event::Inhibited(value));
+                            return None;
+                        }
+                        let level = match bit {
+                            0 => false,
+                            1 ..= 8 => value & (1 << (bit - 1)) != 0,
+                            9 => parity(value),
+                            _ => true,
+                        };
+                        self.data_low = ! level;
+                        Some(half / 2)
+                    },
+                    1 => {
+                        self.clock_low = true;
+                        Some(half)
+                    },
+                    _ => {
+                        self.clock_low = false;
+                        Some(half - half / 2)
+                    },
+                }
+            },
+            State::Receive { step, mut bits } => {
+                match step {
+                    // Clock in data bits, parity and stop, sampled while the clock is high
+                    0 ..= 19 if step.is_multiple_of(2) => self.clock_low = true,
+                    0 ..= 19 => {
+                        self.clock_low = false;
+                        let data = lines.is_none_or(|(_, data)| data);
+                        bits |= (data as u16) << (step / 2);
+                    },
+                    // Acknowledge
+                    20 => {
+                        self.data_low = true;
+                        self.clock_low = true;
+                    },
+                    21 => self.clock_low = false,
+                    _ => {
+                        self.data_low = false;
+                        self.state = State::Idle;
+                        let value = bits as u8;
+                        let valid = (bits & 1 << 8 != 0) == parity(value) && bits & 1 << 9 != 0;
+                        if valid {
+                            debug!(" ; ps2 received 0x{:02X}", value);
+                            self.log.push(Ps2Event::ToDevice(value));
+                            // Commands discard output not yet sent
+                            self.pending = None;
+                            self.device.receive(value);
+                        } else {
+                            self.log.push(Ps2Event::FrameError(value));
+                            self.pending = Some(RESEND);
+                        }
+                        return None;
+                    },
+                }
+                self.state = State::Receive { step: step + 1, bits };
+                Some(half)
+            },
+            State::Frame { value, to_device } => {
+                self.state = State::Idle;
+                if let Ps2Link::Controller(controller) = &mut self.link {
+                    controller.status |= Ps2Controller::TDS;
+                    if to_device {
+                        self.log.push(Ps2Event::ToDevice(value));
+                        self.device.receive(value);
+                    } else {
+                        controller.data = value;
+                        self.log.push(Ps2Event::ToHost(value));
+                    }
+                }
+                None
+            },
+        }
+    }
+
+    /// Register read of a controller
+    pub(crate) fn load(&self, addr: Addr) -> Option<u8> {
+        match self.link {
+            Ps2Link::Controller(controller) if addr == controller.ctl => Some(controller.control),
+            Ps2Link::Controller(controller) if addr == controller.sts => Some(controller.status),
+            Ps2Link::Controller(controller) if addr == controller.dat => Some(controller.data),
+            _ => None,
+        }
+    }
+
+    /// Register write of a controller, returns false if `addr` is not one of its registers
+    pub(crate) fn store(&mut self, addr: Addr, value: u8) -> bool {
+        let controller = match &mut self.link {
+            Ps2Link::Controller(controller) => controller,
+            Ps2Link::Pins { .. } => return false,
+        };
+        if addr == controller.ctl {
+            controller.control = value;
+        } else if addr == controller.sts {
+            controller.status &= !value;
+        } else if addr == controller.dat {
+            controller.data = value;
+            if controller.control & Ps2Controller::TRMS != 0 {
+                self.transmit = Some(value);
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+}
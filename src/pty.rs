@@ -0,0 +1,121 @@
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Pacer, Uart};
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// UART backend on a pseudo-terminal, so serial tools can connect to the emulated firmware
+/// Transmitted bytes are written to the master side and bytes read from it are received. With a
+/// baud rate, bytes are paced in real time in both directions.
+pub struct Pty {
+    master: File,
+    /// Held open so the line settings persist and reads do not fail while no client is connected
+    _slave: File,
+    path: String,
+    tx: VecDeque<u8>,
+    rx: VecDeque<u8>,
+    tx_pacer: Pacer,
+    rx_pacer: Pacer,
+}
+
+impl Pty {
+    pub fn open(baud: Option<u32>) -> io::Result<Self> {
+        let fd = check(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) })?;
+        let master = unsafe { File::from_raw_fd(fd) };
+        check(unsafe { libc::grantpt(fd) })?;
+        check(unsafe { libc::unlockpt(fd) })?;
+        let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+
+        let name = unsafe { libc::ptsname(fd) };
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let path = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+
+        // Raw mode, so data is not echoed or translated by the line discipline
+        let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+        {
+            let mut termios: libc::termios = unsafe { mem::zeroed() };
+            check(unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) })?;
+            unsafe { libc::cfmakeraw(&mut termios) };
+            check(unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) })?;
+        }
+
+        Ok(Self {
+            master,
+            _slave: slave,
+            path,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            tx_pacer: Pacer::new(baud),
+            rx_pacer: Pacer::new(baud),
+        })
+    }
+
+    /// Path of the terminal for clients to open
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Exchange pending bytes with `uart`
+    pub fn poll(&mut self, uart: &mut Uart) -> io::Result<()> {
+        self.tx.extend(uart.take());
+        let count = self.tx_pacer.take(self.tx.len());
+        if count > 0 {
+            let data: Vec<u8> = self.tx.iter().take(count).cloned().collect();
+            match self.master.write(&data) {
+                Ok(written) => {
+                    self.tx.drain(..written);
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut buf = [0; 256];
+        match self.master.read(&mut buf) {
+            Ok(count) => self.rx.extend(&buf[..count]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => (),
+            Err(err) => return Err(err),
+        }
+        let count = self.rx_pacer.take(self.rx.len());
+        uart.rx.extend(self.rx.drain(..count));
+
+        Ok(())
+    }
+
+    /// Write remaining transmitted bytes, at the baud rate if pacing, giving up after a second
+    /// without progress
+    pub fn flush(&mut self, uart: &mut Uart) -> io::Result<()> {
+        self.tx.extend(uart.take());
+        let mut progress = Instant::now();
+        while ! self.tx.is_empty() && progress.elapsed() < Duration::from_secs(1) {
+            let remaining = self.tx.len();
+            let mut ignored = Uart::new();
+            self.poll(&mut ignored)?;
+            if self.tx.len() < remaining {
+                progress = Instant::now();
+            } else {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,13 @@
-use crate::{Addr, Mem};
+use crate::{Addr, Interrupt, Mem, Variant};
 
 pub trait Reg: Mem {
+    fn variant(&self) -> Variant;
+
+    /// Interrupt requested by an attached peripheral
+    fn peripheral_request(&self) -> Option<Interrupt> {
+        None
+    }
+
     fn r(&self, index: u8) -> Addr {
         if index >= 8 {
             panic!("Invalid register r{}", index);
@@ -32,15 +39,23 @@ pub trait Reg: Mem {
     }
 
     fn dptr(&self, index: bool) -> Addr {
-        if self.load(self.dps()) & 1 == 0 {
-            Addr::Reg(0x82 + (index as u8))
-        } else {
+        let second = match self.dps() {
+            Some(dps) => self.load(dps) & 1 != 0,
+            None => false,
+        };
+        if second {
             Addr::Reg(0x84 + (index as u8))
+        } else {
+            Addr::Reg(0x82 + (index as u8))
         }
     }
 
-    fn dps(&self) -> Addr {
-        Addr::Reg(0x86)
+    fn dps(&self) -> Option<Addr> {
+        self.variant().dps().map(Addr::Reg)
+    }
+
+    fn pcon(&self) -> Addr {
+        Addr::Reg(0x87)
     }
 
     fn psw(&self) -> Addr {
@@ -0,0 +1,11 @@
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reset {
+    /// Supply voltage applied, RAM contents are undefined
+    PowerOn,
+    /// Reset pin asserted
+    External,
+    /// Watchdog timer expired
+    Watchdog,
+    /// Reset requested by firmware
+    Software,
+}
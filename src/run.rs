@@ -0,0 +1,33 @@
+use crate::{Error, Exit};
+
+/// Limit on how long `Mcu::run` executes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Budget {
+    /// Steps, each an instruction, interrupt call or period of sleep
+    Instructions(u64),
+    /// Machine cycles
+    Cycles(u64),
+    /// Nanoseconds of emulated time
+    Ns(u64),
+}
+
+/// Reason `Mcu::run` returned
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    /// Budget exhausted
+    Budget,
+    /// About to execute the instruction at a breakpoint
+    Breakpoint(u16),
+    /// Stop predicate returned true
+    Predicate,
+    /// Firmware exited through the semihosting device
+    Exit(Exit),
+    /// Processor halted
+    Halted,
+    /// Processor in idle mode that nothing can end
+    Idle,
+    /// Processor in power-down mode with no scheduled event to wake it
+    PowerDown,
+    /// Execution fault
+    Error(Error),
+}
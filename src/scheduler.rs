@@ -0,0 +1,68 @@
+use crate::Mcu;
+
+pub type Callback = Box<dyn FnOnce(&mut Mcu)>;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct EventId(u64);
+
+struct Event {
+    cycle: u64,
+    id: EventId,
+    callback: Callback,
+}
+
+/// Callbacks keyed by machine cycle, events at the same cycle run in the order they were scheduled
+#[derive(Default)]
+pub struct Scheduler {
+    events: Vec<Event>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn schedule(&mut self, cycle: u64, callback: Callback) -> EventId {
+        let id = EventId(self.next_id);
+        self.next_id += 1;
+        let index = self.events.iter()
+            .position(|event| event.cycle > cycle)
+            .unwrap_or(self.events.len());
+        self.events.insert(index, Event { cycle, id, callback });
+        id
+    }
+
+    /// Remove a pending event, returns false if it already ran or was cancelled
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        match self.events.iter().position(|event| event.id == id) {
+            Some(index) => {
+                self.events.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Cycle of the earliest pending event
+    pub fn next(&self) -> Option<u64> {
+        self.events.first().map(|event| event.cycle)
+    }
+
+    /// Remove and return the earliest event if it is due at `cycle`
+    pub fn pop(&mut self, cycle: u64) -> Option<Callback> {
+        if self.next()? <= cycle {
+            Some(self.events.remove(0).callback)
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
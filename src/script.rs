@@ -0,0 +1,369 @@
+use crate::{parse_number, parse_time, Addr, Error, Exit, Isa, Mcu, Mem, Pattern, Power, Reset};
+
+/// Stimulus script, a line-based description of a test scenario
+///
+/// ```text
+/// # Comments start with a hash
+/// at 10ms set P1.3 low
+/// at 12ms set P1.3 float
+/// at 20ms send "AT\r"
+/// at 5000 cycles reset
+/// expect xram[0x1234] == 0x55 by 50ms
+/// expect P1.0 == high by 60ms
+/// expect uart /^OK\r\n/ by 100ms
+/// expect exit == 0 by 150ms
+/// end 200ms
+/// ```
+///
+/// Times are given in `ns`, `us`, `ms`, `s` or machine `cycles`, the default. Actions run at their
+/// time, expectations pass if their condition holds at any instruction boundary up to their time.
+/// `exit` is the status firmware exited with through the semihosting device.
+/// The script ends when all expectations are resolved, or at the `end` time if provided.
+#[derive(Clone, Debug)]
+pub struct Script {
+    actions: Vec<Action>,
+    expects: Vec<Expect>,
+    end: Option<Time>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Time {
+    Cycles(u64),
+    Ns(u64),
+}
+
+impl Time {
+    fn cycles(self, mcu: &Mcu) -> u64 {
+        match self {
+            Time::Cycles(cycles) => cycles,
+            Time::Ns(ns) => mcu.ns_to_cycles(ns),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum ActionKind {
+    Pin(u8, u8, Option<bool>),
+    Send(Vec<u8>),
+    Reset,
+}
+
+#[derive(Clone, Debug)]
+struct Action {
+    at: Time,
+    kind: ActionKind,
+}
+
+#[derive(Clone, Debug)]
+enum Check {
+    Mem(Addr, u8),
+    Pin(u8, u8, bool),
+    Uart(Pattern),
+    Exit(u8),
+}
+
+#[derive(Clone, Debug)]
+struct Expect {
+    line: usize,
+    text: String,
+    by: Time,
+    check: Check,
+}
+
+/// Result of one expectation
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub line: usize,
+    pub text: String,
+    pub passed: bool,
+    /// Cycle at which the expectation passed or failed
+    pub cycle: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+    /// Bytes transmitted on the UART during the run
+    pub uart: Vec<u8>,
+    pub cycles: u64,
+    /// Fault that stopped the run early
+    pub error: Option<Error>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.outcomes.iter().all(|outcome| outcome.passed)
+    }
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let value = parse_number(s)?;
+    if value > 0xFF {
+        return Err(format!("value '{}' does not fit in a byte", s));
+    }
+    Ok(value as u8)
+}
+
+/// Parse a time in machine cycles, given without a unit or with `cycles`, or with a unit of time
+fn parse_time_words<'a, I: Iterator<Item = &'a str>>(words: &mut std::iter::Peekable<I>) -> Result<Time, String> {
+    let word = words.next().ok_or("missing time")?;
+    if let Some(number) = word.strip_suffix("cycles") {
+        return Ok(Time::Cycles(parse_number(number)?));
+    }
+    if let Ok(cycles) = parse_number(word) {
+        if words.peek() == Some(&"cycles") {
+            words.next();
+        }
+        return Ok(Time::Cycles(cycles));
+    }
+    parse_time(word).map(Time::Ns)
+}
+
+/// Parse a pin name like `P1.3`
+fn parse_pin(s: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("invalid pin '{}'", s);
+    let rest = s.strip_prefix('P').or_else(|| s.strip_prefix('p')).ok_or_else(invalid)?;
+    let (port, bit) = rest.split_once('.').ok_or_else(invalid)?;
+    let port: u8 = port.parse().map_err(|_| invalid())?;
+    let bit: u8 = bit.parse().map_err(|_| invalid())?;
+    if port >= 4 || bit >= 8 {
+        return Err(invalid());
+    }
+    Ok((port, bit))
+}
+
+/// Parse a memory reference like `xram[0x1234]`
+fn parse_addr(s: &str) -> Result<Addr, String> {
+    let invalid = || format!("invalid address '{}'", s);
+    let (space, rest) = s.split_once('[').ok_or_else(invalid)?;
+    let index = parse_number(rest.strip_suffix(']').ok_or_else(invalid)?)?;
+    match space {
+        "reg" if index <= 0xFF => Ok(Addr::Reg(index as u8)),
+        "iram" if index <= 0xFF => Ok(Addr::IRam(index as u8)),
+        "pmem" if index <= 0xFFFF => Ok(Addr::PMem(index as u16)),
+        "xram" if index <= 0xFFFF => Ok(Addr::XRam(index as u16)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse a double quoted string with escapes
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("invalid string {}", s))?;
+    let mut data = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            data.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next().ok_or("trailing backslash")? {
+            'n' => data.push(b'\n'),
+            'r' => data.push(b'\r'),
+            't' => data.push(b'\t'),
+            '0' => data.push(0),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let value = u8::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 2);
+                data.push(value.ok_or_else(|| format!("invalid escape \\x{}", hex))?);
+            },
+            c => {
+                let mut buf = [0; 4];
+                data.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            },
+        }
+    }
+    Ok(data)
+}
+
+fn parse_level(s: &str) -> Result<Option<bool>, String> {
+    match s {
+        "low" | "0" => Ok(Some(false)),
+        "high" | "1" => Ok(Some(true)),
+        "float" | "release" => Ok(None),
+        _ => Err(format!("invalid level '{}'", s)),
+    }
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut script = Script {
+            actions: Vec::new(),
+            expects: Vec::new(),
+            end: None,
+        };
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            script.parse_line(line_number, line)
+                .map_err(|err| format!("line {}: {}", line_number, err))?;
+        }
+
+        Ok(script)
+    }
+
+    fn parse_line(&mut self, line_number: usize, line: &str) -> Result<(), String> {
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match keyword {
+            "at" => {
+                let mut words = rest.split_whitespace().peekable();
+                let at = parse_time_words(&mut words)?;
+                let kind = match words.next() {
+                    Some("set") => {
+                        let (port, bit) = parse_pin(words.next().ok_or("missing pin")?)?;
+                        let level = parse_level(words.next().ok_or("missing level")?)?;
+                        ActionKind::Pin(port, bit, level)
+                    },
+                    Some("send") => {
+                        // The string may contain whitespace, take the remainder of the line
+                        let start = rest.find('"').ok_or("missing string")?;
+                        let data = parse_string(&rest[start..])?;
+                        self.actions.push(Action { at, kind: ActionKind::Send(data) });
+                        return Ok(());
+                    },
+                    Some("reset") => ActionKind::Reset,
+                    Some(other) => return Err(format!("unknown action '{}'", other)),
+                    None => return Err("missing action".to_string()),
+                };
+                if let Some(extra) = words.next() {
+                    return Err(format!("unexpected '{}'", extra));
+                }
+                self.actions.push(Action { at, kind });
+            },
+            "expect" => {
+                let (condition, time) = rest.rsplit_once(" by ").ok_or("missing 'by <time>'")?;
+                let mut words = time.split_whitespace().peekable();
+                let by = parse_time_words(&mut words)?;
+                let condition = condition.trim();
+                let check = if let Some(pattern) = condition.strip_prefix("uart") {
+                    let pattern = pattern.trim()
+                        .strip_prefix('/')
+                        .and_then(|s| s.strip_suffix('/'))
+                        .ok_or("uart pattern must be enclosed in slashes")?;
+                    Check::Uart(Pattern::new(pattern)?)
+                } else {
+                    let (left, right) = condition.split_once("==").ok_or("missing '=='")?;
+                    let (left, right) = (left.trim(), right.trim());
+                    if left == "exit" {
+                        Check::Exit(parse_byte(right)?)
+                    } else if left.starts_with('P') || left.starts_with('p') {
+                        let (port, bit) = parse_pin(left)?;
+                        let level = parse_level(right)?.ok_or("expected level must be low or high")?;
+                        Check::Pin(port, bit, level)
+                    } else {
+                        Check::Mem(parse_addr(left)?, parse_byte(right)?)
+                    }
+                };
+                self.expects.push(Expect {
+                    line: line_number,
+                    text: line.to_string(),
+                    by,
+                    check,
+                });
+            },
+            "end" => {
+                let mut words = rest.split_whitespace().peekable();
+                self.end = Some(parse_time_words(&mut words)?);
+            },
+            _ => return Err(format!("unknown keyword '{}'", keyword)),
+        }
+        Ok(())
+    }
+
+    /// Execute the script against `mcu`, which should already be reset
+    pub fn run(&self, mcu: &mut Mcu) -> Report {
+        let start = mcu.cycles;
+
+        for action in self.actions.iter() {
+            let kind = action.kind.clone();
+            mcu.schedule_at(start + action.at.cycles(mcu), move |mcu| match kind {
+                ActionKind::Pin(port, bit, level) => mcu.drive_pin(port, bit, level),
+                ActionKind::Send(data) => mcu.uart.send(&data),
+                ActionKind::Reset => mcu.reset(Reset::External),
+            });
+        }
+
+        let deadlines: Vec<u64> = self.expects.iter()
+            .map(|expect| start + expect.by.cycles(mcu))
+            .collect();
+        let end = self.end.map(|end| start + end.cycles(mcu));
+        let last = end.or_else(|| deadlines.iter().cloned().max()).unwrap_or(start);
+
+        let mut uart = Vec::new();
+        let mut uart_changed = true;
+        let mut outcomes: Vec<Option<Outcome>> = vec![None; self.expects.len()];
+        let mut error = None;
+        loop {
+            for (i, expect) in self.expects.iter().enumerate() {
+                if outcomes[i].is_some() {
+                    continue;
+                }
+                let passed = match &expect.check {
+                    Check::Mem(addr, value) => mcu.load(*addr) == *value,
+                    Check::Pin(port, bit, high) => mcu.pin(*port, *bit) == *high,
+                    Check::Uart(pattern) => uart_changed && pattern.is_match(&uart),
+                    Check::Exit(status) => mcu.semihost.as_ref()
+                        .is_some_and(|semihost| semihost.exit == Some(Exit::Status(*status))),
+                };
+                if passed || mcu.cycles > deadlines[i] {
+                    outcomes[i] = Some(Outcome {
+                        line: expect.line,
+                        text: expect.text.clone(),
+                        passed,
+                        cycle: mcu.cycles - start,
+                    });
+                }
+            }
+
+            let resolved = outcomes.iter().all(|outcome| outcome.is_some());
+            if mcu.cycles >= last || (end.is_none() && resolved) || mcu.halted {
+                break;
+            }
+            if mcu.power() == Power::PowerDown && mcu.scheduler.is_empty() {
+                break;
+            }
+
+            // Idle fast-forwarding stops at the next unresolved deadline or the end
+            let limit = outcomes.iter().zip(deadlines.iter())
+                .filter(|(outcome, _)| outcome.is_none())
+                .fold(last, |limit, (_, &deadline)| limit.min(deadline));
+            let deadline = mcu.deadline;
+            mcu.deadline = limit.min(deadline);
+            let result = mcu.step();
+            mcu.deadline = deadline;
+
+            let data = mcu.uart.take();
+            uart_changed = ! data.is_empty();
+            uart.extend(data);
+
+            if let Err(err) = result {
+                error = Some(err);
+                break;
+            }
+        }
+
+        let cycles = mcu.cycles - start;
+        let outcomes = outcomes.into_iter().zip(self.expects.iter()).map(|(outcome, expect)| {
+            outcome.unwrap_or_else(|| Outcome {
+                line: expect.line,
+                text: expect.text.clone(),
+                passed: false,
+                cycle: cycles,
+            })
+        }).collect();
+
+        Report {
+            outcomes,
+            uart,
+            cycles,
+            error,
+        }
+    }
+}
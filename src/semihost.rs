@@ -0,0 +1,149 @@
+use std::{collections::VecDeque, fmt, io::Read};
+
+use crate::Addr;
+
+/// Exit with the status in DATA
+pub const CMD_EXIT: u8 = 0x01;
+/// Write DATA to the console
+pub const CMD_PUTCHAR: u8 = 0x02;
+/// Read a byte from the console into DATA, status is 1 at end of input
+pub const CMD_GETCHAR: u8 = 0x03;
+/// Read byte DATA of the cycle counter into DATA, the counter is latched when DATA is 0
+pub const CMD_CYCLES: u8 = 0x04;
+/// Append DATA to the assertion message
+pub const CMD_MESSAGE: u8 = 0x05;
+/// Fail with the assertion message
+pub const CMD_ASSERT: u8 = 0x06;
+
+/// How firmware ended the run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Exit {
+    Status(u8),
+    AssertFailed(String),
+}
+
+/// Host interface for test firmware, two registers mapped at a chosen SFR or XRAM address
+///
+/// DATA at the base address holds the argument and result of commands. Writing a command to CMD,
+/// at the following address, executes it. Reading CMD returns the status of the last command, 0 on
+/// success.
+pub struct Semihost {
+    pub base: Addr,
+    data: u8,
+    status: u8,
+    cycles: u64,
+    message: Vec<u8>,
+    /// Bytes written by firmware and not yet taken by the host
+    pub output: Vec<u8>,
+    /// Bytes waiting to be read by firmware
+    pub input: VecDeque<u8>,
+    /// Source of input once `input` is empty
+    pub reader: Option<Box<dyn Read>>,
+    pub exit: Option<Exit>,
+}
+
+impl fmt::Debug for Semihost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semihost")
+            .field("base", &self.base)
+            .field("data", &self.data)
+            .field("status", &self.status)
+            .field("exit", &self.exit)
+            .finish()
+    }
+}
+
+impl Semihost {
+    /// Map at `base`, which must be an SFR or XRAM address with room for both registers
+    pub fn new(base: Addr) -> Result<Self, String> {
+        match base {
+            Addr::Reg(i) if (0x80..0xFF).contains(&i) => (),
+            Addr::XRam(i) if i < 0xFFFF => (),
+            _ => return Err(format!("invalid semihosting address {:X?}", base)),
+        }
+        Ok(Self {
+            base,
+            data: 0,
+            status: 0,
+            cycles: 0,
+            message: Vec::new(),
+            output: Vec::new(),
+            input: VecDeque::new(),
+            reader: None,
+            exit: None,
+        })
+    }
+
+    /// Register offset of `addr`, if it is mapped
+    fn offset(&self, addr: Addr) -> Option<u8> {
+        match (self.base, addr) {
+            (Addr::Reg(base), Addr::Reg(i)) if i.wrapping_sub(base) < 2 => Some(i - base),
+            (Addr::XRam(base), Addr::XRam(i)) if i.wrapping_sub(base) < 2 => Some((i - base) as u8),
+            _ => None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.data = 0;
+        self.status = 0;
+        self.message.clear();
+        self.exit = None;
+    }
+
+    pub fn load(&self, addr: Addr) -> Option<u8> {
+        match self.offset(addr)? {
+            0 => Some(self.data),
+            _ => Some(self.status),
+        }
+    }
+
+    /// Returns false if `addr` is not mapped
+    pub fn store(&mut self, addr: Addr, value: u8, cycles: u64) -> bool {
+        match self.offset(addr) {
+            Some(0) => self.data = value,
+            Some(_) => self.command(value, cycles),
+            None => return false,
+        }
+        true
+    }
+
+    fn command(&mut self, cmd: u8, cycles: u64) {
+        self.status = 0;
+        match cmd {
+            CMD_EXIT => self.exit = Some(Exit::Status(self.data)),
+            CMD_PUTCHAR => self.output.push(self.data),
+            CMD_GETCHAR => match self.getchar() {
+                Some(value) => self.data = value,
+                None => self.status = 1,
+            },
+            CMD_CYCLES => {
+                if self.data == 0 {
+                    self.cycles = cycles;
+                }
+                self.data = self.cycles.checked_shr(self.data as u32 * 8).unwrap_or(0) as u8;
+            },
+            CMD_MESSAGE => self.message.push(self.data),
+            CMD_ASSERT => {
+                let message = String::from_utf8_lossy(&self.message).into_owned();
+                self.exit = Some(Exit::AssertFailed(message));
+            },
+            _ => self.status = 0xFF,
+        }
+    }
+
+    fn getchar(&mut self) -> Option<u8> {
+        if let Some(value) = self.input.pop_front() {
+            return Some(value);
+        }
+        let mut buf = [0];
+        match self.reader.as_mut()?.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    /// Remove and return bytes written by firmware
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
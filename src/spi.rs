@@ -0,0 +1,302 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::Addr;
+
+/// Virtual device on an SPI bus, exchanging whole bytes most significant bit first
+/// The byte shifted out is requested before the byte shifted in during the same transfer, so it can
+/// only depend on earlier bytes, as on real devices.
+pub trait SpiDevice {
+    /// Chip select asserted
+    fn select(&mut self) {}
+
+    /// Byte to shift out to the master during the next transfer
+    fn output(&mut self) -> u8;
+
+    /// Byte shifted in from the master
+    fn input(&mut self, value: u8);
+
+    /// Chip select released, ending the command
+    fn deselect(&mut self) {}
+}
+
+/// Devices shared with the host, so tests can inspect them while attached
+impl<T: SpiDevice> SpiDevice for Rc<RefCell<T>> {
+    fn select(&mut self) {
+        self.borrow_mut().select()
+    }
+
+    fn output(&mut self) -> u8 {
+        self.borrow_mut().output()
+    }
+
+    fn input(&mut self, value: u8) {
+        self.borrow_mut().input(value)
+    }
+
+    fn deselect(&mut self) {
+        self.borrow_mut().deselect()
+    }
+}
+
+/// Clock polarity and phase
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SpiMode {
+    /// Clock idles high
+    pub cpol: bool,
+    /// Data is sampled on the trailing clock edge instead of the leading one
+    pub cpha: bool,
+}
+
+impl SpiMode {
+    /// Mode number 0 to 3
+    pub fn new(mode: u8) -> Self {
+        Self {
+            cpol: mode & 0b10 != 0,
+            cpha: mode & 0b01 != 0,
+        }
+    }
+}
+
+/// SPI controller in special function registers, with the SPCR, SPSR and SPDR layout of the
+/// AT89S8253 and similar parts
+/// Writing SPDR with SPE and MSTR set exchanges a byte at once and sets SPIF. Writing SPSR clears
+/// the flags written as zero.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpiSfr {
+    /// Register addresses, SPCR, SPSR and SPDR
+    pub spcr: u8,
+    pub spsr: u8,
+    pub spdr: u8,
+    pub control: u8,
+    pub status: u8,
+    pub data: u8,
+}
+
+impl SpiSfr {
+    pub const SPE: u8 = 1 << 6;
+    pub const DORD: u8 = 1 << 5;
+    pub const MSTR: u8 = 1 << 4;
+    pub const CPOL: u8 = 1 << 3;
+    pub const CPHA: u8 = 1 << 2;
+    pub const SPIF: u8 = 1 << 7;
+
+    pub fn new(spcr: u8, spsr: u8, spdr: u8) -> Self {
+        Self {
+            spcr,
+            spsr,
+            spdr,
+            control: 0,
+            status: 0,
+            data: 0,
+        }
+    }
+
+    /// Registers of the Atmel AT89S8253
+    pub fn at89s8253() -> Self {
+        Self::new(0xD5, 0xAA, 0x86)
+    }
+
+    pub fn mode(&self) -> SpiMode {
+        SpiMode {
+            cpol: self.control & Self::CPOL != 0,
+            cpha: self.control & Self::CPHA != 0,
+        }
+    }
+}
+
+/// Master side of an SPI bus
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpiMaster {
+    /// Bit-banged on GPIO pins, each as port and bit
+    Pins {
+        sck: (u8, u8),
+        mosi: (u8, u8),
+        miso: (u8, u8),
+    },
+    /// Controller in special function registers
+    Sfr(SpiSfr),
+}
+
+/// Device with its active low chip select pin
+pub struct SpiTarget {
+    pub cs: (u8, u8),
+    pub device: Box<dyn SpiDevice>,
+}
+
+/// SPI bus with devices selected by GPIO pins
+/// With a bit-banged master, `mode` and `lsb_first` give the clocking the devices expect, with an
+/// SFR controller they are taken from SPCR.
+pub struct SpiBus {
+    pub master: SpiMaster,
+    pub mode: SpiMode,
+    pub lsb_first: bool,
+    pub devices: Vec<SpiTarget>,
+    /// Bytes exchanged, as MOSI and MISO
+    pub log: Vec<(u8, u8)>,
+    selected: Option<usize>,
+    last_sck: bool,
+    /// Bits of the current byte sampled so far
+    bit: u8,
+    shift: u8,
+    /// Byte being shifted out by the selected device
+    out: u8,
+    miso: Option<bool>,
+}
+
+impl SpiBus {
+    pub fn new(master: SpiMaster) -> Self {
+        Self {
+            master,
+            mode: SpiMode::default(),
+            lsb_first: false,
+            devices: Vec::new(),
+            log: Vec::new(),
+            selected: None,
+            last_sck: false,
+            bit: 0,
+            shift: 0,
+            out: 0,
+            miso: None,
+        }
+    }
+
+    /// Add a device selected by pulling pin `cs` low
+    pub fn attach<D: SpiDevice + 'static>(&mut self, cs: (u8, u8), device: D) {
+        self.devices.push(SpiTarget {
+            cs,
+            device: Box::new(device),
+        });
+    }
+
+    /// Pins the bus depends on, to know when to update it
+    pub(crate) fn uses_port(&self, port: u8) -> bool {
+        let pins = match self.master {
+            SpiMaster::Pins { sck, mosi, .. } => vec![sck, mosi],
+            SpiMaster::Sfr(_) => Vec::new(),
+        };
+        pins.iter().chain(self.devices.iter().map(|target| &target.cs)).any(|pin| pin.0 == port)
+    }
+
+    fn mode(&self) -> (SpiMode, bool) {
+        match self.master {
+            SpiMaster::Pins { .. } => (self.mode, self.lsb_first),
+            SpiMaster::Sfr(sfr) => (sfr.mode(), sfr.control & SpiSfr::DORD != 0),
+        }
+    }
+
+    /// Exchange a byte with the selected device, for controllers that bypass the pins
+    /// Returns 0xFF, the idle level of MISO, if no device is selected.
+    pub fn exchange(&mut self, mosi: u8) -> u8 {
+        let lsb_first = self.mode().1;
+        let order = |value: u8| if lsb_first { value.reverse_bits() } else { value };
+        let index = match self.selected {
+            Some(index) => index,
+            None => {
+                self.log.push((order(mosi), 0xFF));
+                return 0xFF;
+            },
+        };
+        let device = &mut self.devices[index].device;
+        let miso = device.output();
+        device.input(order(mosi));
+        self.log.push((order(mosi), miso));
+        order(miso)
+    }
+
+    /// Follow chip select levels, and the clock and data lines of a bit-banged master as
+    /// `(sck, mosi)`. Returns the level to drive on MISO, `None` to release it.
+    pub(crate) fn update(&mut self, cs: &[bool], lines: Option<(bool, bool)>) -> Option<bool> {
+        let selected = cs.iter().position(|&level| ! level);
+        if selected != self.selected {
+            if let Some(index) = self.selected {
+                debug!(" ; spi deselect {}", index);
+                self.devices[index].device.deselect();
+            }
+            self.selected = selected;
+            self.bit = 0;
+            self.shift = 0;
+            self.miso = None;
+            if let Some(index) = selected {
+                debug!(" ; spi select {}", index);
+                self.devices[index].device.select();
+                // With CPHA clear, the first bit is presented before the first clock edge
+                if lines.is_some() && ! self.mode().0.cpha {
+                    self.output();
+                }
+            }
+        }
+
+        if let Some((sck, mosi)) = lines {
+            if sck != self.last_sck && self.selected.is_some() {
+                let (mode, _) = self.mode();
+                let leading = sck != mode.cpol;
+                if leading != mode.cpha {
+                    self.sample(mosi);
+                } else {
+                    self.output();
+                }
+            }
+            self.last_sck = sck;
+        }
+        self.miso
+    }
+
+    fn sample(&mut self, mosi: bool) {
+        let lsb_first = self.mode().1;
+        self.shift = self.shift << 1 | mosi as u8;
+        self.bit += 1;
+        if self.bit == 8 {
+            let value = if lsb_first { self.shift.reverse_bits() } else { self.shift };
+            let index = self.selected.unwrap();
+            self.devices[index].device.input(value);
+            self.log.push((value, self.out));
+            self.bit = 0;
+            self.shift = 0;
+        }
+    }
+
+    fn output(&mut self) {
+        if self.bit == 0 {
+            let index = self.selected.unwrap();
+            self.out = self.devices[index].device.output();
+        }
+        let bit = if self.mode().1 { self.bit } else { 7 - self.bit };
+        self.miso = Some(self.out & (1 << bit) != 0);
+    }
+
+    /// Register read of an SFR controller
+    pub(crate) fn load(&self, addr: Addr) -> Option<u8> {
+        match (self.master, addr) {
+            (SpiMaster::Sfr(sfr), Addr::Reg(i)) if i == sfr.spcr => Some(sfr.control),
+            (SpiMaster::Sfr(sfr), Addr::Reg(i)) if i == sfr.spsr => Some(sfr.status),
+            (SpiMaster::Sfr(sfr), Addr::Reg(i)) if i == sfr.spdr => Some(sfr.data),
+            _ => None,
+        }
+    }
+
+    /// Register write of an SFR controller, returns false if `addr` is not one of its registers
+    pub(crate) fn store(&mut self, addr: Addr, value: u8) -> bool {
+        let mut sfr = match (self.master, addr) {
+            (SpiMaster::Sfr(sfr), Addr::Reg(_)) => sfr,
+            _ => return false,
+        };
+        match addr {
+            Addr::Reg(i) if i == sfr.spcr => sfr.control = value,
+            Addr::Reg(i) if i == sfr.spsr => sfr.status &= value,
+            Addr::Reg(i) if i == sfr.spdr => {
+                let enabled = SpiSfr::SPE | SpiSfr::MSTR;
+                if sfr.control & enabled == enabled {
+                    // Update the mode first, the exchange reads the bit order from it
+                    self.master = SpiMaster::Sfr(sfr);
+                    sfr.data = self.exchange(value);
+                    sfr.status |= SpiSfr::SPIF;
+                } else {
+                    sfr.data = value;
+                }
+            },
+            _ => return false,
+        }
+        self.master = SpiMaster::Sfr(sfr);
+        true
+    }
+}
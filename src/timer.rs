@@ -0,0 +1,67 @@
+/// Control bits of timer 0 or 1 from TMOD
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct TimerMode {
+    /// Counting also requires the INTx pin to be high
+    pub gate: bool,
+    /// Count falling edges on the Tx pin instead of machine cycles
+    pub counter: bool,
+    /// 0 for 13 bits, 1 for 16 bits, 2 for 8 bits with reload from THx, 3 for two 8 bit timers
+    pub mode: u8,
+}
+
+impl TimerMode {
+    pub fn new(tmod: u8, timer: usize) -> Self {
+        let bits = tmod >> (4 * timer);
+        Self {
+            gate: bits & (1 << 3) != 0,
+            counter: bits & (1 << 2) != 0,
+            mode: bits & 0b11,
+        }
+    }
+}
+
+/// Count `counts` on a timer with registers `tl` and `th` in `mode`, returns the number of
+/// overflows. Mode 3 counts TL alone.
+pub(crate) fn advance(mode: u8, tl: &mut u8, th: &mut u8, counts: u64) -> u64 {
+    match mode {
+        0 => {
+            // TH and the low 5 bits of TL
+            let value = (*th as u64) << 5 | (*tl & 0x1F) as u64;
+            let total = value + counts;
+            *th = (total >> 5) as u8;
+            *tl = (*tl & 0xE0) | (total & 0x1F) as u8;
+            total >> 13
+        },
+        1 => {
+            let value = (*th as u64) << 8 | *tl as u64;
+            let total = value + counts;
+            *th = (total >> 8) as u8;
+            *tl = total as u8;
+            total >> 16
+        },
+        2 => reload(tl, *th, counts),
+        _ => reload(tl, 0, counts),
+    }
+}
+
+/// Count `counts` on an 8 bit timer that reloads `value` from `reload` on overflow
+pub(crate) fn reload(value: &mut u8, reload: u8, counts: u64) -> u64 {
+    let total = *value as u64 + counts;
+    if total < 0x100 {
+        *value = total as u8;
+        return 0;
+    }
+    let period = 0x100 - reload as u64;
+    let after = total - 0x100;
+    *value = (reload as u64 + after % period) as u8;
+    1 + after / period
+}
+
+/// Counts until the next overflow of a timer with registers `tl` and `th` in `mode`
+pub(crate) fn until_overflow(mode: u8, tl: u8, th: u8) -> u64 {
+    match mode {
+        0 => 0x2000 - ((th as u64) << 5 | (tl & 0x1F) as u64),
+        1 => 0x10000 - ((th as u64) << 8 | tl as u64),
+        _ => 0x100 - tl as u64,
+    }
+}
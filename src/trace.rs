@@ -0,0 +1,387 @@
+use std::fmt;
+
+use crate::{Addr, Error, Isa, Mcu, Reg};
+
+/// Register compared in a trace
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceReg {
+    A,
+    B,
+    Psw,
+    Sp,
+    Dptr,
+    /// Register in the current bank
+    R(u8),
+}
+
+impl TraceReg {
+    pub const ALL: [TraceReg; 13] = [
+        TraceReg::A,
+        TraceReg::B,
+        TraceReg::Psw,
+        TraceReg::Sp,
+        TraceReg::Dptr,
+        TraceReg::R(0),
+        TraceReg::R(1),
+        TraceReg::R(2),
+        TraceReg::R(3),
+        TraceReg::R(4),
+        TraceReg::R(5),
+        TraceReg::R(6),
+        TraceReg::R(7),
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "a" => TraceReg::A,
+            "b" => TraceReg::B,
+            "psw" => TraceReg::Psw,
+            "sp" => TraceReg::Sp,
+            "dptr" => TraceReg::Dptr,
+            _ => {
+                let n: u8 = name.strip_prefix('r')?.parse().ok()?;
+                if n >= 8 {
+                    return None;
+                }
+                TraceReg::R(n)
+            }
+        })
+    }
+
+    pub fn load<M: Reg>(self, mem: &M) -> u16 {
+        match self {
+            TraceReg::A => mem.load(mem.a()) as u16,
+            TraceReg::B => mem.load(mem.b()) as u16,
+            TraceReg::Psw => mem.load(mem.psw()) as u16,
+            TraceReg::Sp => mem.load(mem.sp()) as u16,
+            TraceReg::Dptr => {
+                (mem.load(mem.dptr(false)) as u16) |
+                (mem.load(mem.dptr(true)) as u16) << 8
+            },
+            TraceReg::R(n) => mem.load(mem.r(n)) as u16,
+        }
+    }
+}
+
+impl fmt::Display for TraceReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceReg::A => write!(f, "a"),
+            TraceReg::B => write!(f, "b"),
+            TraceReg::Psw => write!(f, "psw"),
+            TraceReg::Sp => write!(f, "sp"),
+            TraceReg::Dptr => write!(f, "dptr"),
+            TraceReg::R(n) => write!(f, "r{}", n),
+        }
+    }
+}
+
+/// One executed instruction in a trace
+///
+/// Lines have the address of the instruction followed by register values and memory writes after
+/// it executed, all in hexadecimal:
+///
+/// ```text
+/// 0006 a=00 b=00 psw=00 sp=09 dptr=0000 r0=00 iram[08]=03 iram[09]=00
+/// ```
+///
+/// Only registers that are present are compared. Memory writes use `iram`, `reg` for direct
+/// addresses, or `xram`, and must list every address written other than A, B, PSW, SP and DPTR,
+/// unless the line ends with `...`. Blank lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub regs: Vec<(TraceReg, u16)>,
+    pub writes: Vec<(Addr, u8)>,
+    /// Writes other than those listed are not compared
+    pub partial: bool,
+}
+
+/// Low internal RAM is the same memory for direct and indirect access
+fn normalize(addr: Addr) -> Addr {
+    match addr {
+        Addr::IRam(i) if i < 0x80 => Addr::Reg(i),
+        _ => addr,
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16).map_err(|_| format!("invalid hex '{}'", s))
+}
+
+impl TraceEntry {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let pc = parse_hex(words.next().ok_or("missing pc")?)?;
+        let mut regs = Vec::new();
+        let mut writes = Vec::new();
+        let mut partial = false;
+        for word in words {
+            if partial {
+                return Err(format!("unexpected '{}' after '...'", word));
+            }
+            if word == "..." {
+                partial = true;
+                continue;
+            }
+            let (name, value) = word.split_once('=').ok_or_else(|| format!("invalid field '{}'", word))?;
+            let value = parse_hex(value)?;
+            if let Some(reg) = TraceReg::parse(name) {
+                regs.push((reg, value));
+                continue;
+            }
+
+            let (space, index) = name.strip_suffix(']')
+                .and_then(|name| name.split_once('['))
+                .ok_or_else(|| format!("unknown field '{}'", name))?;
+            let index = parse_hex(index)?;
+            let addr = match space {
+                "iram" if index <= 0xFF => Addr::IRam(index as u8),
+                "reg" if index <= 0xFF => Addr::Reg(index as u8),
+                "xram" => Addr::XRam(index),
+                _ => return Err(format!("invalid address '{}'", name)),
+            };
+            if value > 0xFF {
+                return Err(format!("value of '{}' does not fit in a byte", name));
+            }
+            writes.push((addr, value as u8));
+        }
+        Ok(Self { pc, regs, writes, partial })
+    }
+
+    /// Record the state of `mcu` after executing the instruction at `pc`, which performed `writes`
+    pub fn capture(mcu: &Mcu, pc: u16, writes: &[(Addr, u8)]) -> Self {
+        let regs = TraceReg::ALL.iter().map(|&reg| (reg, reg.load(mcu))).collect();
+
+        let excluded = [mcu.a(), mcu.b(), mcu.psw(), mcu.sp(), mcu.dptr(false), mcu.dptr(true)];
+        let mut final_writes: Vec<(Addr, u8)> = Vec::new();
+        for &(addr, value) in writes.iter() {
+            let addr = normalize(addr);
+            if excluded.contains(&addr) {
+                continue;
+            }
+            match final_writes.iter_mut().find(|(other, _)| *other == addr) {
+                Some(write) => write.1 = value,
+                None => final_writes.push((addr, value)),
+            }
+        }
+
+        Self {
+            pc,
+            regs,
+            writes: final_writes,
+            partial: false,
+        }
+    }
+
+    /// Describe the first difference from `actual`, which must be a complete capture
+    pub fn compare(&self, actual: &TraceEntry) -> Option<String> {
+        if self.pc != actual.pc {
+            return Some(format!("pc is {:04X}, expected {:04X}", actual.pc, self.pc));
+        }
+
+        for &(reg, value) in self.regs.iter() {
+            let actual_value = actual.regs.iter()
+                .find(|(other, _)| *other == reg)
+                .map(|&(_, value)| value);
+            if actual_value != Some(value) {
+                return Some(format!("{} is {:02X}, expected {:02X}", reg, actual_value.unwrap_or(0), value));
+            }
+        }
+
+        for &(addr, value) in self.writes.iter() {
+            match actual.writes.iter().find(|(other, _)| *other == normalize(addr)) {
+                Some(&(_, actual_value)) if actual_value != value => {
+                    return Some(format!("{:X?} written {:02X}, expected {:02X}", addr, actual_value, value));
+                },
+                Some(_) => (),
+                None => return Some(format!("{:X?} not written", addr)),
+            }
+        }
+
+        for &(addr, value) in actual.writes.iter().filter(|_| ! self.partial) {
+            if ! self.writes.iter().any(|&(other, _)| normalize(other) == addr) {
+                return Some(format!("{:X?} unexpectedly written {:02X}", addr, value));
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}", self.pc)?;
+        for &(reg, value) in self.regs.iter() {
+            match reg {
+                TraceReg::Dptr => write!(f, " {}={:04X}", reg, value)?,
+                _ => write!(f, " {}={:02X}", reg, value)?,
+            }
+        }
+        for &(addr, value) in self.writes.iter() {
+            match addr {
+                Addr::Reg(i) => write!(f, " reg[{:02X}]={:02X}", i, value)?,
+                Addr::IRam(i) => write!(f, " iram[{:02X}]={:02X}", i, value)?,
+                Addr::PMem(i) => write!(f, " pmem[{:04X}]={:02X}", i, value)?,
+                Addr::XRam(i) => write!(f, " xram[{:04X}]={:02X}", i, value)?,
+            }
+        }
+        if self.partial {
+            write!(f, " ...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Value of a register in a ucsim dump, written as `NAME= 0x12` or `NAME=0x12`
+fn ucsim_field(words: &[&str], name: &str) -> Option<u16> {
+    let index = words.iter().position(|word| word.starts_with(name))?;
+    let value = match &words[index][name.len()..] {
+        "" => words.get(index + 1)?,
+        value => value,
+    };
+    u16::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
+
+/// Convert the output of the ucsim `s51` simulator into a reference trace
+///
+/// The log is recorded by piping `dr` followed by one `step` for each instruction into `s51`.
+/// Every register dump ends with the disassembly of the next instruction, so the address of each
+/// instruction comes from the previous dump. ucsim does not report memory writes, so these are not
+/// compared, and SP is only compared if the dump includes it.
+pub fn import_ucsim(log: &str) -> Result<String, String> {
+    let mut trace = String::new();
+    let mut pc = None;
+    let mut regs: Vec<(TraceReg, u16)> = Vec::new();
+    let mut bank = false;
+    for (index, line) in log.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+
+        // Strip command prompts like `0> `
+        let mut line = line.trim();
+        while let Some((prompt, rest)) = line.split_once("> ") {
+            if prompt.is_empty() || ! prompt.bytes().all(|b| b.is_ascii_digit()) {
+                break;
+            }
+            line = rest.trim_start();
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        if words.first() == Some(&"R0") {
+            bank = true;
+            continue;
+        }
+        if bank {
+            // Bank address followed by R0 to R7
+            bank = false;
+            if words.len() < 9 {
+                return Err(error("incomplete register bank"));
+            }
+            for (n, word) in words[1..9].iter().enumerate() {
+                let value = u16::from_str_radix(word, 16).map_err(|_| error("invalid register value"))?;
+                regs.push((TraceReg::R(n as u8), value));
+            }
+            continue;
+        }
+
+        let fields = [
+            (TraceReg::A, "ACC="),
+            (TraceReg::B, "B="),
+            (TraceReg::Psw, "PSW="),
+            (TraceReg::Sp, "SP="),
+            (TraceReg::Dptr, "DPTR="),
+        ];
+        for &(reg, name) in fields.iter() {
+            if let Some(value) = ucsim_field(&words, name) {
+                regs.push((reg, value));
+            }
+        }
+
+        // Disassembly has an address of at least four digits and follows a breakpoint marker
+        let address = words.iter()
+            .take(2)
+            .filter_map(|word| word.strip_prefix("0x"))
+            .find(|digits| digits.len() >= 4);
+        let next = match address {
+            Some(digits) => u16::from_str_radix(digits, 16).map_err(|_| error("invalid address"))?,
+            None => continue,
+        };
+        if let Some(pc) = pc {
+            if regs.is_empty() {
+                return Err(error("instruction without register dump"));
+            }
+            regs.sort_by_key(|&(reg, _)| TraceReg::ALL.iter().position(|&other| other == reg));
+            let entry = TraceEntry {
+                pc,
+                regs: std::mem::take(&mut regs),
+                writes: Vec::new(),
+                partial: true,
+            };
+            trace.push_str(&entry.to_string());
+            trace.push('\n');
+        }
+        regs.clear();
+        pc = Some(next);
+    }
+    Ok(trace)
+}
+
+/// First difference between execution and a reference trace
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    /// Number of instructions that matched before the divergence
+    pub index: usize,
+    /// Line in the trace
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+    pub message: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "instruction {} (line {}): {}\n  expected: {}\n  actual:   {}",
+            self.index, self.line, self.message, self.expected, self.actual
+        )
+    }
+}
+
+/// Execute one instruction on `mcu` and capture it
+pub fn trace_step(mcu: &mut Mcu) -> Result<TraceEntry, Error> {
+    let pc = mcu.pc;
+    mcu.writes = Some(Vec::new());
+    let result = mcu.step();
+    let writes = mcu.writes.take().unwrap_or_default();
+    result.map(|()| TraceEntry::capture(mcu, pc, &writes))
+}
+
+/// Execute `mcu` along with a reference trace, returning the number of instructions that matched or
+/// the first divergence. Traces must not contain interrupts.
+pub fn diff(mcu: &mut Mcu, trace: &str) -> Result<usize, Divergence> {
+    let mut index = 0;
+    for (line_index, line) in trace.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let divergence = |message: String, actual: String| Divergence {
+            index,
+            line: line_index + 1,
+            expected: line.to_string(),
+            actual,
+            message,
+        };
+
+        let expected = TraceEntry::parse(line).map_err(|err| divergence(err, String::new()))?;
+        let actual = trace_step(mcu).map_err(|err| divergence(err.to_string(), String::new()))?;
+        if let Some(message) = expected.compare(&actual) {
+            return Err(divergence(message, actual.to_string()));
+        }
+
+        index += 1;
+    }
+    Ok(index)
+}
@@ -0,0 +1,30 @@
+use std::collections::VecDeque;
+
+/// Serial port, transmitting and receiving whole bytes through SBUF
+#[derive(Clone, Debug, Default)]
+pub struct Uart {
+    /// Bytes transmitted by firmware and not yet taken by the host
+    pub tx: Vec<u8>,
+    /// Bytes waiting to be received by firmware
+    pub rx: VecDeque<u8>,
+    /// Receive buffer, read from SBUF
+    pub rbuf: u8,
+    /// Cycle when the next byte can complete reception
+    pub next_rx: u64,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue bytes to be received by firmware
+    pub fn send(&mut self, data: &[u8]) {
+        self.rx.extend(data);
+    }
+
+    /// Remove and return bytes transmitted by firmware
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.tx)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{Addr, Reset};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Variant {
+    /// Intel 8051
+    /// 128 bytes of internal RAM, two timers
+    I8051,
+    /// Intel 8052
+    /// 256 bytes of internal RAM, upper 128 bytes only reachable with indirect access
+    I8052,
+    /// Atmel AT89S52
+    /// 8052 with dual data pointers selected by AUXR1
+    At89s52,
+    /// ITE embedded controller 8032 core
+    /// 8052 with dual data pointers selected by DPS
+    #[default]
+    Ite,
+}
+
+impl Variant {
+    /// Number of bytes of internal RAM
+    pub fn iram_size(&self) -> usize {
+        match self {
+            Variant::I8051 => 128,
+            Variant::I8052 | Variant::At89s52 | Variant::Ite => 256,
+        }
+    }
+
+    /// Oscillator clocks in each machine cycle
+    pub fn clocks_per_cycle(&self) -> u64 {
+        12
+    }
+
+    /// Address of the data pointer select register, if there is more than one data pointer
+    pub fn dps(&self) -> Option<u8> {
+        match self {
+            Variant::I8051 | Variant::I8052 => None,
+            Variant::At89s52 => Some(0xA2),
+            Variant::Ite => Some(0x86),
+        }
+    }
+
+    /// Returns true if the special function register at `addr` is implemented
+    pub fn has_sfr(&self, addr: u8) -> bool {
+        match addr {
+            // P0, SP, DPL, DPH, PCON
+            0x80 ..= 0x83 | 0x87 => true,
+            // TCON, TMOD, TL0, TL1, TH0, TH1
+            0x88 ..= 0x8D => true,
+            // P1, SCON, SBUF, P2, IE, P3, IP, PSW, ACC, B
+            0x90 | 0x98 | 0x99 | 0xA0 | 0xA8 | 0xB0 | 0xB8 | 0xD0 | 0xE0 | 0xF0 => true,
+            // AUXR on the AT89S52, CKCON on ITE
+            0x8E => matches!(self, Variant::At89s52 | Variant::Ite),
+            // WDTRST
+            0xA6 => *self == Variant::At89s52,
+            // WDTCON
+            0xD8 => *self == Variant::Ite,
+            // Second data pointer
+            0x84 ..= 0x85 => self.dps().is_some(),
+            // T2CON, T2MOD, RCAP2L, RCAP2H, TL2, TH2
+            0xC8 ..= 0xCD => *self != Variant::I8051,
+            _ => self.dps() == Some(addr),
+        }
+    }
+
+    /// Returns true if an enabled external interrupt ends power-down mode
+    pub fn power_down_wake(&self) -> bool {
+        match self {
+            Variant::I8051 | Variant::I8052 => false,
+            Variant::At89s52 | Variant::Ite => true,
+        }
+    }
+
+    /// Value of the special function register at `addr` after reset, undefined bits reset to zero
+    /// Returns None for registers that are indeterminate or write-only, which are not written
+    pub fn sfr_reset(&self, addr: u8) -> Option<u8> {
+        match addr {
+            // P0, P1, P2, P3
+            0x80 | 0x90 | 0xA0 | 0xB0 => Some(0xFF),
+            // SP
+            0x81 => Some(0x07),
+            // SBUF, WDTRST
+            0x99 | 0xA6 => None,
+            _ => Some(0x00),
+        }
+    }
+
+    /// Location of reset status flags as address and mask
+    /// These bits are preserved across reset unless updated for the reset source
+    pub fn reset_status(&self) -> Option<(Addr, u8)> {
+        match self {
+            Variant::I8051 | Variant::I8052 => None,
+            // PCON.POF
+            Variant::At89s52 => Some((Addr::Reg(0x87), 1 << 4)),
+            // RSTS.LRS
+            Variant::Ite => Some((Addr::XRam(0x2006), 0b11)),
+        }
+    }
+
+    /// Value of reset status flags after a reset from `source`, or None if they are preserved
+    pub fn reset_status_value(&self, source: Reset) -> Option<u8> {
+        match self {
+            Variant::I8051 | Variant::I8052 => None,
+            // Power off flag is only set by power-on reset
+            Variant::At89s52 => match source {
+                Reset::PowerOn => Some(1 << 4),
+                _ => None,
+            },
+            // Last reset source
+            Variant::Ite => Some(match source {
+                Reset::PowerOn => 0b00,
+                Reset::External => 0b01,
+                Reset::Watchdog => 0b10,
+                Reset::Software => 0b11,
+            }),
+        }
+    }
+}
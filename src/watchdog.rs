@@ -0,0 +1,117 @@
+use crate::Addr;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WatchdogAction {
+    /// Reset the processor with the watchdog reset source
+    Reset,
+    /// Request a low priority interrupt at the provided vector
+    Interrupt(u16),
+    /// Stop the processor and report the expiry to the host
+    Halt,
+}
+
+#[derive(Clone, Debug)]
+pub struct Watchdog {
+    /// Register written to feed the watchdog
+    pub feed: Addr,
+    /// Values that must be written to the feed register in order
+    /// The first complete sequence starts the watchdog
+    pub sequence: Vec<u8>,
+    /// Machine cycles without a feed before the watchdog expires
+    pub timeout: u64,
+    pub action: WatchdogAction,
+    /// Counting is enabled
+    pub running: bool,
+    /// Machine cycles since the last feed
+    pub count: u64,
+    /// Cycle count of the most recent expiry
+    pub expired: Option<u64>,
+    /// Interrupt requested and not yet serviced
+    pub pending: bool,
+    progress: usize,
+}
+
+impl Watchdog {
+    pub fn new(feed: Addr, sequence: Vec<u8>, timeout: u64, action: WatchdogAction) -> Self {
+        Self {
+            feed,
+            sequence,
+            timeout,
+            action,
+            running: false,
+            count: 0,
+            expired: None,
+            pending: false,
+            progress: 0,
+        }
+    }
+
+    /// AT89S52 watchdog, fed by writing 0x1E then 0xE1 to WDTRST
+    /// The 14-bit counter resets the processor after 16384 machine cycles
+    pub fn at89s52() -> Self {
+        Self::new(Addr::Reg(0xA6), vec![0x1E, 0xE1], 16384, WatchdogAction::Reset)
+    }
+
+    /// ITE external timer watchdog, fed by writing 0x5C to EWDKEYR
+    pub fn ite(timeout: u64) -> Self {
+        Self::new(Addr::XRam(0x1F07), vec![0x5C], timeout, WatchdogAction::Reset)
+    }
+
+    /// Stop counting and clear state, called on reset
+    pub fn reset(&mut self) {
+        self.running = false;
+        self.count = 0;
+        self.pending = false;
+        self.progress = 0;
+    }
+
+    /// Observe a write to memory
+    pub fn store(&mut self, addr: Addr, value: u8) {
+        if addr != self.feed {
+            return;
+        }
+
+        if self.sequence.get(self.progress) == Some(&value) {
+            self.progress += 1;
+        } else if self.sequence.first() == Some(&value) {
+            self.progress = 1;
+        } else {
+            self.progress = 0;
+        }
+
+        if self.progress == self.sequence.len() {
+            debug!(" ; watchdog fed");
+            self.running = true;
+            self.count = 0;
+            self.progress = 0;
+        }
+    }
+
+    /// Advance by `cycles`, returns the action to take if the watchdog expired
+    pub fn tick(&mut self, cycles: u64, now: u64) -> Option<WatchdogAction> {
+        if ! self.running {
+            return None;
+        }
+
+        self.count += cycles;
+        if self.count < self.timeout {
+            return None;
+        }
+
+        self.count = 0;
+        self.expired = Some(now);
+        if let WatchdogAction::Interrupt(_) = self.action {
+            self.pending = true;
+        }
+        Some(self.action)
+    }
+
+    /// Machine cycles until the watchdog expires
+    pub fn remaining(&self) -> Option<u64> {
+        if self.running {
+            Some(self.timeout - self.count)
+        } else {
+            None
+        }
+    }
+}
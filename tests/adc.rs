@@ -0,0 +1,138 @@
+//! ADC with host supplied analog levels
+
+mod common;
+
+use area8051::{Adc, AdcMap, Addr, Analog, Budget, Mcu, Mem, PeripheralInterrupt, Variant};
+
+fn mcu(code: &[u8], adc: Adc) -> Mcu {
+    common::setup(Variant::I8052, code, |mcu| mcu.adc = Some(adc))
+}
+
+#[test]
+fn polled_conversion() {
+    let code = [
+        // Power, start, channel 2, then wait for ADC_FLAG
+        0x75, 0xBC, 0x8A, 0xE5, 0xBC, 0x30, 0xE4, 0xFB,
+        0x85, 0xBD, 0x30, 0x85, 0xBE, 0x31, 0x53, 0xBC, 0xEF,
+        0x80, 0xFE,
+    ];
+    let mut adc = Adc::new(AdcMap::stc12());
+    adc.vref = 5.0;
+    adc.set(2, Analog::Constant(3.3));
+    let mut mcu = mcu(&code, adc);
+    mcu.run(Budget::Cycles(100), |mcu| mcu.pc == 0x0008);
+    assert!(mcu.cycles >= 45);
+    mcu.run(Budget::Cycles(100), |_| false);
+
+    assert_eq!(&mcu.iram[0x30..0x32], &[0xA8, 0x03]);
+    assert_eq!(mcu.load(Addr::Reg(0xBC)), 0x82);
+    assert_eq!(mcu.adc.as_ref().unwrap().log, [(2, 675)]);
+}
+
+#[test]
+fn conversion_time_from_clocks() {
+    // 540 clocks at 12 clocks per machine cycle
+    let mut mcu = mcu(&common::IDLE, Adc::new(AdcMap::stc12()));
+    mcu.store(Addr::Reg(0xBC), 0x88);
+    mcu.run(Budget::Cycles(44), |_| false);
+    assert!(!mcu.adc.as_ref().unwrap().done());
+    mcu.run(Budget::Cycles(2), |_| false);
+    assert!(mcu.adc.as_ref().unwrap().done());
+}
+
+#[test]
+fn waveform_sampled_at_start() {
+    let mut adc = Adc::new(AdcMap::c8051f());
+    adc.vref = 1.0;
+    // Ramp of 1 V per millisecond on channel 5
+    adc.set(5, Analog::waveform(|ns| ns as f64 / 1e6));
+    let mut mcu = mcu(&common::IDLE, adc);
+    mcu.store(Addr::Reg(0xBB), 5);
+
+    let mut results = Vec::new();
+    for _ in 0..3 {
+        mcu.run(Budget::Ns(200_000), |_| false);
+        mcu.store(Addr::Reg(0xE8), 0x90);
+        assert_eq!(mcu.load(Addr::Reg(0xE8)), 0x90);
+        mcu.run(Budget::Cycles(3), |_| false);
+        assert_eq!(mcu.load(Addr::Reg(0xE8)), 0xA0);
+        results.push((mcu.load(Addr::Reg(0xBE)) as u16) << 8 | mcu.load(Addr::Reg(0xBD)) as u16);
+        mcu.store(Addr::Reg(0xE8), 0x80);
+    }
+
+    let expected: Vec<u16> = mcu.adc.as_ref().unwrap().log.iter().map(|&(_, code)| code).collect();
+    assert_eq!(results, expected);
+    assert!(results[0] > 190 && results[0] < 220, "{:?}", results);
+    assert!(results[1] > results[0] && results[2] > results[1]);
+
+    // Saturates at full scale
+    mcu.run(Budget::Ns(1_000_000), |_| false);
+    mcu.store(Addr::Reg(0xE8), 0x90);
+    mcu.run(Budget::Cycles(3), |_| false);
+    assert_eq!(mcu.adc.as_ref().unwrap().log.last(), Some(&(5, 0x3FF)));
+}
+
+#[test]
+fn conversion_requires_enable() {
+    let mut mcu = mcu(&common::IDLE, Adc::new(AdcMap::c8051f()));
+    mcu.store(Addr::Reg(0xE8), 0x10);
+    mcu.run(Budget::Cycles(10), |_| false);
+    assert!(mcu.adc.as_ref().unwrap().log.is_empty());
+}
+
+#[test]
+fn done_interrupt() {
+    let mut code = vec![
+        // EA and EADC, start channel 0
+        0x75, 0xA8, 0xA0, 0x75, 0xBC, 0x88, 0x80, 0xFE,
+    ];
+    code.resize(0x2B, 0);
+    // Keep the result and clear ADC_FLAG
+    code.extend_from_slice(&[0x85, 0xBD, 0x30, 0x53, 0xBC, 0xEF, 0x32]);
+    let mut adc = Adc::new(AdcMap::stc12());
+    adc.set(0, Analog::Constant(1.65));
+    let mut mcu = mcu(&code, adc);
+    mcu.run(Budget::Cycles(100), |_| false);
+
+    assert_eq!(mcu.iram[0x30], 0x80);
+    assert_eq!(mcu.load(Addr::Reg(0xBC)), 0x80);
+    assert_eq!(mcu.pc, 0x0006);
+}
+
+#[test]
+fn interrupt_from_map() {
+    let mut code = vec![
+        // EA and the enable bit, start channel 0
+        0x75, 0xA8, 0x00, 0x75, 0xBC, 0x88, 0x80, 0xFE,
+    ];
+    code.resize(0x33, 0);
+    // Count entries in 0x30 and clear ADC_FLAG
+    code.extend_from_slice(&[0x05, 0x30, 0x53, 0xBC, 0xEF, 0x32]);
+    let map = AdcMap {
+        interrupt: Some(PeripheralInterrupt { vector: 0x33, enable: 0xAE, priority: 0xBE }),
+        ..AdcMap::stc12()
+    };
+
+    // IE.6 enables it, EADC of the STC12 map does not
+    for &(ie, count) in [(0xC0, 1), (0xA0, 0)].iter() {
+        code[2] = ie;
+        let mut mcu = mcu(&code, Adc::new(map));
+        mcu.run(Budget::Cycles(100), |_| false);
+        assert_eq!(mcu.iram[0x30], count, "IE 0x{:02X}", ie);
+        assert_eq!(mcu.pc, 0x0006);
+    }
+}
+
+#[test]
+fn done_leaves_timer_2_flag() {
+    let mut mcu = mcu(&common::IDLE, Adc::new(AdcMap::stc12()));
+    // TF2 set by firmware survives a conversion and clearing ADC_FLAG
+    mcu.store(Addr::Reg(0xC8), 0x80);
+    mcu.store(Addr::Reg(0xBC), 0x88);
+    mcu.run(Budget::Cycles(50), |_| false);
+    assert_eq!(mcu.load(Addr::Reg(0xBC)), 0x90);
+    assert_eq!(mcu.load(Addr::Reg(0xC8)) & 0x80, 0x80);
+    mcu.store(Addr::Reg(0xBC), 0x80);
+    mcu.run(Budget::Cycles(2), |_| false);
+    assert_eq!(mcu.load(Addr::Reg(0xC8)) & 0x80, 0x80);
+}
@@ -0,0 +1,121 @@
+//! Command line arguments, coverage and trace output of the emulator binary
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("area8051-cli-{}-{}", std::process::id(), name))
+}
+
+fn area8051(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_area8051")).args(args).output().unwrap()
+}
+
+#[test]
+fn usage_errors() {
+    let cases: &[(&[&str], &str)] = &[
+        (&[], "rom file not provided"),
+        (&["rom.bin", "other.bin"], "unexpected argument 'other.bin'"),
+        (&["--bogus", "1", "rom.bin"], "unknown option '--bogus'"),
+        (&["rom.bin", "--max-cycles"], "missing value for --max-cycles"),
+        (&["--max-cycles", "ten", "rom.bin"], "invalid number 'ten'"),
+        (&["--variant", "z80", "rom.bin"], "unknown variant 'z80'"),
+        (&["--variant", "8051", "--ec", "it8587", "rom.bin"], "--ec requires the ite variant"),
+        (&["--host", "127.0.0.1:8051", "rom.bin"], "--host requires --ec"),
+        (&["--baud", "0", "rom.bin"], "invalid baud rate '0'"),
+        (&["--baud", "9600", "rom.bin"], "--baud requires --uart pty"),
+        (&["--exit-addr", "xram:0", "rom.bin"], "invalid exit address 'xram:0'"),
+        (
+            &["--exit-addr", "sfr:0xD0", "rom.bin"],
+            "exit address sfr:0xD0 overlaps an implemented register",
+        ),
+        (&["--script=a.script", "--diff=a.trace", "rom.bin"], "--script and --diff cannot be combined"),
+    ];
+    for &(args, message) in cases.iter() {
+        let output = area8051(args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(stderr.starts_with(&format!("area8051: {}\n", message)), "{:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn help() {
+    let output = area8051(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: area8051"));
+}
+
+#[test]
+fn idle_and_power_down() {
+    // orl pcon, #1 for idle and #2 for power-down, with nothing enabled to wake up
+    for &(pcon, name) in [(0x01, "idle"), (0x02, "power-down")].iter() {
+        let rom_path = temp_path(&format!("{}.bin", name));
+        fs::write(&rom_path, [0x43, 0x87, pcon]).unwrap();
+        let rom = rom_path.to_str().unwrap();
+
+        // Stopping before a limit is distinct from success
+        let output = area8051(&[rom, "--exit-addr", "none", "--max-cycles", "1000"]);
+        assert_eq!(output.status.code(), Some(5), "{}", name);
+        let output = area8051(&[rom, "--exit-addr", "none", "--timeout", "1ms"]);
+        assert_eq!(output.status.code(), Some(5), "{}", name);
+        let output = area8051(&[rom, "--exit-addr", "none"]);
+        assert_eq!(output.status.code(), Some(0), "{}", name);
+        fs::remove_file(&rom_path).ok();
+    }
+}
+
+#[test]
+fn coverage_and_trace_count_fetched_instructions() {
+    // Idle between timer 0 interrupts, the handler is a reti
+    let mut rom = vec![0; 0x3D];
+    rom[0x00..0x03].copy_from_slice(&[0x02, 0x00, 0x30]);
+    rom[0x0B] = 0x32;
+    rom[0x30..0x3D].copy_from_slice(&[
+        0x75, 0x89, 0x02, // mov tmod, #2
+        0x75, 0xA8, 0x82, // mov ie, #0x82
+        0xD2, 0x8C,       // setb tr0
+        0x43, 0x87, 0x01, // orl pcon, #1
+        0x80, 0xFB,       // sjmp 0x38
+    ]);
+    let rom_path = temp_path("idle.bin");
+    let coverage_path = temp_path("idle.coverage");
+    let trace_path = temp_path("idle.trace");
+    fs::write(&rom_path, rom).unwrap();
+
+    let output = area8051(&[
+        rom_path.to_str().unwrap(),
+        "--exit-addr", "none",
+        "--max-cycles", "3000",
+        "--coverage", coverage_path.to_str().unwrap(),
+        "--trace", trace_path.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(4));
+
+    let coverage: BTreeMap<u16, u64> = fs::read_to_string(&coverage_path).unwrap()
+        .lines()
+        .map(|line| {
+            let (pc, count) = line.split_once(' ').unwrap();
+            (u16::from_str_radix(pc, 16).unwrap(), count.parse().unwrap())
+        })
+        .collect();
+    let trace = fs::read_to_string(&trace_path).unwrap();
+    for path in [rom_path, coverage_path, trace_path].iter() {
+        fs::remove_file(path).ok();
+    }
+
+    let interrupts = coverage[&0x000B];
+    assert!(interrupts >= 10, "{:?}", coverage);
+    let addresses: Vec<u16> = coverage.keys().cloned().collect();
+    assert_eq!(addresses, [0x0000, 0x000B, 0x0030, 0x0033, 0x0036, 0x0038, 0x003B]);
+    assert_eq!(coverage[&0x003B], interrupts);
+    assert_eq!(coverage[&0x0038], interrupts + 1);
+
+    // One line for each fetched instruction, each starting with its address
+    assert_eq!(trace.lines().count() as u64, coverage.values().sum::<u64>());
+    assert!(trace.lines().all(|line| coverage.contains_key(&u16::from_str_radix(&line[..4], 16).unwrap())));
+}
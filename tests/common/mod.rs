@@ -0,0 +1,38 @@
+//! Setup shared by the integration tests
+
+#![allow(dead_code)]
+
+use area8051::{Ite, IteChip, Mcu, Variant};
+
+/// Firmware that only loops, sjmp $
+pub const IDLE: [u8; 2] = [0x80, 0xFE];
+
+/// Processor of the default variant with `code` at address 0, after power-on reset
+pub fn mcu(code: &[u8]) -> Mcu {
+    variant(Variant::default(), code)
+}
+
+/// Processor of `variant` with `code` at address 0, after power-on reset
+pub fn variant(variant: Variant, code: &[u8]) -> Mcu {
+    setup(variant, code, |_| ())
+}
+
+/// Processor of the default variant that only loops, after power-on reset
+pub fn idle() -> Mcu {
+    mcu(&IDLE)
+}
+
+/// Processor of `variant` with `code` at address 0, prepared by `setup` before power-on reset so
+/// attached peripherals see the reset
+pub fn setup<F: FnOnce(&mut Mcu)>(variant: Variant, code: &[u8], setup: F) -> Mcu {
+    let mut mcu = Mcu::with_variant(variant, code.to_vec().into_boxed_slice());
+    setup(&mut mcu);
+    mcu.power_on(None);
+    mcu
+}
+
+/// Processor of the ITE variant with the peripherals of `chip` and `code` at address 0, after
+/// power-on reset
+pub fn ite(chip: IteChip, code: &[u8]) -> Mcu {
+    setup(Variant::Ite, code, |mcu| mcu.ite = Some(Ite::new(chip)))
+}
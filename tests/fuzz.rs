@@ -0,0 +1,238 @@
+//! Randomized tests of the instruction core
+//!
+//! Random machine states and instruction streams are executed with `Isa::step`, checking that it
+//! never panics and that invariants hold. Each case is seeded from `FUZZ_SEED` and its index so
+//! failures can be reproduced, `FUZZ_CASES` sets the number of cases for longer runs:
+//!
+//! ```text
+//! FUZZ_CASES=1000000 cargo test --release --test fuzz
+//! ```
+//!
+//! Coverage guided fuzzing with `cargo fuzz` is in the `fuzz` directory.
+
+use area8051::{Addr, Error, Isa, Mcu, Mem, Reg, Variant};
+use std::env;
+
+const SP: Addr = Addr::Reg(0x81);
+const IE: Addr = Addr::Reg(0xA8);
+const PCON: Addr = Addr::Reg(0x87);
+const SBUF: u8 = 0x99;
+
+const VARIANTS: [Variant; 4] = [Variant::I8051, Variant::I8052, Variant::At89s52, Variant::Ite];
+
+/// Instruction lengths in bytes, 0 for the reserved opcode
+const LENGTHS: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    1, 2, 3, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0
+    3, 2, 3, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 1
+    3, 2, 1, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 2
+    3, 2, 1, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 3
+    2, 2, 2, 3, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4
+    2, 2, 2, 3, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5
+    2, 2, 2, 3, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6
+    2, 2, 2, 1, 2, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // 7
+    2, 2, 2, 1, 1, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // 8
+    3, 2, 2, 1, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9
+    2, 2, 2, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, // A
+    2, 2, 2, 1, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, // B
+    2, 2, 2, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // C
+    2, 2, 2, 1, 1, 3, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, // D
+    1, 2, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // E
+    1, 2, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // F
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Flow {
+    /// Falls through to the next instruction
+    Next,
+    /// Falls through or branches relative to the next instruction by the last byte
+    Conditional,
+    /// Jumps, calls and returns
+    Jump,
+}
+
+fn flow(op: u8) -> Flow {
+    match op {
+        _ if op & 0x1F == 0x01 || op & 0x1F == 0x11 => Flow::Jump,
+        0x02 | 0x12 | 0x22 | 0x32 | 0x73 | 0x80 => Flow::Jump,
+        0x10 | 0x20 | 0x30 | 0x40 | 0x50 | 0x60 | 0x70 => Flow::Conditional,
+        0xB4 ..= 0xBF | 0xD5 | 0xD8 ..= 0xDF => Flow::Conditional,
+        _ => Flow::Next,
+    }
+}
+
+/// xorshift64 generator
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64, case: u64) -> Self {
+        let mut rng = Rng((seed ^ case.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1);
+        // Mix nearby seeds apart
+        for _ in 0..4 {
+            rng.next();
+        }
+        rng
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.next() as u8
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+/// Run `check` for each case, collecting failures with the seed needed to reproduce them
+fn cases<F: Fn(&mut Rng) -> Result<(), String>>(default: u64, check: F) {
+    let seed = env_u64("FUZZ_SEED", 0x8051);
+    let count = env_u64("FUZZ_CASES", default);
+
+    let failures: Vec<String> = (0..count)
+        .filter_map(|case| {
+            check(&mut Rng::new(seed, case))
+                .err()
+                .map(|err| format!("case {} (FUZZ_SEED={}): {}", case, seed, err))
+        })
+        .take(10)
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Processor with random internal RAM and special function registers, interrupts and low power
+/// modes are disabled so that the next step executes an instruction
+fn random_mcu(rng: &mut Rng, pmem: Vec<u8>) -> Mcu {
+    let variant = VARIANTS[rng.below(VARIANTS.len())];
+    let mut mcu = Mcu::with_variant(variant, pmem.into_boxed_slice());
+    mcu.power_on(None);
+
+    for byte in mcu.iram.iter_mut() {
+        *byte = rng.byte();
+    }
+    for addr in 0x80..=0xFF {
+        if variant.has_sfr(addr) && addr != SBUF {
+            mcu.store(Addr::Reg(addr), rng.byte());
+        }
+    }
+    mcu.store(IE, 0);
+    let pcon = mcu.load(PCON);
+    mcu.store(PCON, pcon & !0b11);
+    mcu.uart.take();
+
+    mcu.pc = rng.next() as u16;
+    mcu
+}
+
+/// Registers must be in the bank selected by PSW
+fn check_bank(mcu: &Mcu) -> Result<(), String> {
+    let bank = (mcu.load(mcu.psw()) >> 3) & 3;
+    for n in 0..8 {
+        match mcu.r(n) {
+            Addr::Reg(i) if i / 8 == bank && i % 8 == n => (),
+            addr => return Err(format!("r{} is {:X?} in bank {}", n, addr, bank)),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn random_streams() {
+    cases(200, |rng| {
+        let len = rng.below(256);
+        let pmem: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+        let mut mcu = random_mcu(rng, pmem);
+
+        for _ in 0..1000 {
+            let pc = mcu.pc;
+            match mcu.step() {
+                Ok(()) => (),
+                Err(Error::UnknownOpcode { pc: error_pc, op: 0xA5 }) if error_pc == pc => break,
+                Err(err) => return Err(format!("{} at 0x{:04X}", err, pc)),
+            }
+            check_bank(&mcu).map_err(|err| format!("{} after 0x{:04X}", err, pc))?;
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn decoded_length() {
+    cases(2000, |rng| {
+        let op = rng.byte();
+        let bytes = [op, rng.byte(), rng.byte()];
+        let len = LENGTHS[op as usize] as u16;
+
+        let mut mcu = random_mcu(rng, vec![0; 0x10000]);
+        let pc = mcu.pc;
+        for (i, &byte) in bytes.iter().enumerate() {
+            mcu.pmem[pc.wrapping_add(i as u16) as usize] = byte;
+        }
+
+        let result = mcu.step();
+        if op == 0xA5 {
+            return match result {
+                Err(Error::UnknownOpcode { .. }) if mcu.pc == pc => Ok(()),
+                _ => Err(format!("reserved opcode at 0x{:04X} returned {:?}", pc, result)),
+            };
+        }
+        result.map_err(|err| err.to_string())?;
+
+        let next = pc.wrapping_add(len);
+        let taken = next.wrapping_add(bytes[len as usize - 1] as i8 as u16);
+        let ok = match flow(op) {
+            Flow::Next => mcu.pc == next,
+            Flow::Conditional => mcu.pc == next || mcu.pc == taken,
+            Flow::Jump => true,
+        };
+        if ! ok {
+            return Err(format!(
+                "opcode 0x{:02X} at 0x{:04X} moved pc to 0x{:04X}, expected 0x{:04X}",
+                op, pc, mcu.pc, next
+            ));
+        }
+        check_bank(&mcu)
+    });
+}
+
+#[test]
+fn push_pop_round_trip() {
+    cases(2000, |rng| {
+        let src = rng.below(0x80) as u8;
+        let dst = rng.below(0x80) as u8;
+        let mut mcu = random_mcu(rng, vec![0xC0, src, 0xD0, dst]);
+        mcu.pc = 0;
+
+        // Keep the pushed byte within internal RAM
+        let sp = rng.below(mcu.iram.len() - 1) as u8;
+        mcu.store(SP, sp);
+        let value = mcu.load(Addr::Reg(src));
+
+        mcu.step().map_err(|err| err.to_string())?;
+        if mcu.load(SP) != sp.wrapping_add(1) || mcu.load(Addr::IRam(sp.wrapping_add(1))) != value {
+            return Err(format!("push 0x{:02X} with sp 0x{:02X} did not store 0x{:02X}", src, sp, value));
+        }
+
+        mcu.step().map_err(|err| err.to_string())?;
+        if mcu.load(SP) != sp {
+            return Err(format!("sp 0x{:02X} after pop, expected 0x{:02X}", mcu.load(SP), sp));
+        }
+        if mcu.load(Addr::Reg(dst)) != value {
+            return Err(format!(
+                "push 0x{:02X}, pop 0x{:02X} with sp 0x{:02X} moved 0x{:02X}, expected 0x{:02X}",
+                src, dst, sp, mcu.load(Addr::Reg(dst)), value
+            ));
+        }
+        Ok(())
+    });
+}
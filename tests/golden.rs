@@ -0,0 +1,121 @@
+//! Golden-output tests
+//!
+//! Each `tests/golden/<name>.bin` ROM is run until it exits through the semihosting device, with
+//! its command register at XRAM 0xFFFF, or the cycle budget is exhausted. UART output is compared
+//! to `<name>.uart` and final memory to `<name>.mem`. Set `UPDATE_GOLDEN=1` to rewrite the
+//! expectations from the current results. The ROMs are built from `tests/<name>.a51` with
+//! `make golden`.
+
+mod common;
+
+use area8051::{Addr, Budget, Exit, Mem, Semihost, StopReason, Variant};
+use std::{env, fmt::Write, fs, path::Path};
+
+const CYCLE_BUDGET: u64 = 100_000_000;
+
+struct Outcome {
+    uart: Vec<u8>,
+    mem: String,
+}
+
+fn dump(out: &mut String, name: &str, bytes: &[u8], skip_zero: bool) {
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        if skip_zero && chunk.iter().all(|&b| b == 0) {
+            continue;
+        }
+        write!(out, "{} {:04X}:", name, i * 16).unwrap();
+        for b in chunk {
+            write!(out, " {:02X}", b).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+}
+
+fn run(pmem: &[u8]) -> Result<Outcome, String> {
+    let mut mcu = common::setup(Variant::default(), pmem, |mcu| {
+        mcu.semihost = Some(Semihost::new(Addr::XRam(0xFFFE)).unwrap());
+    });
+
+    let mut uart = Vec::new();
+    let reason = mcu.run(Budget::Cycles(CYCLE_BUDGET), |mcu| {
+        uart.extend(mcu.uart.take());
+        false
+    });
+    match reason {
+        StopReason::Exit(Exit::Status(0)) => (),
+        StopReason::Budget => {
+            return Err(format!("cycle budget of {} exhausted at pc 0x{:04X}", CYCLE_BUDGET, mcu.pc));
+        },
+        reason => return Err(format!("stopped at pc 0x{:04X}: {:?}", mcu.pc, reason)),
+    }
+
+    let mut mem = String::new();
+    writeln!(mem, "pc {:04X}", mcu.pc).unwrap();
+    writeln!(mem, "cycles {}", mcu.cycles).unwrap();
+    let sfr: Vec<u8> = (0x80..=0xFF).map(|i| mcu.load(Addr::Reg(i))).collect();
+    dump(&mut mem, "iram", &mcu.iram, false);
+    dump(&mut mem, "sfr", &sfr, false);
+    dump(&mut mem, "xram", &mcu.xram, true);
+
+    Ok(Outcome { uart, mem })
+}
+
+/// Describe the first difference between two texts
+fn diff(expected: &str, actual: &str) -> Option<String> {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (e, a) if e == a => continue,
+            (e, a) => return Some(format!(
+                "line {}\n  expected: {}\n  actual:   {}",
+                line,
+                e.unwrap_or("<end>"),
+                a.unwrap_or("<end>")
+            )),
+        }
+    }
+    unreachable!()
+}
+
+fn check(path: &Path, actual: &str, update: bool) -> Option<String> {
+    if update {
+        fs::write(path, actual).unwrap();
+        return None;
+    }
+    match fs::read_to_string(path) {
+        Ok(expected) => diff(&expected, actual).map(|diff| format!("{}: {}", path.display(), diff)),
+        Err(err) => Some(format!("{}: {}", path.display(), err)),
+    }
+}
+
+#[test]
+fn golden() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+
+    let mut roms: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bin"))
+        .collect();
+    roms.sort();
+    assert!(! roms.is_empty(), "no ROMs in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for rom in roms.iter() {
+        let outcome = match run(&fs::read(rom).unwrap()) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                failures.push(format!("{}: {}", rom.display(), err));
+                continue;
+            }
+        };
+
+        let uart = String::from_utf8_lossy(&outcome.uart);
+        failures.extend(check(&rom.with_extension("uart"), &uart, update));
+        failures.extend(check(&rom.with_extension("mem"), &outcome.mem, update));
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
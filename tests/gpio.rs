@@ -0,0 +1,67 @@
+//! Port latches and externally driven pins
+
+mod common;
+
+use area8051::{Addr, Budget, Level, Mem, PortChange};
+
+const P1: Addr = Addr::Reg(0x90);
+
+#[test]
+fn driven_pins() {
+    // mov p1, #0x0F; mov 0x30, p1; mov 0x31, p1
+    let mut mcu = common::mcu(&[0x75, 0x90, 0x0F, 0x85, 0x90, 0x30, 0x85, 0x90, 0x31]);
+    mcu.run(Budget::Instructions(1), |_| false);
+    mcu.drive_pin(1, 0, Some(false));
+    // Driving high cannot override a cleared latch
+    mcu.drive_pin(1, 7, Some(true));
+    mcu.run(Budget::Instructions(1), |_| false);
+    mcu.drive_pin(1, 0, None);
+    mcu.run(Budget::Instructions(1), |_| false);
+
+    assert_eq!(mcu.iram[0x30], 0x0E);
+    assert_eq!(mcu.iram[0x31], 0x0F);
+    assert_eq!(mcu.gpio.ports[1].latch, 0x0F);
+    assert!(! mcu.pin(1, 7));
+    assert_eq!(mcu.gpio.ports[1].output(0), Level::High);
+    assert_eq!(mcu.gpio.ports[1].output(7), Level::Low);
+    // P0 has no pull-ups
+    assert_eq!(mcu.gpio.ports[0].output(0), Level::Float);
+    assert_eq!(mcu.load(P1), 0x0F);
+}
+
+#[test]
+fn history() {
+    let mut mcu = common::mcu(&[0x75, 0x90, 0xF0, 0x80, 0xFE]);
+    mcu.gpio.history.clear();
+    mcu.pulse_pin(10, 1, 4, 5);
+    mcu.run(Budget::Cycles(20), |_| false);
+    // Driving an already low pin changes nothing
+    mcu.drive_pin(1, 0, Some(false));
+
+    // Firmware writes are recorded at the start of the instruction and scheduled pin changes at
+    // the end of the instruction reaching their cycle
+    let change = |cycle, latch, pins| PortChange { cycle, port: 1, latch, pins };
+    assert_eq!(mcu.gpio.history, [
+        change(0, 0xF0, 0xF0),
+        change(10, 0xF0, 0xE0),
+        change(16, 0xF0, 0xF0),
+    ]);
+}
+
+#[test]
+fn read_modify_write_uses_latch() {
+    // clr p1.1; orl p1, #0; mov a, p1; inc p1; jbc p1.1, $+3
+    let code = [0xC2, 0x91, 0x43, 0x90, 0x00, 0xE5, 0x90, 0x05, 0x90, 0x10, 0x91, 0x00];
+    let mut mcu = common::mcu(&code);
+    mcu.drive_pin(1, 0, Some(false));
+    let mut latches = Vec::new();
+    for _ in 0..5 {
+        mcu.run(Budget::Instructions(1), |_| false);
+        latches.push(mcu.gpio.ports[1].latch);
+    }
+
+    // The low pin is not written back into the latch
+    assert_eq!(latches, [0xFD, 0xFD, 0xFD, 0xFE, 0xFC]);
+    assert_eq!(mcu.load(Addr::Reg(0xE0)), 0xFC);
+    assert_eq!(mcu.load(P1), 0xFC);
+}
@@ -0,0 +1,114 @@
+//! Host I/O port interface of the embedded controller
+
+mod common;
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use area8051::{
+    Addr, Host, HostError, HostInterface, HostServer, IteChip, Mcu, Mem, PortPair,
+};
+
+/// ACPI embedded controller firmware on PM channel 1, with EC RAM in IRAM
+fn acpi() -> Mcu {
+    let mut code = vec![0; 0x4C];
+    code[..0x2D].copy_from_slice(&[
+        // Wait for IBF, keeping the status in r7 and the input in r6
+        0x90, 0x15, 0x00, 0xE0, 0x30, 0xE1, 0xF9, 0xFF,
+        0x90, 0x15, 0x04, 0xE0, 0xFE,
+        // Data without a command is dropped
+        0xEF, 0x30, 0xE3, 0xEF,
+        // RD_EC
+        0xEE, 0xB4, 0x80, 0x0B,
+        0x12, 0x00, 0x40, 0xF8, 0xE6, 0x90, 0x15, 0x01, 0xF0, 0x80, 0xE0,
+        // WR_EC
+        0xB4, 0x81, 0xDD,
+        0x12, 0x00, 0x40, 0xF8, 0x12, 0x00, 0x40, 0xF6, 0x80, 0xD3,
+    ]);
+    // Wait for IBF and read the input
+    code[0x40..].copy_from_slice(&[0x90, 0x15, 0x00, 0xE0, 0x30, 0xE1, 0xF9, 0x90, 0x15, 0x04, 0xE0, 0x22]);
+    common::ite(IteChip::It8587, &code)
+}
+
+#[test]
+fn port_handshakes() {
+    let mut mcu = common::ite(IteChip::It8587, &common::IDLE);
+    let host = Host { timeout: 100 };
+
+    host.command(&mut mcu, PortPair::KBC, 0xAA).unwrap();
+    assert_eq!(mcu.io_read(0x64), HostInterface::IBF | HostInterface::CMD);
+    // Firmware never reads the input buffer
+    assert_eq!(host.write(&mut mcu, PortPair::KBC, 0x55), Err(HostError::Timeout { port: 0x64 }));
+    assert_eq!(host.read(&mut mcu, PortPair::PMC), Err(HostError::Timeout { port: 0x66 }));
+
+    // The data port keeps reading the last output byte
+    mcu.store(Addr::XRam(0x1501), 0x12);
+    assert_eq!(mcu.io_read(0x66) & HostInterface::OBF, HostInterface::OBF);
+    assert_eq!(mcu.io_read(0x62), 0x12);
+    assert_eq!(mcu.io_read(0x66) & HostInterface::OBF, 0);
+    assert_eq!(mcu.io_read(0x62), 0x12);
+    // Ports that are not decoded
+    assert_eq!(mcu.io_read(0x80), 0xFF);
+    mcu.io_write(0x80, 0x01);
+}
+
+#[test]
+fn acpi_ec_commands() {
+    let mut mcu = acpi();
+    let host = Host::new();
+    mcu.iram[0x40] = 0x5A;
+
+    assert_eq!(host.ec_read(&mut mcu, 0x40), Ok(0x5A));
+    host.ec_write(&mut mcu, 0x41, 0xC3).unwrap();
+    assert_eq!(host.ec_read(&mut mcu, 0x41), Ok(0xC3));
+    assert_eq!(mcu.iram[0x41], 0xC3);
+    assert_eq!(mcu.io_read(0x66) & (HostInterface::IBF | HostInterface::OBF), 0);
+}
+
+#[test]
+fn h2ram_window() {
+    let mut mcu = common::ite(IteChip::It8587, &common::IDLE);
+    // Window 0 of 32 bytes at XRAM 0x0100
+    mcu.xram[0x105A] = 0x01;
+    mcu.xram[0x105B] = 0x10;
+    mcu.xram[0x105D] = 0x01;
+
+    mcu.io_write(0x0E05, 0xAB);
+    assert_eq!(mcu.xram[0x0105], 0xAB);
+    mcu.xram[0x011F] = 0xCD;
+    assert_eq!(mcu.io_read(0x0E1F), 0xCD);
+    assert_eq!(mcu.io_read(0x0E20), 0xFF);
+    // Window 1 is disabled
+    assert_eq!(mcu.io_read(0x0C00), 0xFF);
+}
+
+#[test]
+fn socket_protocol() {
+    let mut mcu = common::ite(IteChip::It8587, &common::IDLE);
+    let mut server = HostServer::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    client.write_all(b"outb 0x66 0x80\ninb 0x66\ninb 102\nbogus\n").unwrap();
+    client.set_nonblocking(true).unwrap();
+
+    let mut response = String::new();
+    for _ in 0..1000 {
+        server.poll(&mut mcu).unwrap();
+        let mut data = [0; 256];
+        match client.read(&mut data) {
+            Ok(count) => response.push_str(std::str::from_utf8(&data[..count]).unwrap()),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+            Err(err) => panic!("{}", err),
+        }
+        if response.lines().count() == 4 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(response, "ok\n0x0A\n0x0A\nerror invalid command 'bogus'\n");
+    assert_eq!(mcu.ite.as_mut().unwrap().pmc[0].status(), HostInterface::IBF | HostInterface::CMD);
+}
@@ -0,0 +1,264 @@
+//! I2C bus decoding and virtual devices
+
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use area8051::{sbs, Budget, Eeprom, I2cEvent, I2cOp, I2cSfr, Mcu, ScriptedTarget, SmartBattery, Variant};
+
+/// SCL on P1.0 and SDA on P1.1
+const SCL: (u8, u8) = (1, 0);
+const SDA: (u8, u8) = (1, 1);
+
+fn run(mcu: &mut Mcu) {
+    mcu.run(Budget::Cycles(20_000), |mcu| ! mcu.i2c[0].master_busy());
+}
+
+#[test]
+fn bit_banged_firmware() {
+    let mut code = vec![0; 0x44];
+    // START, then send 0xA0, 0x07 and 0x5A keeping each ACK in bits 0 to 2, then STOP
+    code[..0x21].copy_from_slice(&[
+        0xC2, 0x91, 0xC2, 0x90,
+        0x74, 0xA0, 0x12, 0x00, 0x30, 0x92, 0x00,
+        0x74, 0x07, 0x12, 0x00, 0x30, 0x92, 0x01,
+        0x74, 0x5A, 0x12, 0x00, 0x30, 0x92, 0x02,
+        0xC2, 0x91, 0xD2, 0x90, 0xD2, 0x91,
+        0x80, 0xFE,
+    ]);
+    // Shift out A MSB first, then clock in the ACK bit to C
+    code[0x30..].copy_from_slice(&[
+        0x7F, 0x08, 0x33, 0x92, 0x91, 0xD2, 0x90, 0xC2, 0x90, 0xDF, 0xF7,
+        0xD2, 0x91, 0xD2, 0x90, 0xA2, 0x91, 0xC2, 0x90, 0x22,
+    ]);
+
+    let eeprom = Rc::new(RefCell::new(Eeprom::new(256)));
+    let mut mcu = common::mcu(&code);
+    let bus = mcu.attach_i2c(SCL, SDA);
+    mcu.i2c[bus].attach(eeprom.clone());
+    mcu.run(Budget::Cycles(1000), |_| false);
+
+    assert_eq!(mcu.iram[0x20] & 0b111, 0);
+    assert_eq!(eeprom.borrow().data[0x07], 0x5A);
+    assert_eq!(mcu.i2c[bus].log, vec![
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x50, read: false, ack: true },
+        I2cEvent::Write { value: 0x07, ack: true },
+        I2cEvent::Write { value: 0x5A, ack: true },
+        I2cEvent::Stop,
+    ]);
+}
+
+#[test]
+fn eeprom_page_write_and_random_read() {
+    let eeprom = Rc::new(RefCell::new(Eeprom::new(4096)));
+    let mut mcu = common::idle();
+    let bus = mcu.attach_i2c(SCL, SDA);
+    mcu.i2c[bus].attach(eeprom.clone());
+
+    // Three bytes from the end of a page wrap to its start
+    mcu.i2c_master(bus, 5, &[
+        I2cOp::Start, I2cOp::Write(0xA0), I2cOp::Write(0x01), I2cOp::Write(0x1E),
+        I2cOp::Write(1), I2cOp::Write(2), I2cOp::Write(3), I2cOp::Stop,
+    ]);
+    mcu.i2c_master(bus, 5, &[
+        I2cOp::Start, I2cOp::Write(0xA0), I2cOp::Write(0x01), I2cOp::Write(0x1F),
+        I2cOp::Start, I2cOp::Write(0xA1), I2cOp::Read { ack: true }, I2cOp::Read { ack: false }, I2cOp::Stop,
+    ]);
+    run(&mut mcu);
+
+    let data = &eeprom.borrow().data;
+    assert_eq!(&data[0x11E..0x120], &[1, 2]);
+    assert_eq!(data[0x100], 3);
+    let log = &mcu.i2c[bus].log;
+    assert_eq!(&log[log.len() - 3..], &[
+        I2cEvent::Read { value: 2, ack: true },
+        I2cEvent::Read { value: 0xFF, ack: false },
+        I2cEvent::Stop,
+    ]);
+}
+
+#[test]
+fn missing_device_is_not_acknowledged() {
+    let mut mcu = common::idle();
+    let bus = mcu.attach_i2c(SCL, SDA);
+    mcu.i2c[bus].attach(Eeprom::new(256));
+    mcu.i2c_master(bus, 5, &[I2cOp::Start, I2cOp::Write(0x90), I2cOp::Stop]);
+    run(&mut mcu);
+
+    assert_eq!(mcu.i2c[bus].log, vec![
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x48, read: false, ack: false },
+        I2cEvent::Stop,
+    ]);
+}
+
+#[test]
+fn smart_battery_words_and_blocks() {
+    let mut mcu = common::idle();
+    let bus = mcu.attach_i2c(SCL, SDA);
+    mcu.i2c[bus].attach(SmartBattery::new());
+
+    // Read word voltage
+    mcu.i2c_master(bus, 5, &[
+        I2cOp::Start, I2cOp::Write(0x16), I2cOp::Write(sbs::VOLTAGE),
+        I2cOp::Start, I2cOp::Write(0x17), I2cOp::Read { ack: true }, I2cOp::Read { ack: false }, I2cOp::Stop,
+    ]);
+    run(&mut mcu);
+    let reads: Vec<u8> = mcu.i2c[bus].log.iter().filter_map(|event| match event {
+        I2cEvent::Read { value, .. } => Some(*value),
+        _ => None,
+    }).collect();
+    assert_eq!(reads, 11_800u16.to_le_bytes());
+
+    // Block read through the byte-level interface
+    let name = mcu.i2c[bus].transfer(0x0B, &[sbs::DEVICE_CHEMISTRY], 5);
+    assert_eq!(name.as_deref(), Some(&b"\x04LION"[..]));
+    // Unsupported command
+    assert_eq!(mcu.i2c[bus].transfer(0x0B, &[0x7F], 2), None);
+}
+
+#[test]
+fn smart_battery_write_word() {
+    let battery = Rc::new(RefCell::new(SmartBattery::new()));
+    let mut mcu = common::idle();
+    let bus = mcu.attach_i2c(SCL, SDA);
+    mcu.i2c[bus].attach(battery.clone());
+    mcu.i2c_master(bus, 5, &[
+        I2cOp::Start, I2cOp::Write(0x16), I2cOp::Write(sbs::REMAINING_CAPACITY_ALARM),
+        I2cOp::Write(0x34), I2cOp::Write(0x12), I2cOp::Stop,
+    ]);
+    run(&mut mcu);
+
+    assert_eq!(battery.borrow().word(sbs::REMAINING_CAPACITY_ALARM), Some(0x1234));
+}
+
+#[test]
+fn scripted_target_stretches_clock() {
+    let target = Rc::new(RefCell::new(ScriptedTarget::new(0x20)));
+    target.borrow_mut().responses.extend(&[0xC3]);
+    target.borrow_mut().stretch = 100;
+    let mut mcu = common::idle();
+    let bus = mcu.attach_i2c(SCL, SDA);
+    mcu.i2c[bus].attach(target.clone());
+
+    mcu.i2c_master(bus, 5, &[I2cOp::Start, I2cOp::Write(0x41), I2cOp::Read { ack: false }, I2cOp::Stop]);
+    run(&mut mcu);
+
+    assert_eq!(target.borrow().log, vec![
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x20, read: true, ack: true },
+        I2cEvent::Read { value: 0xC3, ack: true },
+        I2cEvent::Stop,
+    ]);
+    assert_eq!(mcu.i2c[bus].log[2], I2cEvent::Read { value: 0xC3, ack: false });
+    // Two stretched acknowledges, on top of 9 clocks per byte of 10 cycles each
+    assert!(mcu.cycles >= 200 + 180, "finished after {} cycles", mcu.cycles);
+}
+
+/// SIO1 registers of the 80C552
+const S1CON: u8 = 0xD8;
+const S1STA: u8 = 0xD9;
+const S1DAT: u8 = 0xDA;
+
+/// Subroutine at 0x80 that writes A to S1CON, waits for SI and returns S1STA in A
+const STEP: [u8; 8] = [0xF5, S1CON, 0x30, 0xDB, 0xFD, 0xE5, S1STA, 0x22];
+
+/// mov S1DAT, #data if given, then mov a, #control; lcall 0x80; mov status, a
+fn step(code: &mut Vec<u8>, data: Option<u8>, control: u8, status: u8) {
+    if let Some(data) = data {
+        code.extend_from_slice(&[0x75, S1DAT, data]);
+    }
+    code.extend_from_slice(&[0x74, control, 0x12, 0x00, 0x80, 0xF5, status]);
+}
+
+/// mov S1CON, #(ENS1 | STO), which does not set SI
+fn stop(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[0x75, S1CON, I2cSfr::ENS1 | I2cSfr::STO]);
+}
+
+fn sfr_firmware(mut code: Vec<u8>) -> Mcu {
+    code.resize(0x80, 0);
+    code.extend_from_slice(&STEP);
+    common::variant(Variant::I8052, &code)
+}
+
+#[test]
+fn sfr_controller() {
+    const START: u8 = I2cSfr::ENS1 | I2cSfr::STA;
+    const CONTINUE: u8 = I2cSfr::ENS1;
+
+    // Write 0x5A to EEPROM address 0x10, then read it back after a repeated START
+    let mut code = Vec::new();
+    step(&mut code, None, START, 0x30);
+    step(&mut code, Some(0xA0), CONTINUE, 0x31);
+    step(&mut code, Some(0x10), CONTINUE, 0x32);
+    step(&mut code, Some(0x5A), CONTINUE, 0x33);
+    stop(&mut code);
+    step(&mut code, None, START, 0x34);
+    step(&mut code, Some(0xA0), CONTINUE, 0x35);
+    step(&mut code, Some(0x10), CONTINUE, 0x36);
+    step(&mut code, None, START, 0x37);
+    step(&mut code, Some(0xA1), CONTINUE, 0x38);
+    // Receive without AA, so the byte is not acknowledged
+    step(&mut code, None, CONTINUE, 0x39);
+    // mov 0x3A, S1DAT
+    code.extend_from_slice(&[0x85, S1DAT, 0x3A]);
+    stop(&mut code);
+    code.extend_from_slice(&common::IDLE);
+
+    let eeprom = Rc::new(RefCell::new(Eeprom::new(256)));
+    let mut mcu = sfr_firmware(code);
+    let bus = mcu.attach_i2c_sfr(I2cSfr::p80c552());
+    mcu.i2c[bus].attach(eeprom.clone());
+    mcu.run(Budget::Cycles(1000), |_| false);
+
+    assert_eq!(eeprom.borrow().data[0x10], 0x5A);
+    assert_eq!(&mcu.iram[0x30..0x3B], &[0x08, 0x18, 0x28, 0x28, 0x08, 0x18, 0x28, 0x10, 0x40, 0x58, 0x5A]);
+    assert_eq!(mcu.i2c[bus].sfr.unwrap().status, I2cSfr::IDLE);
+    assert_eq!(mcu.i2c[bus].log, vec![
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x50, read: false, ack: true },
+        I2cEvent::Write { value: 0x10, ack: true },
+        I2cEvent::Write { value: 0x5A, ack: true },
+        I2cEvent::Stop,
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x50, read: false, ack: true },
+        I2cEvent::Write { value: 0x10, ack: true },
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x50, read: true, ack: true },
+        I2cEvent::Read { value: 0x5A, ack: false },
+        I2cEvent::Stop,
+    ]);
+}
+
+#[test]
+fn sfr_controller_missing_device_and_disabled() {
+    // Address 0x51 is not acknowledged, and neither is the byte sent after it
+    let mut code = Vec::new();
+    step(&mut code, None, I2cSfr::ENS1 | I2cSfr::STA, 0x30);
+    step(&mut code, Some(0xA2), I2cSfr::ENS1, 0x31);
+    step(&mut code, Some(0x00), I2cSfr::ENS1, 0x32);
+    stop(&mut code);
+    code.extend_from_slice(&common::IDLE);
+
+    let mut mcu = sfr_firmware(code);
+    let bus = mcu.attach_i2c_sfr(I2cSfr::p80c552());
+    mcu.i2c[bus].attach(Eeprom::new(256));
+    mcu.run(Budget::Cycles(1000), |_| false);
+    assert_eq!(&mcu.iram[0x30..0x33], &[0x08, 0x20, 0x30]);
+    assert_eq!(mcu.i2c[bus].log, vec![
+        I2cEvent::Start,
+        I2cEvent::Address { address: 0x51, read: false, ack: false },
+        I2cEvent::Write { value: 0x00, ack: false },
+        I2cEvent::Stop,
+    ]);
+
+    // Without ENS1, STA does nothing and SI is never set
+    let mut mcu = sfr_firmware(vec![0x75, S1CON, I2cSfr::STA, 0x80, 0xFE]);
+    let bus = mcu.attach_i2c_sfr(I2cSfr::p80c552());
+    mcu.run(Budget::Cycles(100), |_| false);
+    let sfr = mcu.i2c[bus].sfr.unwrap();
+    assert_eq!((sfr.control, sfr.status), (I2cSfr::STA, I2cSfr::IDLE));
+    assert!(mcu.i2c[bus].log.is_empty());
+}
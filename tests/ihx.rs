@@ -0,0 +1,27 @@
+//! Intel HEX loading
+
+use area8051::parse_ihx;
+
+#[test]
+fn records() {
+    let source = "\
+:0300000002001EDD
+:02001000740179
+:00000001FF
+";
+    let image = parse_ihx(source, 0).unwrap();
+    assert_eq!(image.len(), 0x12);
+    assert_eq!(&image[..3], &[0x02, 0x00, 0x1E]);
+    assert!(image[3..0x10].iter().all(|&b| b == 0xFF));
+    assert_eq!(&image[0x10..], &[0x74, 0x01]);
+
+    let image = parse_ihx(source, 0x100).unwrap();
+    assert_eq!(&image[0x100..0x103], &[0x02, 0x00, 0x1E]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(parse_ihx(":0300000002001EDC\n", 0).unwrap_err(), "line 1: invalid checksum");
+    assert_eq!(parse_ihx("0300000002001EDB\n", 0).unwrap_err(), "line 1: missing start code");
+    assert_eq!(parse_ihx(":02FFFF00000000\n", 0x10).unwrap_err(), "line 1: data beyond 64 KiB");
+}
@@ -0,0 +1,120 @@
+//! Interrupt priority and nesting
+
+mod common;
+
+use area8051::{Budget, Mcu};
+
+/// Program with each part placed at its address
+fn rom(parts: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut code = vec![0; 0x80];
+    for &(addr, bytes) in parts {
+        code[addr as usize..addr as usize + bytes.len()].copy_from_slice(bytes);
+    }
+    code
+}
+
+/// Vector addresses in the order they were entered, with IE0 and TF0 requested together
+fn order(ip: u8) -> Vec<u8> {
+    let code = rom(&[
+        (0x00, &[0x02, 0x00, 0x30]),
+        // Each handler appends its vector at @r0
+        (0x03, &[0x76, 0x03, 0x08, 0x32]),
+        (0x0B, &[0x76, 0x0B, 0x08, 0x32]),
+        // EA, ET0 and EX0, edge triggered INT0, then request both
+        (0x30, &[0x78, 0x40, 0x75, 0xA8, 0x83, 0x75, 0xB8, ip, 0x75, 0x88, 0x23, 0x80, 0xFE]),
+    ]);
+    let mut mcu = common::mcu(&code);
+    mcu.run(Budget::Cycles(100), |_| false);
+    let count = mcu.iram[0] - 0x40;
+    mcu.iram[0x40..0x40 + count as usize].to_vec()
+}
+
+#[test]
+fn polling_order_and_priority() {
+    // Same priority, INT0 is polled first
+    assert_eq!(order(0x00), [0x03, 0x0B]);
+    // Timer 0 at high priority is serviced first
+    assert_eq!(order(0x02), [0x0B, 0x03]);
+}
+
+/// Counts in 0x31 of INT0 handler entries, before and after the timer 0 handler requests INT0
+fn nesting(ip: u8) -> (u8, u8) {
+    let code = rom(&[
+        (0x00, &[0x02, 0x00, 0x30]),
+        (0x03, &[0x05, 0x31, 0x32]),
+        (0x0B, &[0x02, 0x00, 0x50]),
+        (0x30, &[0x75, 0xA8, 0x83, 0x75, 0xB8, ip, 0x75, 0x88, 0x01, 0xD2, 0x8D, 0x80, 0xFE]),
+        // Request INT0, then record whether it already ran
+        (0x50, &[0xD2, 0x89, 0x00, 0x85, 0x31, 0x32, 0x32]),
+    ]);
+    let mut mcu = common::mcu(&code);
+    mcu.run(Budget::Cycles(100), |_| false);
+    (mcu.iram[0x32], mcu.iram[0x31])
+}
+
+#[test]
+fn high_priority_preempts() {
+    // INT0 at high priority runs inside the timer 0 handler
+    assert_eq!(nesting(0x01), (1, 1));
+    // At the same priority it waits for reti
+    assert_eq!(nesting(0x00), (0, 1));
+    // A high priority handler is not preempted by a low priority request
+    assert_eq!(nesting(0x02), (0, 1));
+}
+
+/// Counts INT0 handler entries in 0x31 and INT1 handler entries in 0x33
+/// INT0 is edge triggered and INT1 level triggered.
+fn external() -> Mcu {
+    let code = rom(&[
+        (0x00, &[0x02, 0x00, 0x30]),
+        (0x03, &[0x05, 0x31, 0x32]),
+        (0x13, &[0x05, 0x33, 0x32]),
+        (0x30, &[0x75, 0xA8, 0x85, 0x75, 0x88, 0x01, 0x80, 0xFE]),
+    ]);
+    common::mcu(&code)
+}
+
+#[test]
+fn edge_triggered() {
+    let mut mcu = external();
+    // A short pulse is latched in IE0
+    mcu.pulse_pin(20, 3, 2, 1);
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.iram[0x31], 1);
+    assert_eq!(mcu.sfr[0x88 - 0x80] & 0x02, 0);
+
+    // Holding the pin low requests once
+    mcu.drive_pin(3, 2, Some(false));
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.iram[0x31], 2);
+    mcu.drive_pin(3, 2, None);
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.iram[0x31], 2);
+}
+
+#[test]
+fn level_triggered() {
+    let mut mcu = external();
+    // A pulse that ends before it is sampled is missed
+    mcu.schedule_at(20, |mcu| {
+        mcu.drive_pin(3, 3, Some(false));
+        mcu.drive_pin(3, 3, None);
+    });
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.iram[0x33], 0);
+
+    // Requests continue while the pin is low, with one instruction between handlers
+    mcu.drive_pin(3, 3, Some(false));
+    mcu.run(Budget::Cycles(100), |_| false);
+    let count = mcu.iram[0x33];
+    assert!(count > 5, "{} requests", count);
+    assert_ne!(mcu.sfr[0x88 - 0x80] & 0x08, 0);
+
+    // IE1 follows the pin
+    mcu.drive_pin(3, 3, None);
+    mcu.run(Budget::Cycles(10), |_| false);
+    assert_eq!(mcu.sfr[0x88 - 0x80] & 0x08, 0);
+    let count = mcu.iram[0x33];
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.iram[0x33], count);
+}
@@ -0,0 +1,397 @@
+//! Instruction conformance vectors
+//!
+//! Each vector executes one instruction from address 0 on a freshly reset processor. Registers and
+//! memory listed in `expect` must have the given values, everything else must be unchanged. PSW is
+//! compared without the parity flag, which is instead checked against the accumulator.
+
+use area8051::{Addr, Error, Isa, Mcu, Mem};
+
+const A: Addr = Addr::Reg(0xE0);
+const B: Addr = Addr::Reg(0xF0);
+const PSW: Addr = Addr::Reg(0xD0);
+const SP: Addr = Addr::Reg(0x81);
+const DPL: Addr = Addr::Reg(0x82);
+const DPH: Addr = Addr::Reg(0x83);
+const P2: Addr = Addr::Reg(0xA0);
+
+const CY: u8 = 1 << 7;
+const AC: u8 = 1 << 6;
+const OV: u8 = 1 << 2;
+
+/// Register in bank 0
+fn r(n: u8) -> Addr {
+    Addr::Reg(n)
+}
+
+struct Vector {
+    name: String,
+    code: Vec<u8>,
+    rom: Vec<(u16, u8)>,
+    init: Vec<(Addr, u8)>,
+    expect: Vec<(Addr, u8)>,
+    psw: Option<u8>,
+    pc: Option<u16>,
+    cycles: u64,
+}
+
+fn v<S: Into<String>>(name: S, code: &[u8]) -> Vector {
+    Vector {
+        name: name.into(),
+        code: code.to_vec(),
+        rom: Vec::new(),
+        init: Vec::new(),
+        expect: Vec::new(),
+        psw: None,
+        pc: None,
+        cycles: 1,
+    }
+}
+
+impl Vector {
+    fn init(mut self, addr: Addr, value: u8) -> Self {
+        self.init.push((addr, value));
+        self
+    }
+
+    fn rom(mut self, addr: u16, value: u8) -> Self {
+        self.rom.push((addr, value));
+        self
+    }
+
+    fn expect(mut self, addr: Addr, value: u8) -> Self {
+        self.expect.push((addr, value));
+        self
+    }
+
+    /// Expected PSW without the parity flag, defaults to the initial PSW
+    fn psw(mut self, psw: u8) -> Self {
+        self.psw = Some(psw);
+        self
+    }
+
+    /// Expected PC, defaults to the address after the instruction
+    fn pc(mut self, pc: u16) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    fn cycles(mut self, cycles: u64) -> Self {
+        self.cycles = cycles;
+        self
+    }
+}
+
+/// Low internal RAM is the same memory for direct and indirect access
+fn normalize(addr: Addr) -> Addr {
+    match addr {
+        Addr::IRam(i) if i < 0x80 => Addr::Reg(i),
+        _ => addr,
+    }
+}
+
+fn snapshot(mcu: &Mcu) -> Vec<(Addr, u8)> {
+    let mut addrs: Vec<Addr> = (0x00..=0xFF).map(Addr::Reg).collect();
+    addrs.extend((0x80..=0xFF).map(Addr::IRam));
+    let mut values: Vec<(Addr, u8)> = addrs.into_iter()
+        .filter(|&addr| addr != PSW)
+        .map(|addr| (addr, mcu.load(addr)))
+        .collect();
+    // Only nonzero external RAM, everything else is known to be cleared by power-on
+    values.extend(
+        mcu.xram.iter().enumerate()
+            .filter(|(_, &value)| value != 0)
+            .map(|(i, &value)| (Addr::XRam(i as u16), value))
+    );
+    values
+}
+
+fn run(vector: &Vector) -> Result<(), String> {
+    let mut pmem = vec![0; 0x10000];
+    pmem[..vector.code.len()].copy_from_slice(&vector.code);
+    for &(addr, value) in vector.rom.iter() {
+        pmem[addr as usize] = value;
+    }
+
+    let mut mcu = Mcu::new(pmem.into_boxed_slice());
+    mcu.power_on(None);
+    for &(addr, value) in vector.init.iter() {
+        mcu.store(addr, value);
+    }
+
+    let psw = vector.psw.unwrap_or_else(|| mcu.load(PSW));
+    let before = snapshot(&mcu);
+    let cycles = mcu.cycles;
+
+    if let Err(err) = mcu.step() {
+        return Err(format!("{}: {}", vector.name, err));
+    }
+
+    let mut errors = Vec::new();
+
+    let pc = vector.pc.unwrap_or(vector.code.len() as u16);
+    if mcu.pc != pc {
+        errors.push(format!("pc 0x{:04X} != 0x{:04X}", mcu.pc, pc));
+    }
+
+    if mcu.cycles - cycles != vector.cycles {
+        errors.push(format!("cycles {} != {}", mcu.cycles - cycles, vector.cycles));
+    }
+
+    let actual_psw = mcu.load(PSW);
+    if actual_psw & !1 != psw & !1 {
+        errors.push(format!("psw 0x{:02X} != 0x{:02X}", actual_psw & !1, psw & !1));
+    }
+    let parity = mcu.load(A).count_ones() as u8 & 1;
+    if actual_psw & 1 != parity {
+        errors.push(format!("parity {} does not match a", actual_psw & 1));
+    }
+
+    for &(addr, value) in vector.expect.iter() {
+        let actual = mcu.load(addr);
+        if actual != value {
+            errors.push(format!("{:X?} 0x{:02X} != 0x{:02X}", addr, actual, value));
+        }
+    }
+
+    let after = snapshot(&mcu);
+    let expected = |addr: Addr| vector.expect.iter().any(|&(expect, _)| normalize(expect) == normalize(addr));
+    for &(addr, old) in before.iter() {
+        let new = mcu.load(addr);
+        if ! expected(addr) && new != old {
+            errors.push(format!("{:X?} changed from 0x{:02X} to 0x{:02X}", addr, old, new));
+        }
+    }
+    for &(addr, new) in after.iter() {
+        if ! expected(addr) && ! before.iter().any(|&(old, _)| old == addr) {
+            errors.push(format!("{:X?} changed from 0x00 to 0x{:02X}", addr, new));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{}: {}", vector.name, errors.join(", ")))
+    }
+}
+
+fn vectors() -> Vec<Vector> {
+    let mut vectors = vec![
+        v("nop", &[0x00]),
+        v("ljmp", &[0x02, 0x12, 0x34]).pc(0x1234).cycles(2),
+        v("rr a", &[0x03]).init(A, 0x81).expect(A, 0xC0),
+        v("inc a wraps, flags unchanged", &[0x04]).init(A, 0xFF).init(PSW, CY).expect(A, 0x00),
+        v("inc direct", &[0x05, 0x30]).init(r(0x30), 0x7F).expect(r(0x30), 0x80),
+        v("jbc taken", &[0x10, 0x00, 0x05]).init(r(0x20), 0x01).expect(r(0x20), 0x00).pc(0x08).cycles(2),
+        v("jbc not taken", &[0x10, 0x01, 0x05]).init(r(0x20), 0x01).cycles(2),
+        v("lcall", &[0x12, 0x12, 0x34])
+            .expect(SP, 0x09).expect(r(0x08), 0x03).expect(r(0x09), 0x00)
+            .pc(0x1234).cycles(2),
+        v("rrc a", &[0x13]).init(A, 0x01).expect(A, 0x00).psw(CY),
+        v("rrc a carry in", &[0x13]).init(A, 0x02).init(PSW, CY).expect(A, 0x81).psw(0),
+        v("dec a wraps", &[0x14]).init(A, 0x00).expect(A, 0xFF),
+        v("dec direct", &[0x15, 0x30]).init(r(0x30), 0x80).expect(r(0x30), 0x7F),
+        v("jb taken", &[0x20, 0x00, 0xFD]).init(r(0x20), 0x01).pc(0x00).cycles(2),
+        v("jb not taken", &[0x20, 0x00, 0xFD]).cycles(2),
+        v("ret", &[0x22])
+            .init(SP, 0x09).init(r(0x08), 0x34).init(r(0x09), 0x12)
+            .expect(SP, 0x07).pc(0x1234).cycles(2),
+        v("rl a", &[0x23]).init(A, 0x81).expect(A, 0x03),
+        v("add a, #data signed overflow", &[0x24, 0x01]).init(A, 0x7F).expect(A, 0x80).psw(AC | OV),
+        v("add a, #data carry", &[0x24, 0x01]).init(A, 0xFF).expect(A, 0x00).psw(CY | AC),
+        v("add a, #data carry and overflow", &[0x24, 0x80]).init(A, 0x80).expect(A, 0x00).psw(CY | OV),
+        v("add a, #data ignores carry", &[0x24, 0x01]).init(A, 0x01).init(PSW, CY).expect(A, 0x02).psw(0),
+        v("add a, direct", &[0x25, 0x30]).init(A, 0x10).init(r(0x30), 0x22).expect(A, 0x32).psw(0),
+        v("jnb taken", &[0x30, 0x00, 0x10]).pc(0x13).cycles(2),
+        v("jnb not taken", &[0x30, 0x00, 0x10]).init(r(0x20), 0x01).cycles(2),
+        v("reti", &[0x32])
+            .init(SP, 0x09).init(r(0x08), 0x34).init(r(0x09), 0x12)
+            .expect(SP, 0x07).pc(0x1234).cycles(2),
+        v("rlc a", &[0x33]).init(A, 0x80).init(PSW, CY).expect(A, 0x01).psw(CY),
+        v("addc a, #data nibble carry", &[0x34, 0x00]).init(A, 0x0F).init(PSW, CY).expect(A, 0x10).psw(AC),
+        v("addc a, #data carry from 0xFF", &[0x34, 0xFF]).init(A, 0x00).init(PSW, CY).expect(A, 0x00).psw(CY | AC),
+        v("addc a, #data overflow from carry", &[0x34, 0x00]).init(A, 0x7F).init(PSW, CY).expect(A, 0x80).psw(AC | OV),
+        v("addc a, direct", &[0x35, 0x30]).init(A, 0x10).init(r(0x30), 0x22).expect(A, 0x32).psw(0),
+        v("jc taken", &[0x40, 0x02]).init(PSW, CY).pc(0x04).cycles(2),
+        v("jc not taken", &[0x40, 0x02]).cycles(2),
+        v("orl direct, a", &[0x42, 0x30]).init(A, 0xF0).init(r(0x30), 0x0F).expect(r(0x30), 0xFF),
+        v("orl direct, #data", &[0x43, 0x30, 0x81]).init(r(0x30), 0x18).expect(r(0x30), 0x99).cycles(2),
+        v("orl a, #data", &[0x44, 0x0F]).init(A, 0x30).expect(A, 0x3F),
+        v("orl a, direct", &[0x45, 0x30]).init(A, 0x30).init(r(0x30), 0x01).expect(A, 0x31),
+        v("jnc taken", &[0x50, 0x80]).pc(0xFF82).cycles(2),
+        v("jnc not taken", &[0x50, 0x80]).init(PSW, CY).cycles(2),
+        v("anl direct, a", &[0x52, 0x30]).init(A, 0xF0).init(r(0x30), 0x3C).expect(r(0x30), 0x30),
+        v("anl direct, #data", &[0x53, 0x30, 0x0F]).init(r(0x30), 0x3C).expect(r(0x30), 0x0C).cycles(2),
+        v("anl a, #data", &[0x54, 0x0F]).init(A, 0x3C).expect(A, 0x0C),
+        v("anl a, direct", &[0x55, 0x30]).init(A, 0x3C).init(r(0x30), 0xF0).expect(A, 0x30),
+        v("jz taken", &[0x60, 0x10]).pc(0x12).cycles(2),
+        v("jz not taken", &[0x60, 0x10]).init(A, 0x01).cycles(2),
+        v("xrl direct, a", &[0x62, 0x30]).init(A, 0xFF).init(r(0x30), 0x0F).expect(r(0x30), 0xF0),
+        v("xrl direct, #data", &[0x63, 0x30, 0xFF]).init(r(0x30), 0x0F).expect(r(0x30), 0xF0).cycles(2),
+        v("xrl a, #data", &[0x64, 0xFF]).init(A, 0x0F).expect(A, 0xF0),
+        v("xrl a, direct", &[0x65, 0x30]).init(A, 0x0F).init(r(0x30), 0x01).expect(A, 0x0E),
+        v("jnz taken", &[0x70, 0x10]).init(A, 0x01).pc(0x12).cycles(2),
+        v("jnz not taken", &[0x70, 0x10]).cycles(2),
+        v("orl c, bit", &[0x72, 0x00]).init(r(0x20), 0x01).psw(CY).cycles(2),
+        v("orl c, bit clear", &[0x72, 0x00]).cycles(2),
+        v("jmp @a+dptr", &[0x73]).init(A, 0x10).init(DPH, 0x10).pc(0x1010).cycles(2),
+        v("mov a, #data", &[0x74, 0x55]).expect(A, 0x55),
+        v("mov a, #data odd parity", &[0x74, 0x07]).expect(A, 0x07),
+        v("mov direct, #data", &[0x75, 0x30, 0xAA]).expect(r(0x30), 0xAA).cycles(2),
+        v("mov direct, #data to port", &[0x75, 0x90, 0x5A]).expect(Addr::Reg(0x90), 0x5A).cycles(2),
+        v("sjmp backwards", &[0x80, 0xFE]).pc(0x00).cycles(2),
+        v("anl c, bit", &[0x82, 0x00]).init(PSW, CY).psw(0).cycles(2),
+        v("anl c, bit set", &[0x82, 0x00]).init(PSW, CY).init(r(0x20), 0x01).psw(CY).cycles(2),
+        v("movc a, @a+pc", &[0x83]).init(A, 0x02).rom(0x0003, 0x5A).expect(A, 0x5A).cycles(2),
+        v("div ab", &[0x84]).init(A, 251).init(B, 18).init(PSW, CY | AC)
+            .expect(A, 13).expect(B, 17).psw(AC).cycles(4),
+        v("div ab by zero", &[0x84]).init(A, 0x12).init(PSW, CY).psw(OV).cycles(4),
+        v("mov direct, direct", &[0x85, 0x31, 0x30]).init(r(0x31), 0x66).expect(r(0x30), 0x66).cycles(2),
+        v("mov dptr, #data", &[0x90, 0x12, 0x34]).expect(DPH, 0x12).expect(DPL, 0x34).cycles(2),
+        v("mov bit, c", &[0x92, 0x08]).init(PSW, CY).expect(r(0x21), 0x01).cycles(2),
+        v("mov bit, c clear", &[0x92, 0x08]).init(r(0x21), 0xFF).expect(r(0x21), 0xFE).cycles(2),
+        v("movc a, @a+dptr", &[0x93]).init(A, 0x01).init(DPH, 0x01).rom(0x0101, 0xA5).expect(A, 0xA5).cycles(2),
+        v("subb a, #data borrow", &[0x94, 0x01]).init(A, 0x00).expect(A, 0xFF).psw(CY | AC),
+        v("subb a, #data signed overflow", &[0x94, 0x01]).init(A, 0x80).expect(A, 0x7F).psw(AC | OV),
+        v("subb a, #data nibble borrow from carry", &[0x94, 0x0F]).init(A, 0x10).init(PSW, CY).expect(A, 0x00).psw(AC),
+        v("subb a, #data borrow from carry", &[0x94, 0xFF]).init(A, 0x00).init(PSW, CY).expect(A, 0x00).psw(CY | AC),
+        v("subb a, direct", &[0x95, 0x30]).init(A, 0x32).init(r(0x30), 0x22).expect(A, 0x10).psw(0),
+        v("orl c, /bit", &[0xA0, 0x00]).psw(CY).cycles(2),
+        v("mov c, bit", &[0xA2, 0x00]).init(r(0x20), 0x01).psw(CY),
+        v("mov c, bit clear", &[0xA2, 0x00]).init(PSW, CY).psw(0),
+        v("inc dptr", &[0xA3]).init(DPL, 0xFF).expect(DPL, 0x00).expect(DPH, 0x01).cycles(2),
+        v("mul ab overflow", &[0xA4]).init(A, 0x80).init(B, 0x02).init(PSW, CY | AC)
+            .expect(A, 0x00).expect(B, 0x01).psw(AC | OV).cycles(4),
+        v("mul ab", &[0xA4]).init(A, 0x10).init(B, 0x0F).init(PSW, OV).expect(A, 0xF0).expect(B, 0x00).psw(0).cycles(4),
+        v("anl c, /bit", &[0xB0, 0x00]).init(PSW, CY).init(r(0x20), 0x01).psw(0).cycles(2),
+        v("cpl bit", &[0xB2, 0x00]).init(r(0x20), 0x01).expect(r(0x20), 0x00),
+        v("cpl bit in sfr", &[0xB2, 0xF7]).expect(B, 0x80),
+        v("cpl c", &[0xB3]).psw(CY),
+        v("cjne a, #data less", &[0xB4, 0x20, 0x10]).init(A, 0x10).psw(CY).pc(0x13).cycles(2),
+        v("cjne a, #data greater", &[0xB4, 0x20, 0x10]).init(A, 0x30).init(PSW, CY).psw(0).pc(0x13).cycles(2),
+        v("cjne a, #data equal", &[0xB4, 0x20, 0x10]).init(A, 0x20).init(PSW, CY).psw(0).cycles(2),
+        v("cjne a, direct", &[0xB5, 0x30, 0x10]).init(A, 0x20).init(r(0x30), 0x21).psw(CY).pc(0x13).cycles(2),
+        v("push", &[0xC0, 0x30]).init(r(0x30), 0x99).expect(SP, 0x08).expect(r(0x08), 0x99).cycles(2),
+        v("push to upper internal ram", &[0xC0, 0x30]).init(SP, 0x7F).init(r(0x30), 0x99)
+            .expect(SP, 0x80).expect(Addr::IRam(0x80), 0x99).cycles(2),
+        v("clr bit", &[0xC2, 0x00]).init(r(0x20), 0xFF).expect(r(0x20), 0xFE),
+        v("clr c", &[0xC3]).init(PSW, CY | AC).psw(AC),
+        v("swap a", &[0xC4]).init(A, 0x12).expect(A, 0x21),
+        v("xch a, direct", &[0xC5, 0x30]).init(A, 0x01).init(r(0x30), 0x02).expect(A, 0x02).expect(r(0x30), 0x01),
+        v("pop", &[0xD0, 0x30]).init(SP, 0x08).init(r(0x08), 0x55).expect(SP, 0x07).expect(r(0x30), 0x55).cycles(2),
+        v("setb bit", &[0xD2, 0x00]).expect(r(0x20), 0x01),
+        v("setb c", &[0xD3]).psw(CY),
+        v("da a after add", &[0xD4]).init(A, 0x81).init(PSW, AC).expect(A, 0x87),
+        v("da a carry out", &[0xD4]).init(A, 0x9A).expect(A, 0x00).psw(CY),
+        v("da a keeps carry", &[0xD4]).init(A, 0x12).init(PSW, CY).expect(A, 0x72),
+        v("da a no adjust", &[0xD4]).init(A, 0x45).expect(A, 0x45),
+        v("djnz direct taken", &[0xD5, 0x30, 0xFD]).init(r(0x30), 0x02).expect(r(0x30), 0x01).pc(0x00).cycles(2),
+        v("djnz direct not taken", &[0xD5, 0x30, 0xFD]).init(r(0x30), 0x01).expect(r(0x30), 0x00).cycles(2),
+        v("movx a, @dptr", &[0xE0]).init(DPH, 0x12).init(DPL, 0x34).init(Addr::XRam(0x1234), 0x77)
+            .expect(A, 0x77).cycles(2),
+        v("clr a", &[0xE4]).init(A, 0xFF).expect(A, 0x00),
+        v("mov a, direct", &[0xE5, 0x30]).init(r(0x30), 0x42).expect(A, 0x42),
+        v("mov a, sfr", &[0xE5, 0xF0]).init(B, 0x42).expect(A, 0x42),
+        v("movx @dptr, a", &[0xF0]).init(DPH, 0x12).init(DPL, 0x34).init(A, 0x77)
+            .expect(Addr::XRam(0x1234), 0x77).cycles(2),
+        v("cpl a", &[0xF4]).init(A, 0x0F).expect(A, 0xF0),
+        v("mov direct, a", &[0xF5, 0x30]).init(A, 0x42).expect(r(0x30), 0x42),
+        v("mov r0 in bank 1", &[0x78, 0x11]).init(Addr::Reg(0xD0), 0x08).expect(r(0x08), 0x11),
+        v("mov r7 in bank 3", &[0x7F, 0x11]).init(Addr::Reg(0xD0), 0x18).expect(r(0x1F), 0x11),
+    ];
+
+    // ajmp and acall in every page
+    for page in 0..8 {
+        let target = ((page as u16) << 8) | 0x34;
+        vectors.push(v(format!("ajmp page {}", page), &[0x01 | (page << 5), 0x34]).pc(target).cycles(2));
+        vectors.push(
+            v(format!("acall page {}", page), &[0x11 | (page << 5), 0x34])
+                .expect(SP, 0x09).expect(r(0x08), 0x02).expect(r(0x09), 0x00)
+                .pc(target).cycles(2)
+        );
+    }
+
+    // Indirect operands, r1 points to upper internal RAM
+    for i in 0..2 {
+        let ptr = if i == 0 { 0x40 } else { 0x90 };
+        let mem = Addr::IRam(ptr);
+        let name = |s: &str| format!("{} @r{}", s, i);
+        vectors.extend(vec![
+            v(name("inc"), &[0x06 + i]).init(r(i), ptr).init(mem, 0x10).expect(mem, 0x11),
+            v(name("dec"), &[0x16 + i]).init(r(i), ptr).init(mem, 0x10).expect(mem, 0x0F),
+            v(name("add a,"), &[0x26 + i]).init(r(i), ptr).init(mem, 0x0F).init(A, 0x01).expect(A, 0x10).psw(AC),
+            v(name("addc a,"), &[0x36 + i]).init(r(i), ptr).init(mem, 0x0F).init(A, 0x00).init(PSW, CY)
+                .expect(A, 0x10).psw(AC),
+            v(name("orl a,"), &[0x46 + i]).init(r(i), ptr).init(mem, 0x0F).init(A, 0x10).expect(A, 0x1F),
+            v(name("anl a,"), &[0x56 + i]).init(r(i), ptr).init(mem, 0x0F).init(A, 0x1C).expect(A, 0x0C),
+            v(name("xrl a,"), &[0x66 + i]).init(r(i), ptr).init(mem, 0x0F).init(A, 0x1C).expect(A, 0x13),
+            v(name("mov #data to"), &[0x76 + i, 0x5A]).init(r(i), ptr).expect(mem, 0x5A),
+            v(name("mov direct,"), &[0x86 + i, 0x30]).init(r(i), ptr).init(mem, 0x5A).expect(r(0x30), 0x5A).cycles(2),
+            v(name("subb a,"), &[0x96 + i]).init(r(i), ptr).init(mem, 0x01).init(A, 0x10).expect(A, 0x0F).psw(AC),
+            v(name("mov from direct to"), &[0xA6 + i, 0x30]).init(r(i), ptr).init(r(0x30), 0x5A).expect(mem, 0x5A).cycles(2),
+            v(name("cjne"), &[0xB6 + i, 0x10, 0x10]).init(r(i), ptr).init(mem, 0x20).pc(0x13).cycles(2),
+            v(name("xch a,"), &[0xC6 + i]).init(r(i), ptr).init(mem, 0x01).init(A, 0x02).expect(A, 0x01).expect(mem, 0x02),
+            v(name("xchd a,"), &[0xD6 + i]).init(r(i), ptr).init(mem, 0x12).init(A, 0x34).expect(A, 0x32).expect(mem, 0x14),
+            v(name("movx a,"), &[0xE2 + i]).init(r(i), 0x34).init(P2, 0x12).init(Addr::XRam(0x1234), 0x77)
+                .expect(A, 0x77).cycles(2),
+            v(name("mov a,"), &[0xE6 + i]).init(r(i), ptr).init(mem, 0x42).expect(A, 0x42),
+            v(name("movx a to"), &[0xF2 + i]).init(r(i), 0x34).init(P2, 0x12).init(A, 0x77)
+                .expect(Addr::XRam(0x1234), 0x77).cycles(2),
+            v(name("mov a to"), &[0xF6 + i]).init(r(i), ptr).init(A, 0x42).expect(mem, 0x42),
+        ]);
+    }
+
+    // Register operands
+    for n in 0..8 {
+        let rn = r(n);
+        let name = |s: &str| format!("{} r{}", s, n);
+        vectors.extend(vec![
+            v(name("inc"), &[0x08 + n]).init(rn, 0xFF).expect(rn, 0x00),
+            v(name("dec"), &[0x18 + n]).init(rn, 0x00).expect(rn, 0xFF),
+            v(name("add a,"), &[0x28 + n]).init(rn, 0x80).init(A, 0x80).expect(A, 0x00).psw(CY | OV),
+            v(name("addc a,"), &[0x38 + n]).init(rn, 0x7F).init(A, 0x00).init(PSW, CY).expect(A, 0x80).psw(AC | OV),
+            v(name("orl a,"), &[0x48 + n]).init(rn, 0x01).init(A, 0x10).expect(A, 0x11),
+            v(name("anl a,"), &[0x58 + n]).init(rn, 0x11).init(A, 0x10).expect(A, 0x10),
+            v(name("xrl a,"), &[0x68 + n]).init(rn, 0x11).init(A, 0x10).expect(A, 0x01),
+            v(name("mov #data to"), &[0x78 + n, 0x5A]).expect(rn, 0x5A),
+            v(name("mov direct,"), &[0x88 + n, 0x30]).init(rn, 0x5A).expect(r(0x30), 0x5A).cycles(2),
+            v(name("subb a,"), &[0x98 + n]).init(rn, 0x7F).init(A, 0xFE).expect(A, 0x7F).psw(AC | OV),
+            v(name("mov from direct to"), &[0xA8 + n, 0x30]).init(r(0x30), 0x5A).expect(rn, 0x5A).cycles(2),
+            v(name("cjne"), &[0xB8 + n, 0x10, 0x10]).init(rn, 0x10).cycles(2),
+            v(name("xch a,"), &[0xC8 + n]).init(rn, 0x01).init(A, 0x02).expect(A, 0x01).expect(rn, 0x02),
+            v(name("djnz"), &[0xD8 + n, 0xFE]).init(rn, 0x00).expect(rn, 0xFF).pc(0x00).cycles(2),
+            v(name("mov a,"), &[0xE8 + n]).init(rn, 0x42).expect(A, 0x42),
+            v(name("mov a to"), &[0xF8 + n]).init(A, 0x42).expect(rn, 0x42),
+        ]);
+    }
+
+    vectors
+}
+
+#[test]
+fn every_opcode_has_a_vector() {
+    let vectors = vectors();
+    let missing: Vec<String> = (0x00..=0xFF)
+        // Reserved opcode
+        .filter(|&op| op != 0xA5)
+        .filter(|&op| ! vectors.iter().any(|vector| vector.code[0] == op))
+        .map(|op| format!("0x{:02X}", op))
+        .collect();
+    assert!(missing.is_empty(), "opcodes without vectors: {}", missing.join(", "));
+}
+
+#[test]
+fn reserved_opcode() {
+    let mut mcu = Mcu::new(vec![0x00, 0xA5].into_boxed_slice());
+    mcu.power_on(None);
+    mcu.step().unwrap();
+    let cycles = mcu.cycles;
+    assert_eq!(mcu.step(), Err(Error::UnknownOpcode { pc: 0x0001, op: 0xA5 }));
+    assert_eq!(mcu.pc, 0x0001);
+    assert_eq!(mcu.cycles, cycles);
+}
+
+#[test]
+fn conformance() {
+    let failures: Vec<String> = vectors().iter().filter_map(|vector| run(vector).err()).collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
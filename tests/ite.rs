@@ -0,0 +1,204 @@
+//! ITE embedded controller peripherals
+
+mod common;
+
+use area8051::{
+    sbs, Addr, Budget, HostInterface, IteChip, Mcu, Mem, SmartBattery, Smbus,
+};
+
+/// Firmware running `code`, then looping with sjmp $
+fn mcu(mut code: Vec<u8>) -> Mcu {
+    code.extend_from_slice(&common::IDLE);
+    common::ite(IteChip::It5570, &code)
+}
+
+/// mov dptr, #addr; mov a, #value; movx @dptr, a
+fn write(code: &mut Vec<u8>, addr: u16, value: u8) {
+    code.extend_from_slice(&[0x90, (addr >> 8) as u8, addr as u8, 0x74, value, 0xF0]);
+}
+
+/// mov dptr, #addr; movx a, @dptr; mov iram, a
+fn read(code: &mut Vec<u8>, addr: u16, iram: u8) {
+    code.extend_from_slice(&[0x90, (addr >> 8) as u8, addr as u8, 0xE0, 0xF5, iram]);
+}
+
+#[test]
+fn chip_id_and_gpio() {
+    let mut code = Vec::new();
+    read(&mut code, 0x2000, 0x30);
+    read(&mut code, 0x2001, 0x31);
+    // GPA0 output high, GPB1 input
+    write(&mut code, 0x1610, 0x40);
+    write(&mut code, 0x1601, 0x01);
+    read(&mut code, 0x1662, 0x32);
+    let mut mcu = mcu(code);
+    mcu.ite.as_mut().unwrap().gpio_inputs[1] &= !(1 << 1);
+    mcu.run(Budget::Cycles(100), |_| false);
+
+    assert_eq!(&mcu.iram[0x30..0x33], &[0x55, 0x70, 0xFD]);
+    let ite = mcu.ite.as_ref().unwrap();
+    assert!(ite.gpio_pin(0, 0));
+    assert!(! ite.gpio_pin(1, 1));
+    // Control registers are kept by the model, other addresses stay plain memory
+    assert_eq!(mcu.load(Addr::XRam(0x1610)), 0x40);
+    assert_eq!(mcu.xram[0x1610], 0);
+}
+
+#[test]
+fn keyboard_and_power_management_channels() {
+    let mut code = Vec::new();
+    read(&mut code, 0x1304, 0x30);
+    read(&mut code, 0x130A, 0x31);
+    write(&mut code, 0x1306, 0x55);
+    read(&mut code, 0x1500, 0x32);
+    read(&mut code, 0x1504, 0x33);
+    write(&mut code, 0x1501, 0x12);
+
+    let mut mcu = mcu(code);
+    {
+        let ite = mcu.ite.as_mut().unwrap();
+        ite.kbc.host_write(true, 0xAA);
+        ite.pmc[0].host_write(false, 0x80);
+    }
+    mcu.run(Budget::Cycles(100), |_| false);
+
+    let status = HostInterface::IBF | HostInterface::CMD;
+    assert_eq!(&mcu.iram[0x30..0x34], &[status, 0xAA, HostInterface::IBF, 0x80]);
+    let ite = mcu.ite.as_mut().unwrap();
+    assert_eq!(ite.kbc.status(), HostInterface::OBF | HostInterface::CMD);
+    assert_eq!(ite.kbc.host_read(), Some(0x55));
+    assert_eq!(ite.kbc.host_read(), None);
+    assert_eq!(ite.pmc[0].host_read(), Some(0x12));
+}
+
+#[test]
+fn debugger_load_keeps_input_buffer() {
+    let mut code = Vec::new();
+    read(&mut code, 0x130A, 0x30);
+    let mut mcu = mcu(code);
+    mcu.ite.as_mut().unwrap().kbc.host_write(false, 0x42);
+
+    // A debugger load sees the input without clearing IBF
+    assert_eq!(mcu.load(Addr::XRam(0x130A)), 0x42);
+    assert_eq!(mcu.load(Addr::XRam(0x1304)), HostInterface::IBF);
+
+    // The firmware read of KBHIDIR clears it
+    mcu.run(Budget::Cycles(20), |_| false);
+    assert_eq!(mcu.iram[0x30], 0x42);
+    assert_eq!(mcu.load(Addr::XRam(0x1304)), 0);
+}
+
+#[test]
+fn smbus_word_read() {
+    let mut code = Vec::new();
+    write(&mut code, 0x1C10, Smbus::SMHEN);
+    write(&mut code, 0x1C03, 0x0B << 1 | 1);
+    write(&mut code, 0x1C02, sbs::VOLTAGE);
+    write(&mut code, 0x1C01, Smbus::SRT | 0b011 << 2);
+    read(&mut code, 0x1C00, 0x30);
+    read(&mut code, 0x1C04, 0x31);
+    read(&mut code, 0x1C05, 0x32);
+
+    let mut mcu = mcu(code);
+    mcu.ite.as_mut().unwrap().smbus[0].bus.attach(SmartBattery::new());
+    mcu.run(Budget::Cycles(200), |_| false);
+
+    assert_eq!(&mcu.iram[0x30..0x33], &[Smbus::FINTR, 0x18, 0x2E]);
+}
+
+#[test]
+fn smbus_block_read_and_errors() {
+    let mut mcu = mcu(Vec::new());
+    mcu.ite.as_mut().unwrap().smbus[1].bus.attach(SmartBattery::new());
+    mcu.store(Addr::XRam(0x1C21), Smbus::SMHEN);
+    mcu.store(Addr::XRam(0x1C14), 0x0B << 1 | 1);
+    mcu.store(Addr::XRam(0x1C13), sbs::DEVICE_CHEMISTRY);
+    mcu.store(Addr::XRam(0x1C12), Smbus::SRT | 0b101 << 2);
+
+    assert_eq!(mcu.load(Addr::XRam(0x1C15)), 4);
+    let mut name = Vec::new();
+    for _ in 0..4 {
+        assert_ne!(mcu.load(Addr::XRam(0x1C11)) & Smbus::BDS, 0);
+        name.push(mcu.load(Addr::XRam(0x1C17)));
+        mcu.store(Addr::XRam(0x1C11), Smbus::BDS);
+    }
+    assert_eq!(name, b"LION");
+    assert_eq!(mcu.load(Addr::XRam(0x1C11)), Smbus::FINTR);
+
+    // No device at the address
+    mcu.store(Addr::XRam(0x1C11), 0xFF);
+    mcu.store(Addr::XRam(0x1C14), 0x20 << 1);
+    mcu.store(Addr::XRam(0x1C12), Smbus::SRT | 0b001 << 2);
+    assert_eq!(mcu.load(Addr::XRam(0x1C11)), Smbus::FINTR | Smbus::DVER);
+}
+
+#[test]
+fn pwm_tachometer_and_adc() {
+    let mut mcu = mcu(Vec::new());
+    {
+        let ite = mcu.ite.as_mut().unwrap();
+        ite.fan_rpm[0] = 3000;
+        ite.adc_inputs[1] = 0x2AB;
+    }
+    mcu.store(Addr::XRam(0x1803), 0x80);
+    assert!((mcu.ite.as_ref().unwrap().pwm_duty(1) - 128.0 / 255.0).abs() < 1e-9);
+
+    let tach = (mcu.load(Addr::XRam(0x181F)) as u16) << 8 | mcu.load(Addr::XRam(0x181E)) as u16;
+    assert_eq!(tach, 1437);
+
+    assert_eq!(mcu.load(Addr::XRam(0x1906)), 0);
+    mcu.store(Addr::XRam(0x1901), 0x01);
+    assert_eq!(mcu.load(Addr::XRam(0x1906)) & 0x80, 0x80);
+    assert_eq!(mcu.load(Addr::XRam(0x1907)), 0xAB);
+    assert_eq!(mcu.load(Addr::XRam(0x1908)), 0x02);
+}
+
+#[test]
+fn peci_get_temp() {
+    let mut mcu = mcu(Vec::new());
+    for &(addr, value) in [(0x3003, 0x30), (0x3004, 1), (0x3005, 2), (0x3002, 0x01), (0x3001, 0x01)].iter() {
+        mcu.store(Addr::XRam(addr), value);
+    }
+    assert_eq!(mcu.load(Addr::XRam(0x3000)), 0x02);
+    // Debugger loads see the next byte without consuming it, firmware reads move on
+    assert_eq!(mcu.load(Addr::XRam(0x3007)), mcu.load(Addr::XRam(0x3007)));
+    let temperature = i16::from_le_bytes([mcu.read(Addr::XRam(0x3007)), mcu.read(Addr::XRam(0x3007))]);
+    assert_eq!(temperature, -20 * 64);
+}
+
+#[test]
+fn scratch_rom() {
+    // Code at 0x0200 is copied to scratch SRAM and then runs from it
+    let mut code = vec![0; 0x202];
+    code[..3].copy_from_slice(&[0x02, 0x02, 0x00]);
+    code[0x200..].copy_from_slice(&[0x80, 0xFE]);
+    let mut mcu = mcu(code);
+    mcu.store(Addr::XRam(0x1040), 0x00);
+    mcu.store(Addr::XRam(0x1041), 0x02);
+    mcu.store(Addr::XRam(0x1042), 0x00);
+
+    assert_eq!(&mcu.xram[..2], &[0x80, 0xFE]);
+    mcu.xram[0] = 0x00;
+    assert_eq!(mcu.load(Addr::PMem(0x0200)), 0x00);
+    assert_eq!(mcu.load(Addr::PMem(0x0A00)), 0xFF);
+
+    // The patched nop and mov r6, a run from scratch SRAM
+    mcu.run(Budget::Instructions(3), |_| false);
+    assert_eq!(mcu.pc, 0x0202);
+}
+
+#[test]
+fn firmware_loop() {
+    // Wait for IBF, echo the input byte incremented
+    let mut code = vec![
+        0x90, 0x13, 0x04, 0xE0, 0x30, 0xE1, 0xFC,
+    ];
+    read(&mut code, 0x130A, 0x30);
+    code.extend_from_slice(&[0x04, 0x90, 0x13, 0x06, 0xF0]);
+    let mut mcu = mcu(code);
+    mcu.run(Budget::Cycles(100), |_| false);
+    mcu.ite.as_mut().unwrap().kbc.host_write(false, 0x41);
+    mcu.run(Budget::Cycles(100), |_| false);
+
+    assert_eq!(mcu.ite.as_mut().unwrap().kbc.host_read(), Some(0x42));
+}
@@ -0,0 +1,100 @@
+//! Keyboard matrix scanning
+
+mod common;
+
+use area8051::{Addr, Budget, KeyMatrix, MatrixLines, Mcu, Mem};
+
+/// Four columns on P1.0 to P1.3 and four rows on P2.0 to P2.3
+fn pins() -> KeyMatrix {
+    KeyMatrix::new(MatrixLines::Pins {
+        outputs: (0..4).map(|bit| (1, bit)).collect(),
+        inputs: (0..4).map(|bit| (2, bit)).collect(),
+    })
+}
+
+/// Rows read with only `column` driven low through the ITE scan registers
+fn scan(mcu: &mut Mcu, column: u8) -> u8 {
+    mcu.store(Addr::XRam(0x1D00), !(1 << column));
+    mcu.load(Addr::XRam(0x1D04))
+}
+
+#[test]
+fn firmware_scan() {
+    // Drive each column low in turn and keep the rows from 0x30
+    let mut code = Vec::new();
+    for column in 0..4 {
+        code.extend_from_slice(&[0x75, 0x90, !(1 << column), 0x85, 0xA0, 0x30 + column]);
+    }
+    code.extend_from_slice(&[0x75, 0x90, 0xFF, 0x80, 0xFE]);
+    let mut mcu = common::mcu(&code);
+    mcu.attach_matrix(pins());
+    mcu.set_key(1, 2, true);
+    mcu.set_key(3, 0, true);
+    mcu.run(Budget::Cycles(50), |_| false);
+
+    assert_eq!(&mcu.iram[0x30..0x34], &[0xF7, 0xFF, 0xFD, 0xFF]);
+    // Rows are released with no column driven
+    assert_eq!(mcu.gpio.ports[2].pins(), 0xFF);
+    assert!(mcu.matrix.as_ref().unwrap().pressed(1, 2));
+
+    mcu.set_key(1, 2, false);
+    mcu.pc = 0;
+    mcu.run(Budget::Cycles(50), |_| false);
+    assert_eq!(&mcu.iram[0x30..0x34], &[0xF7, 0xFF, 0xFF, 0xFF]);
+}
+
+#[test]
+fn ghosting() {
+    let mut mcu = common::mcu(&common::IDLE);
+    mcu.attach_matrix(KeyMatrix::ite());
+    mcu.set_key(0, 0, true);
+    mcu.set_key(0, 1, true);
+    mcu.set_key(1, 0, true);
+    assert_eq!(scan(&mut mcu, 1), 0xFE);
+
+    // Without diodes, column 1 reaches row 1 through the other keys
+    mcu.matrix.as_mut().unwrap().ghosting = true;
+    assert_eq!(scan(&mut mcu, 1), 0xFC);
+    assert_eq!(scan(&mut mcu, 2), 0xFF);
+
+    // Columns from 16 are in KSOH2
+    mcu.set_key(7, 17, true);
+    mcu.store(Addr::XRam(0x1D00), 0xFF);
+    mcu.store(Addr::XRam(0x1D03), !(1 << 1));
+    assert_eq!(mcu.load(Addr::XRam(0x1D04)), 0x7F);
+}
+
+#[test]
+fn bounce() {
+    let mut matrix = KeyMatrix::ite();
+    matrix.bounce = 400;
+    let mut mcu = common::mcu(&common::IDLE);
+    mcu.attach_matrix(matrix);
+    mcu.set_key(2, 3, true);
+
+    let mut levels = Vec::new();
+    for _ in 0..25 {
+        levels.push(scan(&mut mcu, 3));
+        mcu.run(Budget::Cycles(16), |_| false);
+    }
+    assert!(levels.contains(&0xFF) && levels.contains(&0xFB));
+    assert_eq!(scan(&mut mcu, 3), 0xFB);
+}
+
+#[test]
+fn bounce_on_pins() {
+    // Hold column 0 low
+    let mut matrix = pins();
+    matrix.bounce = 400;
+    let mut mcu = common::mcu(&[0xC2, 0x90, 0x80, 0xFE]);
+    mcu.attach_matrix(matrix);
+    mcu.run(Budget::Cycles(10), |_| false);
+    mcu.gpio.history.clear();
+    mcu.set_key(0, 0, true);
+    mcu.run(Budget::Cycles(500), |_| false);
+
+    let changes = mcu.gpio.history.iter().filter(|change| change.port == 2).count();
+    assert!(changes > 2, "{} changes", changes);
+    assert!(! mcu.gpio.pin(2, 0));
+    assert!(mcu.scheduler.is_empty());
+}
@@ -0,0 +1,49 @@
+//! Pacing serial data in real time
+
+use area8051::Pacer;
+use std::time::{Duration, Instant};
+
+fn ms(ms: f64) -> Duration {
+    Duration::from_secs_f64(ms / 1000.0)
+}
+
+#[test]
+fn unpaced() {
+    let mut pacer = Pacer::new(None);
+    assert_eq!(pacer.take(5), 5);
+    assert_eq!(pacer.take(0), 0);
+}
+
+#[test]
+fn paced_at_baud_rate() {
+    // 10000 baud is one byte per millisecond
+    let mut pacer = Pacer::new(Some(10_000));
+    let start = Instant::now();
+    assert_eq!(pacer.take_at(start, 3), 1);
+    assert_eq!(pacer.take_at(start + ms(0.5), 2), 0);
+    assert_eq!(pacer.take_at(start + ms(1.0), 2), 1);
+    assert_eq!(pacer.take_at(start + ms(1.5), 1), 0);
+}
+
+#[test]
+fn waiting_bytes_catch_up() {
+    // Polling less often than once per byte still releases bytes at the baud rate
+    let mut pacer = Pacer::new(Some(10_000));
+    let start = Instant::now();
+    let mut released = 0;
+    for poll in 0..10 {
+        released += pacer.take_at(start + ms(poll as f64 * 3.0), 100 - released);
+    }
+    assert_eq!(released, 28);
+}
+
+#[test]
+fn idle_line_gives_no_credit() {
+    let mut pacer = Pacer::new(Some(10_000));
+    let start = Instant::now();
+    assert_eq!(pacer.take_at(start, 1), 1);
+    assert_eq!(pacer.take_at(start + ms(5.0), 0), 0);
+    assert_eq!(pacer.take_at(start + ms(20.0), 5), 1);
+    assert_eq!(pacer.take_at(start + ms(20.5), 4), 0);
+    assert_eq!(pacer.take_at(start + ms(23.0), 4), 3);
+}
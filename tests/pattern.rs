@@ -0,0 +1,54 @@
+//! Byte patterns of UART expectations
+
+use area8051::Pattern;
+
+fn matches(pattern: &str, data: &str) -> bool {
+    Pattern::new(pattern).unwrap().is_match(data.as_bytes())
+}
+
+#[test]
+fn literals_and_anchors() {
+    assert!(matches("OK", "\r\nOK\r\n"));
+    assert!(! matches("^OK", "\r\nOK"));
+    assert!(matches("^OK", "OK\r\n"));
+    assert!(! matches("OK$", "OK\r\n"));
+    assert!(matches("OK\\r\\n$", "OK\r\n"));
+    assert!(matches("^$", ""));
+    assert!(matches("", "anything"));
+    // An escaped dollar is a literal
+    assert!(matches("5\\$", "costs 5$ today"));
+    // An escaped backslash before the anchor
+    assert!(matches("C:\\\\$", "C:\\"));
+    assert!(! matches("C:\\\\$", "C:\\ "));
+    assert!(matches("5\\\\\\$", "5\\$"));
+}
+
+#[test]
+fn classes_and_escapes() {
+    assert!(matches("^[a-c]+$", "abcab"));
+    assert!(! matches("^[a-c]+$", "abcd"));
+    assert!(matches("^[^0-9]$", "x"));
+    assert!(! matches("^[^0-9]$", "7"));
+    assert!(matches("^[-a]$", "-"));
+    assert!(matches("^[]]$", "]"));
+    assert!(matches("^\\d\\d:\\w+\\s$", "42:ab_9 "));
+    assert!(matches("^[\\d.]+$", "3.14"));
+    assert!(matches("^\\x41\\t.$", "A\tz"));
+    assert!(Pattern::new("\\xA").is_err());
+    assert!(Pattern::new("[abc").is_err());
+    assert!(Pattern::new("abc\\").is_err());
+}
+
+#[test]
+fn repetitions() {
+    assert!(matches("^ab*c$", "ac"));
+    assert!(matches("^ab*c$", "abbbc"));
+    assert!(! matches("^ab+c$", "ac"));
+    assert!(matches("^ab?c$", "abc"));
+    assert!(! matches("^ab?c$", "abbc"));
+    // Greedy repetitions backtrack
+    assert!(matches("^a.*b$", "axxbyyb"));
+    assert!(matches("^\\d+0$", "1000"));
+    assert!(Pattern::new("*a").is_err());
+    assert!(Pattern::new("a+?").is_err());
+}
@@ -0,0 +1,85 @@
+//! PCON idle and power-down modes
+
+mod common;
+
+use area8051::{Addr, Budget, Mcu, Mem, Power, Variant, Watchdog};
+
+const PCON: Addr = Addr::Reg(0x87);
+const TCON: Addr = Addr::Reg(0x88);
+
+/// Enable `ie`, enter the PCON mode `mode`, then count wake-ups in 0x30
+/// Handlers for INT0 and timer 0 count in 0x31.
+fn sleeper(variant: Variant, ie: u8, mode: u8) -> Mcu {
+    let mut code = vec![0x02, 0x00, 0x30, 0x05, 0x31, 0x32];
+    code.resize(0x0B, 0);
+    code.extend_from_slice(&[0x05, 0x31, 0x32]);
+    code.resize(0x30, 0);
+    // Edge triggered INT0
+    code.extend_from_slice(&[0x75, 0xA8, ie, 0x75, 0x88, 0x01, 0x43, 0x87, mode, 0x05, 0x30, 0x80, 0xF9]);
+    common::variant(variant, &code)
+}
+
+#[test]
+fn idle_wakes_on_interrupt() {
+    let mut mcu = sleeper(Variant::I8052, 0x82, 0x01);
+    mcu.run(Budget::Instructions(4), |_| false);
+    assert_eq!(mcu.power(), Power::Idle);
+    let entered = mcu.cycles;
+    mcu.schedule_at(100, |mcu| mcu.store(TCON, 0x21));
+    mcu.run(Budget::Cycles(100 - entered), |_| false);
+    assert_eq!(mcu.power(), Power::Idle);
+    assert_eq!(mcu.idle_cycles, 100 - entered);
+
+    // The handler runs, then execution continues after the instruction that entered idle
+    mcu.run(Budget::Instructions(4), |_| false);
+    assert_eq!(mcu.iram[0x31], 1);
+    assert_eq!(mcu.iram[0x30], 1);
+    assert_eq!(mcu.load(PCON) & 0b11, 0);
+}
+
+#[test]
+fn power_down_wakes_on_external_interrupt() {
+    let mut mcu = sleeper(Variant::At89s52, 0x83, 0x02);
+    mcu.run(Budget::Instructions(4), |_| false);
+    assert_eq!(mcu.power(), Power::PowerDown);
+
+    // Timers are stopped, only INT0 ends power-down
+    mcu.schedule_in(50, |mcu| mcu.store(TCON, 0x21));
+    mcu.schedule_in(100, |mcu| mcu.drive_pin(3, 2, Some(false)));
+    mcu.run(Budget::Cycles(90), |_| false);
+    assert_eq!(mcu.power(), Power::PowerDown);
+    assert_eq!(mcu.iram[0x31], 0);
+    mcu.run(Budget::Cycles(20), |mcu| mcu.iram[0x30] == 1);
+    assert_eq!(mcu.power(), Power::Active);
+    assert_eq!(mcu.iram[0x31], 1);
+    // Timer 0 is serviced once the clock runs again
+    mcu.run(Budget::Instructions(1), |_| false);
+    assert_eq!(mcu.pc, 0x000B);
+
+    // Only reset ends power-down on the 8052
+    let mut mcu = sleeper(Variant::I8052, 0x81, 0x02);
+    mcu.run(Budget::Instructions(4), |_| false);
+    mcu.schedule_in(10, |mcu| mcu.drive_pin(3, 2, Some(false)));
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.power(), Power::PowerDown);
+}
+
+#[test]
+fn idle_stops_at_budget() {
+    // A running watchdog is the only event, far beyond the budget
+    let mut mcu = sleeper(Variant::At89s52, 0x00, 0x01);
+    mcu.watchdog = Some(Watchdog::at89s52());
+    mcu.store(Addr::Reg(0xA6), 0x1E);
+    mcu.store(Addr::Reg(0xA6), 0xE1);
+    mcu.run(Budget::Cycles(100), |_| false);
+    assert_eq!(mcu.cycles, 100);
+
+    mcu.run_until(200).unwrap();
+    assert_eq!(mcu.cycles, 200);
+
+    // The budget ends before a scheduled event
+    mcu.schedule_at(1000, |_| ());
+    mcu.run(Budget::Cycles(5), |_| false);
+    assert_eq!(mcu.cycles, 205);
+    assert_eq!(mcu.power(), Power::Idle);
+}
@@ -7,6 +7,7 @@ void puts(const char * s) {
     }
 }
 
+// Semihosting exit command, with status 0 in the data register at 0xFFFE
 void shutdown() {
     *((char *)0xFFFF) = 1;
 }
@@ -0,0 +1,170 @@
+//! PS/2 keyboard and touchpad channels
+
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use area8051::{
+    Addr, Budget, Mcu, Mem, Ps2Controller, Ps2Device, Ps2Event, Ps2Keyboard, Ps2Link, Ps2Port,
+    Ps2Touchpad,
+};
+
+/// Clock and data on P1.0 and P1.1
+const PINS: Ps2Link = Ps2Link::Pins { clock: (1, 0), data: (1, 1) };
+
+/// Receive frames forever, storing the bytes from IRAM 0x30
+const RECEIVE: [u8; 29] = [
+    0x78, 0x30,
+    // Start and data bits, sampled on the falling clock edge
+    0x7F, 0x09, 0x20, 0x90, 0xFD, 0xA2, 0x91, 0x13, 0x30, 0x90, 0xFD, 0xDF, 0xF5,
+    0xF6, 0x08,
+    // Parity and stop
+    0x7F, 0x02, 0x20, 0x90, 0xFD, 0x30, 0x90, 0xFD, 0xDF, 0xF8,
+    0x80, 0xE5,
+];
+
+fn responses<D: Ps2Device>(device: &mut D, command: u8) -> Vec<u8> {
+    device.receive(command);
+    std::iter::from_fn(|| device.send()).collect()
+}
+
+#[test]
+fn keyboard_commands() {
+    let mut keyboard = Ps2Keyboard::new();
+    assert_eq!(responses(&mut keyboard, 0xFF), [0xFA, 0xAA]);
+    assert_eq!(responses(&mut keyboard, 0xF2), [0xFA, 0xAB, 0x83]);
+    assert_eq!(responses(&mut keyboard, 0xED), [0xFA]);
+    assert_eq!(responses(&mut keyboard, 0x04), [0xFA]);
+    assert_eq!(keyboard.leds, 0x04);
+    assert_eq!(responses(&mut keyboard, 0xF0), [0xFA]);
+    assert_eq!(responses(&mut keyboard, 0x00), [0xFA, 0x02]);
+    assert_eq!(responses(&mut keyboard, 0xFE), [0x02]);
+    assert_eq!(responses(&mut keyboard, 0xEE), [0xEE]);
+    assert_eq!(responses(&mut keyboard, 0x12), [0xFE]);
+
+    // Keys are not reported while scanning is disabled
+    assert_eq!(responses(&mut keyboard, 0xF5), [0xFA]);
+    keyboard.press(0x1C);
+    assert_eq!(keyboard.send(), None);
+    assert_eq!(responses(&mut keyboard, 0xF4), [0xFA]);
+    keyboard.press(0xE075);
+    keyboard.release(0xE075);
+    assert_eq!(std::iter::from_fn(|| keyboard.send()).collect::<Vec<_>>(), [0xE0, 0x75, 0xE0, 0xF0, 0x75]);
+}
+
+#[test]
+fn touchpad_commands() {
+    let mut touchpad = Ps2Touchpad::new();
+    assert_eq!(responses(&mut touchpad, 0xFF), [0xFA, 0xAA, 0x00]);
+    assert_eq!(responses(&mut touchpad, 0xF2), [0xFA, 0x00]);
+    touchpad.motion(5, 5, 0);
+    assert_eq!(touchpad.send(), None);
+
+    assert_eq!(responses(&mut touchpad, 0xF3), [0xFA]);
+    assert_eq!(responses(&mut touchpad, 40), [0xFA]);
+    assert_eq!(responses(&mut touchpad, 0xF4), [0xFA]);
+    // Left button, moving left past the range and up
+    touchpad.motion(-300, 3, 0b001);
+    assert_eq!(std::iter::from_fn(|| touchpad.send()).collect::<Vec<_>>(), [0x59, 0x00, 0x03]);
+    assert_eq!(responses(&mut touchpad, 0xE9), [0xFA, 0x24, 2, 40]);
+}
+
+#[test]
+fn bit_banged_scancodes() {
+    let keyboard = Rc::new(RefCell::new(Ps2Keyboard::new()));
+    let mut mcu = common::mcu(&RECEIVE);
+    mcu.attach_ps2(Ps2Port::new(PINS, keyboard.clone()));
+    keyboard.borrow_mut().press(0x1C);
+    keyboard.borrow_mut().release(0x1C);
+    keyboard.borrow_mut().press(0xE075);
+    mcu.ps2_poll(0);
+    mcu.run(Budget::Cycles(6000), |_| false);
+
+    assert_eq!(&mcu.iram[0x30..0x35], &[0x1C, 0xF0, 0x1C, 0xE0, 0x75]);
+    assert_eq!(mcu.ps2[0].log.len(), 5);
+}
+
+#[test]
+fn host_inhibits_clock() {
+    // Hold the clock low from cycle 200 to 400
+    let code = [0x7F, 0x64, 0xDF, 0xFE, 0xC2, 0x90, 0x7F, 0x64, 0xDF, 0xFE, 0xD2, 0x90, 0x80, 0xFE];
+    let keyboard = Rc::new(RefCell::new(Ps2Keyboard::new()));
+    let mut mcu = common::mcu(&code);
+    mcu.attach_ps2(Ps2Port::new(PINS, keyboard.clone()));
+    keyboard.borrow_mut().press(0x1C);
+    mcu.ps2_poll(0);
+
+    mcu.run(Budget::Cycles(350), |_| false);
+    assert_eq!(mcu.ps2[0].log, [Ps2Event::Inhibited(0x1C)]);
+    mcu.run(Budget::Cycles(1500), |_| false);
+    assert_eq!(mcu.ps2[0].log, [Ps2Event::Inhibited(0x1C), Ps2Event::ToHost(0x1C)]);
+}
+
+#[test]
+fn bit_banged_command() {
+    // Send reset to the keyboard, keep the acknowledge bit in 0x21.0, then receive the responses
+    let mut code = vec![
+        // Inhibit, then request to send
+        0xC2, 0x90, 0x7F, 0x3C, 0xDF, 0xFE, 0xC2, 0x91, 0xD2, 0x90,
+        // Odd parity to bit 0x00
+        0x74, 0xFF, 0xA2, 0xD0, 0xB3, 0x92, 0x00,
+        // Data bits change while the clock is low
+        0x7F, 0x08, 0x20, 0x90, 0xFD, 0x13, 0x92, 0x91, 0x30, 0x90, 0xFD, 0xDF, 0xF5,
+        // Parity, stop and acknowledge
+        0x20, 0x90, 0xFD, 0xA2, 0x00, 0x92, 0x91, 0x30, 0x90, 0xFD,
+        0x20, 0x90, 0xFD, 0xD2, 0x91, 0x30, 0x90, 0xFD,
+        0x20, 0x90, 0xFD, 0xA2, 0x91, 0x92, 0x08, 0x30, 0x90, 0xFD,
+        0x02, 0x00, 0x40,
+    ];
+    code.resize(0x40, 0);
+    code.extend_from_slice(&RECEIVE);
+    let keyboard = Rc::new(RefCell::new(Ps2Keyboard::new()));
+    keyboard.borrow_mut().leds = 0x07;
+    let mut mcu = common::mcu(&code);
+    mcu.attach_ps2(Ps2Port::new(PINS, keyboard.clone()));
+    mcu.iram[0x21] = 0xFF;
+    mcu.run(Budget::Cycles(5000), |_| false);
+
+    assert_eq!(mcu.iram[0x21] & 1, 0);
+    assert_eq!(&mcu.iram[0x30..0x32], &[0xFA, 0xAA]);
+    assert_eq!(mcu.ps2[0].log, [Ps2Event::ToDevice(0xFF), Ps2Event::ToHost(0xFA), Ps2Event::ToHost(0xAA)]);
+    assert_eq!(keyboard.borrow().leds, 0);
+}
+
+#[test]
+fn controller_channels() {
+    let touchpad = Rc::new(RefCell::new(Ps2Touchpad::new()));
+    let mut mcu = common::idle();
+    mcu.attach_ps2(Ps2Port::new(Ps2Link::Controller(Ps2Controller::ite(0)), Ps2Keyboard::new()));
+    mcu.attach_ps2(Ps2Port::new(Ps2Link::Controller(Ps2Controller::ite(1)), touchpad.clone()));
+    let frame = |mcu: &mut Mcu, channel: u16| {
+        mcu.run(Budget::Cycles(76 * 13), |_| false);
+        assert_eq!(mcu.load(Addr::XRam(0x1708 + channel)), Ps2Controller::TDS);
+        mcu.store(Addr::XRam(0x1708 + channel), Ps2Controller::TDS);
+        mcu.load(Addr::XRam(0x170C + channel))
+    };
+
+    // Identify the keyboard
+    mcu.store(Addr::XRam(0x1700), Ps2Controller::PSHE | Ps2Controller::TRMS);
+    mcu.store(Addr::XRam(0x170C), 0xF2);
+    frame(&mut mcu, 0);
+    mcu.store(Addr::XRam(0x1700), Ps2Controller::PSHE);
+    let id: Vec<u8> = (0..3).map(|_| frame(&mut mcu, 0)).collect();
+    assert_eq!(id, [0xFA, 0xAB, 0x83]);
+
+    // Enable touchpad reports
+    mcu.store(Addr::XRam(0x1701), Ps2Controller::PSHE | Ps2Controller::TRMS);
+    mcu.store(Addr::XRam(0x170D), 0xF4);
+    frame(&mut mcu, 1);
+    mcu.store(Addr::XRam(0x1701), Ps2Controller::PSHE);
+    assert_eq!(frame(&mut mcu, 1), 0xFA);
+    assert!(touchpad.borrow().reporting);
+
+    touchpad.borrow_mut().motion(1, -1, 0b010);
+    mcu.ps2_poll(1);
+    let packet: Vec<u8> = (0..3).map(|_| frame(&mut mcu, 1)).collect();
+    assert_eq!(packet, [0x2A, 0x01, 0xFF]);
+    // Nothing more to receive
+    mcu.run(Budget::Cycles(76 * 13), |_| false);
+    assert_eq!(mcu.load(Addr::XRam(0x1709)), 0);
+}
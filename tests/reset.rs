@@ -0,0 +1,97 @@
+//! Register values and status flags after each reset source
+
+mod common;
+
+use area8051::{Addr, Isa, Mem, Reset, Variant, Watchdog, WatchdogAction};
+
+const PCON: Addr = Addr::Reg(0x87);
+const RSTS: Addr = Addr::XRam(0x2006);
+
+#[test]
+fn sfr_reset_values() {
+    let mut mcu = common::variant(Variant::At89s52, &[]);
+    for i in 0x80..=0xFF {
+        mcu.sfr[i - 0x80] = 0x55;
+    }
+    mcu.iram[0x30] = 0xAA;
+    mcu.reset(Reset::External);
+
+    for &port in [0x80, 0x90, 0xA0, 0xB0].iter() {
+        assert_eq!(mcu.load(Addr::Reg(port)), 0xFF);
+    }
+    assert_eq!(mcu.load(Addr::Reg(0x81)), 0x07);
+    for &register in [0x82, 0x88, 0xA2, 0xA8, 0xC8, 0xCD, 0xD0, 0xE0, 0xF0].iter() {
+        assert_eq!(mcu.sfr[register - 0x80], 0x00, "register 0x{:02X}", register);
+    }
+    // SBUF and WDTRST are not written, nor are unimplemented addresses
+    assert_eq!(mcu.sfr[0x99 - 0x80], 0x55);
+    assert_eq!(mcu.sfr[0xA6 - 0x80], 0x55);
+    assert_eq!(mcu.sfr[0xC0 - 0x80], 0x55);
+    // Internal RAM is preserved
+    assert_eq!(mcu.iram[0x30], 0xAA);
+    assert_eq!(mcu.pc, 0);
+}
+
+#[test]
+fn timer_2_only_on_8052() {
+    let mut mcu = common::variant(Variant::I8051, &[]);
+    mcu.sfr[0xC8 - 0x80] = 0x55;
+    mcu.reset(Reset::External);
+    assert_eq!(mcu.sfr[0xC8 - 0x80], 0x55);
+
+    let mut mcu = common::variant(Variant::I8052, &[]);
+    mcu.sfr[0xC8 - 0x80] = 0x55;
+    mcu.reset(Reset::External);
+    assert_eq!(mcu.sfr[0xC8 - 0x80], 0x00);
+}
+
+#[test]
+fn power_off_flag() {
+    let mut mcu = common::variant(Variant::At89s52, &[]);
+    assert_eq!(mcu.load(PCON) & 0x10, 0x10);
+
+    // Only power-on reset sets POF, other sources preserve it
+    mcu.store(PCON, 0x00);
+    mcu.reset(Reset::External);
+    assert_eq!(mcu.load(PCON) & 0x10, 0x00);
+    mcu.store(PCON, 0x10);
+    mcu.reset(Reset::Watchdog);
+    assert_eq!(mcu.load(PCON), 0x10);
+}
+
+#[test]
+fn last_reset_source() {
+    let mut mcu = common::variant(Variant::Ite, &[]);
+    assert_eq!(mcu.load(RSTS) & 0b11, 0b00);
+    // Bits other than LRS are preserved
+    mcu.store(RSTS, 0x40);
+    for &(source, lrs) in [
+        (Reset::External, 0b01),
+        (Reset::Watchdog, 0b10),
+        (Reset::Software, 0b11),
+        (Reset::PowerOn, 0b00),
+    ].iter() {
+        mcu.reset(source);
+        assert_eq!(mcu.load(RSTS), 0x40 | lrs, "{:?}", source);
+    }
+
+    // No status register on the 8051
+    let mut mcu = common::variant(Variant::I8051, &[]);
+    mcu.store(PCON, 0x10);
+    mcu.reset(Reset::PowerOn);
+    assert_eq!(mcu.load(PCON), 0x00);
+}
+
+#[test]
+fn reset_values_are_not_firmware_writes() {
+    // A watchdog fed by clearing ACC
+    let mut mcu = common::variant(Variant::Ite, &[]);
+    mcu.watchdog = Some(Watchdog::new(Addr::Reg(0xE0), vec![0x00], 100, WatchdogAction::Reset));
+    mcu.writes = Some(Vec::new());
+    mcu.store(Addr::Reg(0xE0), 0x55);
+    mcu.reset(Reset::External);
+
+    assert_eq!(mcu.load(Addr::Reg(0xE0)), 0x00);
+    assert!(! mcu.watchdog.as_ref().unwrap().running);
+    assert_eq!(mcu.writes.as_ref().unwrap(), &[(Addr::Reg(0xE0), 0x55)]);
+}
@@ -0,0 +1,60 @@
+//! Stop reasons of `Mcu::run`
+
+mod common;
+
+use area8051::{Budget, Error, StopReason};
+
+/// inc a, sjmp back to it
+const LOOP: [u8; 3] = [0x04, 0x80, 0xFD];
+
+#[test]
+fn budgets() {
+    let mut mcu = common::mcu(&LOOP);
+    assert_eq!(mcu.run(Budget::Instructions(5), |_| false), StopReason::Budget);
+    assert_eq!(mcu.pc, 0x0001);
+    assert_eq!(mcu.cycles, 7);
+
+    let mut mcu = common::mcu(&LOOP);
+    assert_eq!(mcu.run(Budget::Cycles(6), |_| false), StopReason::Budget);
+    assert_eq!(mcu.cycles, 6);
+
+    // 12 clocks per cycle at 12 MHz, one cycle per microsecond
+    let mut mcu = common::mcu(&LOOP);
+    mcu.frequency = 12_000_000;
+    assert_eq!(mcu.run(Budget::Ns(30_000), |_| false), StopReason::Budget);
+    assert_eq!(mcu.cycles, 30);
+}
+
+#[test]
+fn breakpoint_and_predicate() {
+    let mut mcu = common::mcu(&LOOP);
+    mcu.breakpoints.push(0x0001);
+    assert_eq!(mcu.run(Budget::Cycles(100), |_| false), StopReason::Breakpoint(0x0001));
+    assert_eq!(mcu.cycles, 1);
+
+    // Resuming executes the instruction at the breakpoint
+    assert_eq!(mcu.run(Budget::Cycles(100), |_| false), StopReason::Breakpoint(0x0001));
+    assert_eq!(mcu.cycles, 4);
+
+    mcu.breakpoints.clear();
+    assert_eq!(mcu.run(Budget::Cycles(100), |mcu| mcu.sfr[0x60] == 5), StopReason::Predicate);
+}
+
+#[test]
+fn idle_and_error() {
+    // orl pcon, #1
+    let mut mcu = common::mcu(&[0x43, 0x87, 0x01]);
+    assert_eq!(mcu.run(Budget::Cycles(100), |_| false), StopReason::Idle);
+    assert_eq!(mcu.cycles, 2);
+
+    // orl pcon, #2
+    let mut mcu = common::mcu(&[0x43, 0x87, 0x02]);
+    assert_eq!(mcu.run(Budget::Cycles(100), |_| false), StopReason::PowerDown);
+    assert_eq!(mcu.cycles, 2);
+
+    let mut mcu = common::mcu(&[0x00, 0xA5]);
+    assert_eq!(
+        mcu.run(Budget::Cycles(100), |_| false),
+        StopReason::Error(Error::UnknownOpcode { pc: 0x0001, op: 0xA5 })
+    );
+}
@@ -0,0 +1,80 @@
+//! Cycle keyed events
+
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use area8051::{Budget, EventId, Mcu};
+
+type Log = Rc<RefCell<Vec<(u64, u32)>>>;
+
+/// Schedule an event at `cycle` that logs `tag` with the cycle it ran at
+fn event(mcu: &mut Mcu, log: &Log, cycle: u64, tag: u32) -> EventId {
+    let log = log.clone();
+    mcu.schedule_at(cycle, move |mcu| log.borrow_mut().push((mcu.cycles, tag)))
+}
+
+#[test]
+fn order() {
+    // Loop of nop and sjmp, three cycles per iteration
+    let mut mcu = common::mcu(&[0x00, 0x80, 0xFD]);
+    let log = Log::default();
+    event(&mut mcu, &log, 30, 1);
+    event(&mut mcu, &log, 10, 2);
+    event(&mut mcu, &log, 30, 3);
+    event(&mut mcu, &log, 10, 4);
+    // Events in the past run at the end of the next instruction
+    event(&mut mcu, &log, 0, 5);
+    mcu.run_until(40).unwrap();
+
+    // Same cycle in the order scheduled, at the end of the instruction reaching it
+    assert_eq!(*log.borrow(), [(1, 5), (10, 2), (10, 4), (30, 1), (30, 3)]);
+}
+
+#[test]
+fn cancel() {
+    let mut mcu = common::idle();
+    let log = Log::default();
+    let first = event(&mut mcu, &log, 10, 1);
+    let second = event(&mut mcu, &log, 10, 2);
+    assert!(mcu.cancel(second));
+    assert!(! mcu.cancel(second));
+    mcu.run_until(20).unwrap();
+    assert!(! mcu.cancel(first));
+    assert_eq!(*log.borrow(), [(10, 1)]);
+    assert!(mcu.scheduler.is_empty());
+}
+
+#[test]
+fn events_scheduled_by_events() {
+    let mut mcu = common::idle();
+    let log = Log::default();
+    let inner = log.clone();
+    mcu.schedule_at(4, move |mcu| {
+        let log = inner.clone();
+        // Due immediately, runs in the same tick
+        mcu.schedule_in(0, move |mcu| log.borrow_mut().push((mcu.cycles, 2)));
+        let log = inner.clone();
+        mcu.schedule_in(6, move |mcu| log.borrow_mut().push((mcu.cycles, 3)));
+        inner.borrow_mut().push((mcu.cycles, 1));
+    });
+    mcu.run(Budget::Cycles(20), |_| false);
+    assert_eq!(*log.borrow(), [(4, 1), (4, 2), (10, 3)]);
+}
+
+#[test]
+fn run_until_idle() {
+    // orl pcon, #1
+    let mut mcu = common::mcu(&[0x43, 0x87, 0x01]);
+    let log = Log::default();
+    event(&mut mcu, &log, 1000, 1);
+    mcu.run_until(100).unwrap();
+    assert_eq!(mcu.cycles, 100);
+    assert!(log.borrow().is_empty());
+
+    // Idle time is skipped to the event
+    mcu.run_until(2000).unwrap();
+    assert_eq!(*log.borrow(), [(1000, 1)]);
+    assert_eq!(mcu.cycles, 2000);
+    assert_eq!(mcu.idle_cycles, 1998);
+}
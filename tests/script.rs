@@ -0,0 +1,90 @@
+//! Stimulus scripts and the number and time parsers they share with the command line
+
+mod common;
+
+use area8051::{parse_number, parse_time, Script};
+
+#[test]
+fn numbers_and_times() {
+    assert_eq!(parse_number("42"), Ok(42));
+    assert_eq!(parse_number("0x1F"), Ok(0x1F));
+    assert_eq!(parse_number("0X1f"), Ok(0x1F));
+    assert!(parse_number("0x").is_err());
+    assert!(parse_number("-1").is_err());
+
+    assert_eq!(parse_time("250ns"), Ok(250));
+    assert_eq!(parse_time("3us"), Ok(3_000));
+    assert_eq!(parse_time("0x10ms"), Ok(16_000_000));
+    assert_eq!(parse_time("2s"), Ok(2_000_000_000));
+    assert!(parse_time("10").is_err());
+    assert!(parse_time("10h").is_err());
+    assert_eq!(parse_time("20000000000s"), Err("time '20000000000s' is too long".to_string()));
+}
+
+#[test]
+fn parse_errors() {
+    let error = |source: &str| Script::parse(source).unwrap_err();
+    assert_eq!(error("\n# comment\nwait 10"), "line 3: unknown keyword 'wait'");
+    assert_eq!(error("at 10 set P4.0 low"), "line 1: invalid pin 'P4.0'");
+    assert_eq!(error("at 10 set P1.0 up"), "line 1: invalid level 'up'");
+    assert_eq!(error("at 10 jump"), "line 1: unknown action 'jump'");
+    assert_eq!(error("at 10 reset now"), "line 1: unexpected 'now'");
+    assert_eq!(error("at 10 send AT"), "line 1: missing string");
+    assert_eq!(error("at 10 send \"\\x4\""), "line 1: invalid escape \\x4");
+    assert_eq!(error("expect P1.0 == low"), "line 1: missing 'by <time>'");
+    assert_eq!(error("expect P1.0 == float by 1ms"), "line 1: expected level must be low or high");
+    assert_eq!(error("expect xram[0x10000] == 1 by 1ms"), "line 1: invalid address 'xram[0x10000]'");
+    assert_eq!(error("expect iram[0x30] == 0x100 by 1ms"), "line 1: value '0x100' does not fit in a byte");
+    assert_eq!(error("expect uart OK by 1ms"), "line 1: uart pattern must be enclosed in slashes");
+    assert_eq!(error("expect uart /a**/ by 1ms"), "line 1: nested repetition");
+    // Multiplying into nanoseconds overflows
+    assert_eq!(error("end 20000000000s"), "line 1: time '20000000000s' is too long");
+}
+
+#[test]
+fn actions_and_expectations() {
+    // Copy P1.3 to P1.0 forever
+    let mut mcu = common::mcu(&[0xA2, 0x93, 0x92, 0x90, 0x80, 0xFA]);
+    let script = Script::parse("
+        at 100 set P1.3 low
+        at 300 cycles set P1.3 float
+        expect P1.0 == high by 50
+        expect P1.0 == low by 200cycles
+        expect iram[0x30] == 0x12 by 10
+        end 400
+    ").unwrap();
+    let report = script.run(&mut mcu);
+
+    let outcomes: Vec<(usize, bool)> = report.outcomes.iter().map(|outcome| (outcome.line, outcome.passed)).collect();
+    assert_eq!(outcomes, [(4, true), (5, true), (6, false)]);
+    assert_eq!(report.outcomes[0].cycle, 0);
+    assert!(report.outcomes[1].cycle >= 100 && report.outcomes[1].cycle < 110);
+    assert!(report.outcomes[2].cycle > 10);
+    assert!(! report.passed());
+    assert_eq!(report.cycles, 400);
+    assert!(mcu.pin(1, 0));
+}
+
+#[test]
+fn idle_stops_at_deadlines() {
+    // EA and ET0, timer 0 overflows 256 cycles after the start, then idle and count wake-ups in 0x30
+    let mut code = vec![0x02, 0x00, 0x30];
+    code.resize(0x0B, 0);
+    code.extend_from_slice(&[0x05, 0x30, 0x32]);
+    code.resize(0x30, 0);
+    code.extend_from_slice(&[
+        0x75, 0xA8, 0x82, 0x75, 0x89, 0x01, 0x75, 0x8C, 0xFF, 0x75, 0x88, 0x10, 0x43, 0x87, 0x01, 0x80, 0xFB,
+    ]);
+    let mut mcu = common::mcu(&code);
+    let script = Script::parse("
+        expect iram[0x30] == 1 by 100
+        end 150
+    ").unwrap();
+    let report = script.run(&mut mcu);
+
+    // The overflow after the deadline does not pass the expectation, and the run ends on time
+    assert!(! report.outcomes[0].passed);
+    assert_eq!(report.outcomes[0].cycle, 101);
+    assert_eq!(report.cycles, 150);
+    assert_eq!(mcu.iram[0x30], 0);
+}
@@ -0,0 +1,78 @@
+//! Semihosting device commands
+
+mod common;
+
+use area8051::{Addr, Budget, Exit, Mcu, Mem, Semihost, StopReason, Variant};
+
+const DATA: u8 = 0xFE;
+const CMD: u8 = 0xFF;
+
+/// mov DATA, #value; mov CMD, #cmd
+fn command(code: &mut Vec<u8>, value: u8, cmd: u8) {
+    code.extend_from_slice(&[0x75, DATA, value, 0x75, CMD, cmd]);
+}
+
+fn run(code: Vec<u8>, input: &[u8]) -> (Mcu, StopReason) {
+    let mut semihost = Semihost::new(Addr::Reg(DATA)).unwrap();
+    semihost.input.extend(input);
+    let mut mcu = common::setup(Variant::default(), &code, |mcu| mcu.semihost = Some(semihost));
+    let reason = mcu.run(Budget::Cycles(1000), |_| false);
+    (mcu, reason)
+}
+
+#[test]
+fn console_and_exit() {
+    let mut code = Vec::new();
+    command(&mut code, b'A', 0x02);
+    // getchar twice, mov 0x30, DATA; mov 0x31, CMD
+    for &(data, status) in [(0x30, 0x31), (0x32, 0x33)].iter() {
+        command(&mut code, 0, 0x03);
+        code.extend_from_slice(&[0x85, DATA, data, 0x85, CMD, status]);
+    }
+    // Byte 0 of the cycle counter, mov 0x34, DATA
+    command(&mut code, 0, 0x04);
+    code.extend_from_slice(&[0x85, DATA, 0x34]);
+    command(&mut code, 3, 0x01);
+
+    let (mut mcu, reason) = run(code, b"x");
+    assert_eq!(reason, StopReason::Exit(Exit::Status(3)));
+    assert_eq!(mcu.semihost.as_mut().unwrap().take(), b"A");
+    assert_eq!(mcu.load(Addr::Reg(0x30)), b'x');
+    assert_eq!(mcu.load(Addr::Reg(0x31)), 0);
+    assert_eq!(mcu.load(Addr::Reg(0x33)), 1);
+    // Eleven instructions of two cycles each before the latching store
+    assert_eq!(mcu.load(Addr::Reg(0x34)), 22);
+    assert!(mcu.halted);
+}
+
+#[test]
+fn assert_failed() {
+    let mut code = Vec::new();
+    for &c in b"x > 1".iter() {
+        command(&mut code, c, 0x05);
+    }
+    code.extend_from_slice(&[0x75, CMD, 0x06]);
+
+    let (_, reason) = run(code, b"");
+    assert_eq!(reason, StopReason::Exit(Exit::AssertFailed("x > 1".to_string())));
+}
+
+#[test]
+fn invalid_address() {
+    assert!(Semihost::new(Addr::Reg(0xFF)).is_err());
+    assert!(Semihost::new(Addr::XRam(0xFFFF)).is_err());
+    assert!(Semihost::new(Addr::IRam(0x30)).is_err());
+}
+
+#[test]
+fn reset_bypasses_device() {
+    // DATA over P0 and CMD over SP, reset values go to the registers instead
+    let semihost = Semihost::new(Addr::Reg(0x80)).unwrap();
+    let mcu = common::setup(Variant::default(), &common::IDLE, |mcu| mcu.semihost = Some(semihost));
+    assert_eq!(mcu.sfr[0x01], 0x07);
+    assert_eq!(mcu.gpio.ports[0].latch, 0xFF);
+
+    let semihost = mcu.semihost.as_ref().unwrap();
+    assert_eq!(semihost.load(Addr::Reg(0x81)), Some(0));
+    assert_eq!(semihost.exit, None);
+}
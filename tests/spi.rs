@@ -0,0 +1,148 @@
+//! SPI buses and the SPI flash model
+
+mod common;
+
+use std::{cell::RefCell, fs, rc::Rc};
+
+use area8051::{
+    spi_flash::*, Budget, Mcu, SpiBus, SpiFlash, SpiMaster, SpiMode, SpiSfr, Variant,
+};
+
+/// SCK, MOSI, MISO and chip select on P1.0 to P1.3
+const SCK: (u8, u8) = (1, 0);
+const MOSI: (u8, u8) = (1, 1);
+const MISO: (u8, u8) = (1, 2);
+const CS: (u8, u8) = (1, 3);
+
+/// Chip select of the flash driven by the host, firmware only loops
+fn host(flash: Rc<RefCell<SpiFlash>>) -> Mcu {
+    let mut mcu = common::idle();
+    let mut bus = SpiBus::new(SpiMaster::Sfr(SpiSfr::at89s8253()));
+    bus.attach(CS, flash);
+    mcu.attach_spi(bus);
+    mcu
+}
+
+/// Exchange a command with the flash, returns the bytes shifted out
+fn command(mcu: &mut Mcu, data: &[u8]) -> Vec<u8> {
+    mcu.drive_pin(CS.0, CS.1, Some(false));
+    let result = data.iter().map(|&value| mcu.spi[0].exchange(value)).collect();
+    mcu.drive_pin(CS.0, CS.1, None);
+    result
+}
+
+#[test]
+fn flash_program_erase_and_read() {
+    let flash = Rc::new(RefCell::new(SpiFlash::new(1 << 20)));
+    let mut mcu = host(flash.clone());
+
+    assert_eq!(command(&mut mcu, &[JEDEC_ID, 0, 0, 0]), [0xFF, 0xEF, 0x40, 0x14]);
+
+    // Programming without the write enable latch is ignored
+    command(&mut mcu, &[PP, 0x01, 0x00, 0xFE, 0x12]);
+    assert_eq!(flash.borrow().data[0x100FE], 0xFF);
+
+    assert_eq!(command(&mut mcu, &[WREN, RDSR]), [0xFF, 0xFF]);
+    assert_eq!(command(&mut mcu, &[RDSR, 0]), [0xFF, WEL]);
+    // Two bytes at the end of a page, the third wraps to its start
+    command(&mut mcu, &[PP, 0x01, 0x00, 0xFE, 0x12, 0x34, 0x56]);
+    assert_eq!(command(&mut mcu, &[RDSR, 0]), [0xFF, 0]);
+    assert_eq!(command(&mut mcu, &[READ, 0x01, 0x00, 0xFE, 0, 0]), [0xFF, 0xFF, 0xFF, 0xFF, 0x12, 0x34]);
+    assert_eq!(command(&mut mcu, &[FAST_READ, 0x01, 0x00, 0x00, 0, 0]), [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x56]);
+
+    // Programming only clears bits
+    command(&mut mcu, &[WREN]);
+    command(&mut mcu, &[PP, 0x01, 0x00, 0xFE, 0xF0]);
+    assert_eq!(flash.borrow().data[0x100FE], 0x10);
+
+    // Busy while erasing, then the sector reads erased
+    flash.borrow_mut().busy_polls = 2;
+    command(&mut mcu, &[WREN]);
+    command(&mut mcu, &[SE, 0x01, 0x00, 0x80]);
+    assert_eq!(command(&mut mcu, &[RDSR, 0, 0, 0]), [0xFF, WIP, WIP, 0]);
+    assert!(flash.borrow().data[0x10000..0x11000].iter().all(|&byte| byte == 0xFF));
+}
+
+#[test]
+fn flash_persists_to_file() {
+    let path = std::env::temp_dir().join(format!("area8051-flash-{}.bin", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let flash = Rc::new(RefCell::new(SpiFlash::open(&path, 1 << 16).unwrap()));
+    let mut mcu = host(flash);
+    command(&mut mcu, &[WREN]);
+    command(&mut mcu, &[PP, 0x00, 0x12, 0x34, 0xA5, 0x5A]);
+
+    let flash = SpiFlash::open(&path, 1 << 16).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(&flash.data[0x1234..0x1236], &[0xA5, 0x5A]);
+    assert_eq!(flash.data[0], 0xFF);
+}
+
+/// Read the JEDEC ID to 0x30 to 0x32 by bit-banging, in mode 0 or 3
+fn bit_banged(mode: u8) -> Mcu {
+    let mut code = vec![0; 0x3F];
+    let sck = if mode == 0 { 0xC2 } else { 0xD2 };
+    code[..0x1C].copy_from_slice(&[
+        sck, 0x90, 0xC2, 0x93,
+        0x74, 0x9F, 0x12, 0x00, 0x30,
+        0x12, 0x00, 0x30, 0xF5, 0x30,
+        0x12, 0x00, 0x30, 0xF5, 0x31,
+        0x12, 0x00, 0x30, 0xF5, 0x32,
+        0xD2, 0x93, 0x80, 0xFE,
+    ]);
+    // Exchange A, MSB first
+    let clock: [u8; 4] = if mode == 0 {
+        // Sample on the rising edge
+        [0xD2, 0x90, 0xA2, 0x92]
+    } else {
+        // Data changes on the falling edge, sample on the rising edge
+        [0xC2, 0x90, 0xD2, 0x90]
+    };
+    code[0x30..0x35].copy_from_slice(&[0x7F, 0x08, 0x33, 0x92, 0x91]);
+    code[0x35..0x39].copy_from_slice(&clock);
+    let tail: &[u8] = if mode == 0 {
+        &[0xC2, 0x90, 0xDF, 0xF5, 0x33, 0x22]
+    } else {
+        &[0xA2, 0x92, 0xDF, 0xF5, 0x33, 0x22]
+    };
+    code[0x39..].copy_from_slice(tail);
+
+    let mut mcu = common::mcu(&code);
+    let mut bus = SpiBus::new(SpiMaster::Pins { sck: SCK, mosi: MOSI, miso: MISO });
+    bus.mode = SpiMode::new(mode);
+    bus.attach(CS, SpiFlash::new(1 << 21));
+    mcu.attach_spi(bus);
+    mcu.run(Budget::Cycles(2000), |mcu| mcu.pc == 0x1A);
+    mcu
+}
+
+#[test]
+fn bit_banged_modes() {
+    for &mode in [0, 3].iter() {
+        let mcu = bit_banged(mode);
+        assert_eq!(&mcu.iram[0x30..0x33], &[0xEF, 0x40, 0x15], "mode {}", mode);
+        assert_eq!(mcu.spi[0].log[0], (JEDEC_ID, 0xFF), "mode {}", mode);
+    }
+}
+
+#[test]
+fn sfr_controller() {
+    let code = vec![
+        // SPE and MSTR, select the flash
+        0x75, 0xD5, 0x50, 0xC2, 0x93,
+        // JEDEC-ID, keeping SPSR to 0x33 and clearing it after the command
+        0x75, 0x86, 0x9F, 0xE5, 0xAA, 0x75, 0xAA, 0x00, 0xF5, 0x33,
+        0x75, 0x86, 0x00, 0x85, 0x86, 0x30,
+        0x75, 0x86, 0x00, 0x85, 0x86, 0x31,
+        0x75, 0x86, 0x00, 0x85, 0x86, 0x32,
+        0xD2, 0x93, 0x80, 0xFE,
+    ];
+    let mut mcu = common::variant(Variant::At89s52, &code);
+    let mut bus = SpiBus::new(SpiMaster::Sfr(SpiSfr::at89s8253()));
+    bus.attach(CS, SpiFlash::new(1 << 16));
+    mcu.attach_spi(bus);
+    mcu.run(Budget::Cycles(100), |_| false);
+
+    assert_eq!(&mcu.iram[0x30..0x34], &[0xEF, 0x40, 0x10, SpiSfr::SPIF]);
+}
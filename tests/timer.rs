@@ -0,0 +1,105 @@
+//! Timers 0 and 1
+
+mod common;
+
+use area8051::{Addr, Budget, Mcu, Mem, Power};
+
+const TCON: Addr = Addr::Reg(0x88);
+const TMOD: Addr = Addr::Reg(0x89);
+const TL0: Addr = Addr::Reg(0x8A);
+const TL1: Addr = Addr::Reg(0x8B);
+const TH0: Addr = Addr::Reg(0x8C);
+const TH1: Addr = Addr::Reg(0x8D);
+
+const TF0: u8 = 1 << 5;
+const TF1: u8 = 1 << 7;
+
+/// Loop on sjmp with the timer registers set
+fn timers(tmod: u8, values: &[(Addr, u8)]) -> Mcu {
+    let mut mcu = common::idle();
+    mcu.store(TMOD, tmod);
+    for &(addr, value) in values {
+        mcu.store(addr, value);
+    }
+    mcu
+}
+
+#[test]
+fn sixteen_bit() {
+    let mut mcu = timers(0x01, &[(TH0, 0xFF), (TL0, 0xF0), (TCON, 0x10)]);
+    mcu.run(Budget::Cycles(14), |_| false);
+    assert_eq!(mcu.load(TL0), 0xFE);
+    assert_eq!(mcu.load(TCON) & TF0, 0);
+    mcu.run(Budget::Cycles(4), |_| false);
+    assert_eq!((mcu.load(TH0), mcu.load(TL0)), (0x00, 0x02));
+    assert_eq!(mcu.load(TCON) & TF0, TF0);
+}
+
+#[test]
+fn thirteen_bit() {
+    // The upper 3 bits of TL0 are not part of the count
+    let mut mcu = timers(0x00, &[(TH0, 0xFF), (TL0, 0xFC), (TCON, 0x10)]);
+    mcu.run(Budget::Cycles(4), |_| false);
+    assert_eq!((mcu.load(TH0), mcu.load(TL0)), (0x00, 0xE0));
+    assert_eq!(mcu.load(TCON) & TF0, TF0);
+}
+
+#[test]
+fn auto_reload() {
+    let mut mcu = timers(0x20, &[(TH1, 0xF0), (TL1, 0xF0), (TCON, 0x40)]);
+    mcu.run(Budget::Cycles(20), |_| false);
+    assert_eq!(mcu.load(TL1), 0xF4);
+    assert_eq!(mcu.load(TH1), 0xF0);
+    assert_eq!(mcu.load(TCON) & TF1, TF1);
+}
+
+#[test]
+fn split_timer_0() {
+    // TL0 runs on TR0 into TF0 and TH0 on TR1 into TF1, timer 1 keeps counting without a flag
+    let mut mcu = timers(0x13, &[(TL0, 0xFE), (TH0, 0xF0), (TH1, 0xFF), (TL1, 0xFF), (TCON, 0x50)]);
+    mcu.run(Budget::Cycles(4), |_| false);
+    assert_eq!((mcu.load(TL0), mcu.load(TH0)), (0x02, 0xF4));
+    assert_eq!(mcu.load(TCON) & (TF0 | TF1), TF0);
+    assert_eq!(mcu.load(TL1), 0x03);
+    mcu.run(Budget::Cycles(12), |_| false);
+    assert_eq!(mcu.load(TCON) & TF1, TF1);
+}
+
+#[test]
+fn gate_and_counter() {
+    // Timer 0 gated by INT0, timer 1 counting edges on T1
+    let mut mcu = timers(0x59, &[(TCON, 0x50)]);
+    mcu.drive_pin(3, 2, Some(false));
+    mcu.run(Budget::Cycles(10), |_| false);
+    assert_eq!(mcu.load(TL0), 0);
+    mcu.drive_pin(3, 2, None);
+    mcu.run(Budget::Cycles(10), |_| false);
+    assert_eq!(mcu.load(TL0), 10);
+
+    for _ in 0..3 {
+        mcu.drive_pin(3, 5, Some(false));
+        mcu.drive_pin(3, 5, None);
+    }
+    assert_eq!(mcu.load(TL1), 3);
+}
+
+#[test]
+fn idle_until_overflow() {
+    // EA and ET0, timer 0 from 0xFF00, then idle and count wake-ups in 0x30
+    let mut code = vec![0x02, 0x00, 0x30];
+    code.resize(0x0B, 0);
+    code.extend_from_slice(&[0x05, 0x30, 0x32]);
+    code.resize(0x30, 0);
+    code.extend_from_slice(&[
+        0x75, 0xA8, 0x82, 0x75, 0x89, 0x01, 0x75, 0x8C, 0xFF, 0x75, 0x88, 0x10, 0x43, 0x87, 0x01, 0x80, 0xFB,
+    ]);
+    let mut mcu = common::mcu(&code);
+    mcu.run(Budget::Cycles(300), |mcu| mcu.iram[0x30] == 1);
+    assert_eq!(mcu.power(), Power::Active);
+    assert!(mcu.idle_cycles > 240);
+
+    // The timer wraps to zero, later overflows are 65536 cycles apart
+    let start = mcu.cycles;
+    mcu.run(Budget::Cycles(70_000), |mcu| mcu.iram[0x30] == 2);
+    assert!(mcu.cycles - start > 65_000);
+}
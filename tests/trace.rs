@@ -0,0 +1,115 @@
+//! Differential tests against reference traces
+//!
+//! Each `tests/trace/<name>.trace` is replayed against `<name>.bin`, see `TraceEntry` for the
+//! format. The `.trace` files were written by hand from the instruction set manual, they are not
+//! simulator recordings. Logs of the ucsim `s51` simulator are replayed the same way from
+//! `<name>.ucsim`, recorded from each ROM with `make -C tests ucsim`, see `import_ucsim`.
+
+mod common;
+
+use area8051::{diff, import_ucsim, Mcu};
+use std::{fs, path::Path};
+
+fn mcu(rom: &Path) -> Mcu {
+    common::mcu(&fs::read(rom).unwrap())
+}
+
+#[test]
+fn traces() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("trace");
+
+    let mut traces: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "trace" || ext == "ucsim"))
+        .collect();
+    traces.sort();
+    assert!(! traces.is_empty(), "no traces in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for trace in traces.iter() {
+        let mut mcu = mcu(&trace.with_extension("bin"));
+        let mut source = fs::read_to_string(trace).unwrap();
+        if trace.extension().is_some_and(|ext| ext == "ucsim") {
+            source = import_ucsim(&source).unwrap();
+        }
+        if let Err(divergence) = diff(&mut mcu, &source) {
+            failures.push(format!("{}: {}", trace.display(), divergence));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn first_divergence() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("trace");
+    let trace = fs::read_to_string(dir.join("arith.trace")).unwrap()
+        .replace("0008 a=99 psw=44", "0008 a=99 psw=45");
+
+    let divergence = diff(&mut mcu(&dir.join("arith.bin")), &trace).unwrap_err();
+    assert_eq!(divergence.index, 5);
+    assert_eq!(divergence.line, 20);
+    assert_eq!(divergence.message, "psw is 44, expected 45");
+}
+
+/// Register dumps in the format printed by `s51` for the first two instructions of `arith.bin`
+const UCSIM_LOG: &str = "\
+0>      R0 R1 R2 R3 R4 R5 R6 R7
+0x00 00 00 00 00 00 00 00 00 ........
+@R0 00 .  ACC= 0x00   0 .  B= 0x00   DPTR= 0x0000 @DPTR= 0x00   0 .
+@R1 00 .  PSW= 0x00 CY=0 AC=0 OV=0 P=0
+F 0x0000 74 7f    mov   a,#0x7f
+0>      R0 R1 R2 R3 R4 R5 R6 R7
+0x00 00 00 00 00 00 00 00 00 ........
+@R0 00 .  ACC= 0x7f 127 .  B= 0x00   DPTR= 0x0000 @DPTR= 0x74 116 t
+@R1 00 .  PSW= 0x01 CY=0 AC=0 OV=0 P=1
+F 0x0002 24 01    add   a,#0x01
+0>      R0 R1 R2 R3 R4 R5 R6 R7
+0x00 00 00 00 00 00 00 00 00 ........
+@R0 00 .  ACC= 0x80 128 .  B= 0x00   DPTR= 0x0000 @DPTR= 0x74 116 t
+@R1 00 .  PSW= 0x45 CY=0 AC=1 OV=1 P=1
+F 0x0004 78 30    mov   r0,#0x30
+0> ";
+
+#[test]
+fn ucsim_import() {
+    let trace = import_ucsim(UCSIM_LOG).unwrap();
+    assert_eq!(trace, "\
+0000 a=7F b=00 psw=01 dptr=0000 r0=00 r1=00 r2=00 r3=00 r4=00 r5=00 r6=00 r7=00 ...
+0002 a=80 b=00 psw=45 dptr=0000 r0=00 r1=00 r2=00 r3=00 r4=00 r5=00 r6=00 r7=00 ...
+");
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("trace");
+    assert_eq!(diff(&mut mcu(&dir.join("arith.bin")), &trace).unwrap(), 2);
+
+    // Writes are not compared, registers are
+    let trace = import_ucsim(&UCSIM_LOG.replace("PSW= 0x45", "PSW= 0x44")).unwrap();
+    let divergence = diff(&mut mcu(&dir.join("arith.bin")), &trace).unwrap_err();
+    assert_eq!(divergence.index, 1);
+    assert_eq!(divergence.message, "psw is 45, expected 44");
+}
+
+#[test]
+fn ucsim_import_errors() {
+    let truncated = UCSIM_LOG.replace("0x00 00 00 00 00 00 00 00 00 ........", "0x00 00 00");
+    assert_eq!(import_ucsim(&truncated).unwrap_err(), "line 2: incomplete register bank");
+    assert_eq!(import_ucsim("").unwrap(), "");
+}
+
+/// Every trace ROM is compared against a recorded `s51` log, run once the logs are recorded with
+/// `make -C tests ucsim`
+#[test]
+#[ignore = "needs s51 logs recorded with make -C tests ucsim"]
+fn ucsim_recordings() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("trace");
+
+    let mut missing = Vec::new();
+    for entry in fs::read_dir(&dir).unwrap() {
+        let rom = entry.unwrap().path();
+        if rom.extension().is_some_and(|ext| ext == "bin") && ! rom.with_extension("ucsim").exists() {
+            missing.push(rom.display().to_string());
+        }
+    }
+
+    assert!(missing.is_empty(), "no ucsim log for\n{}", missing.join("\n"));
+}
@@ -0,0 +1,100 @@
+//! Serial port reception at the baud rate of its clock
+
+mod common;
+
+use area8051::{Addr, Isa, Mcu, Mem, Variant};
+
+const PCON: Addr = Addr::Reg(0x87);
+const TCON: Addr = Addr::Reg(0x88);
+const TMOD: Addr = Addr::Reg(0x89);
+const TH1: Addr = Addr::Reg(0x8D);
+const SCON: Addr = Addr::Reg(0x98);
+const T2CON: Addr = Addr::Reg(0xC8);
+const RCAP2L: Addr = Addr::Reg(0xCA);
+const RCAP2H: Addr = Addr::Reg(0xCB);
+
+const RI: u8 = 1 << 0;
+
+/// Loop on sjmp with the serial registers set and `data` waiting to be received
+fn serial(variant: Variant, values: &[(Addr, u8)], data: &[u8]) -> Mcu {
+    let mut mcu = common::variant(variant, &[0x80, 0xFE]);
+    for &(addr, value) in values {
+        mcu.store(addr, value);
+    }
+    mcu.uart.send(data);
+    mcu
+}
+
+/// Cycles at which each byte is received within `cycles`, clearing RI after each
+fn receive(mcu: &mut Mcu, cycles: u64) -> Vec<(u64, u8)> {
+    let mut received = Vec::new();
+    while mcu.cycles < cycles {
+        mcu.step().unwrap();
+        let scon = mcu.load(SCON);
+        if scon & RI != 0 {
+            received.push((mcu.cycles, mcu.load(Addr::Reg(0x99))));
+            mcu.store(SCON, scon & !RI);
+        }
+    }
+    received
+}
+
+/// Cycles between consecutive bytes
+fn intervals(received: &[(u64, u8)]) -> Vec<u64> {
+    received.windows(2).map(|pair| pair[1].0 - pair[0].0).collect()
+}
+
+#[test]
+fn timer_1_baud_rate() {
+    // Mode 1 at 9600 baud with an 11.0592 MHz oscillator
+    let values = [(TMOD, 0x20), (TH1, 0xFD), (TCON, 0x40), (SCON, 0x50)];
+    let mut mcu = serial(Variant::I8051, &values, b"abc");
+    let received = receive(&mut mcu, 3000);
+    assert_eq!(received.iter().map(|&(_, value)| value).collect::<Vec<u8>>(), b"abc");
+    assert_eq!(intervals(&received), [960, 960]);
+
+    // SMOD doubles the rate
+    let mut mcu = serial(Variant::I8051, &values, b"abc");
+    mcu.store(PCON, 0x80);
+    assert_eq!(intervals(&receive(&mut mcu, 3000)), [480, 480]);
+
+    // Mode 3 has a ninth data bit
+    let mut mcu = serial(Variant::I8051, &values, b"abc");
+    mcu.store(SCON, 0xD0);
+    assert_eq!(intervals(&receive(&mut mcu, 3000)), [1056, 1056]);
+}
+
+#[test]
+fn stopped_timer_receives_nothing() {
+    let values = [(TMOD, 0x20), (TH1, 0xFD), (SCON, 0x50)];
+    let mut mcu = serial(Variant::I8051, &values, b"abc");
+    assert!(receive(&mut mcu, 3000).is_empty());
+    assert_eq!(mcu.next_event(), None);
+
+    // Starting the timer releases the bytes
+    mcu.store(TCON, 0x40);
+    assert_eq!(receive(&mut mcu, 6000).len(), 3);
+}
+
+#[test]
+fn fixed_rate_modes() {
+    // Mode 0 shifts eight bits at one per machine cycle
+    let mut mcu = serial(Variant::I8051, &[(SCON, 0x10)], b"abc");
+    assert_eq!(intervals(&receive(&mut mcu, 100)), [8, 8]);
+
+    // Mode 2 shifts eleven bits at 1/64 of the oscillator, 59 cycles seen at the next instruction
+    let mut mcu = serial(Variant::I8051, &[(SCON, 0x90)], b"abc");
+    assert_eq!(intervals(&receive(&mut mcu, 200)), [60, 60]);
+}
+
+#[test]
+fn timer_2_baud_rate() {
+    // RCLK and TR2 with a reload of -36, also 9600 baud
+    let values = [(RCAP2H, 0xFF), (RCAP2L, 0xDC), (T2CON, 0x24), (SCON, 0x50)];
+    let mut mcu = serial(Variant::I8052, &values, b"abc");
+    assert_eq!(intervals(&receive(&mut mcu, 3000)), [960, 960]);
+
+    // The 8051 has no timer 2, so timer 1 clocks the port
+    let mut mcu = serial(Variant::I8051, &values, b"abc");
+    assert!(receive(&mut mcu, 3000).is_empty());
+}
@@ -0,0 +1,54 @@
+//! Memory and registers present on each chip variant
+
+mod common;
+
+use area8051::{Addr, Budget, Diagnostic, Mem, Variant};
+
+#[test]
+fn strict_upper_iram() {
+    // mov r0, #0x90; mov a, @r0; mov @r0, a
+    let code = [0x78, 0x90, 0xE6, 0xF6];
+    let mut mcu = common::variant(Variant::I8051, &code);
+    mcu.strict = true;
+    mcu.run(Budget::Instructions(3), |_| false);
+    // Missing upper RAM reads as a floating bus
+    assert_eq!(mcu.load(Addr::Reg(0xE0)), 0xFF);
+    assert_eq!(mcu.take_diagnostics(), [
+        Diagnostic::IRam { pc: 0x0002, addr: 0x90 },
+        Diagnostic::IRam { pc: 0x0003, addr: 0x90 },
+    ]);
+
+    // Upper RAM of the 8052 is only reachable indirectly
+    let mut mcu = common::variant(Variant::I8052, &code);
+    mcu.strict = true;
+    mcu.iram[0x90] = 0x5A;
+    mcu.run(Budget::Instructions(2), |_| false);
+    assert_eq!(mcu.load(Addr::Reg(0xE0)), 0x5A);
+    assert_eq!(mcu.load(Addr::Reg(0x90)), 0xFF);
+    assert!(mcu.take_diagnostics().is_empty());
+}
+
+#[test]
+fn strict_sfr() {
+    // mov 0xC8, #1; mov 0x8E, #1; mov 0xD8, #1
+    let code = [0x75, 0xC8, 0x01, 0x75, 0x8E, 0x01, 0x75, 0xD8, 0x01];
+    let mut mcu = common::variant(Variant::I8051, &code);
+    mcu.strict = true;
+    mcu.run(Budget::Instructions(3), |_| false);
+    assert_eq!(mcu.take_diagnostics(), [
+        Diagnostic::Sfr { pc: 0x0000, addr: 0xC8 },
+        Diagnostic::Sfr { pc: 0x0003, addr: 0x8E },
+        Diagnostic::Sfr { pc: 0x0006, addr: 0xD8 },
+    ]);
+
+    // T2CON, CKCON and WDTCON are implemented by the ITE core
+    let mut mcu = common::variant(Variant::Ite, &code);
+    mcu.strict = true;
+    mcu.run(Budget::Instructions(3), |_| false);
+    assert!(mcu.take_diagnostics().is_empty());
+
+    // Nothing is recorded unless strict
+    let mut mcu = common::variant(Variant::I8051, &code);
+    mcu.run(Budget::Instructions(3), |_| false);
+    assert!(mcu.take_diagnostics().is_empty());
+}
@@ -0,0 +1,85 @@
+//! Watchdog feeding, expiry and presets
+
+mod common;
+
+use area8051::{Addr, Budget, Mem, StopReason, Variant, Watchdog, WatchdogAction};
+
+const RSTS: Addr = Addr::XRam(0x2006);
+
+#[test]
+fn feeding_keeps_running() {
+    // Feed, wait 512 cycles and feed again
+    let code = [0x75, 0xA6, 0x1E, 0x75, 0xA6, 0xE1, 0xDF, 0xFE, 0x80, 0xF6];
+    let mut mcu = common::variant(Variant::At89s52, &code);
+    mcu.watchdog = Some(Watchdog::at89s52());
+    mcu.run(Budget::Cycles(100_000), |_| false);
+
+    let watchdog = mcu.watchdog.as_ref().unwrap();
+    assert!(watchdog.running);
+    assert_eq!(watchdog.expired, None);
+}
+
+#[test]
+fn missed_feed_resets() {
+    // Count resets in 0x30, feed once through EWDKEYR and stop feeding
+    let code = [0x05, 0x30, 0x90, 0x1F, 0x07, 0x74, 0x5C, 0xF0, 0x80, 0xFE];
+    let mut mcu = common::variant(Variant::Ite, &code);
+    mcu.watchdog = Some(Watchdog::ite(1000));
+    mcu.run(Budget::Cycles(1500), |_| false);
+
+    assert_eq!(mcu.iram[0x30], 2);
+    // Counting starts with the cycles of the feeding movx
+    assert_eq!(mcu.watchdog.as_ref().unwrap().expired, Some(4 + 1000));
+    // RSTS.LRS records the watchdog reset
+    assert_eq!(mcu.load(RSTS) & 0b11, 0b10);
+}
+
+#[test]
+fn at89s52_preset() {
+    let watchdog = Watchdog::at89s52();
+    assert_eq!(watchdog.feed, Addr::Reg(0xA6));
+    assert_eq!(watchdog.sequence, [0x1E, 0xE1]);
+    assert_eq!(watchdog.action, WatchdogAction::Reset);
+
+    let mut mcu = common::variant(Variant::At89s52, &[0x80, 0xFE]);
+    mcu.watchdog = Some(watchdog);
+    // An incomplete sequence does not start the watchdog
+    mcu.store(Addr::Reg(0xA6), 0xE1);
+    mcu.store(Addr::Reg(0xA6), 0x1E);
+    mcu.store(Addr::Reg(0xA6), 0x00);
+    mcu.run(Budget::Cycles(20_000), |_| false);
+    assert_eq!(mcu.watchdog.as_ref().unwrap().expired, None);
+
+    // Expires 16384 machine cycles after the feed
+    mcu.store(Addr::Reg(0xA6), 0x1E);
+    mcu.store(Addr::Reg(0xA6), 0xE1);
+    let fed = mcu.cycles;
+    mcu.run(Budget::Cycles(20_000), |mcu| mcu.watchdog.as_ref().unwrap().expired.is_some());
+    assert_eq!(mcu.watchdog.as_ref().unwrap().expired, Some(fed + 16384));
+    assert_eq!(mcu.pc, 0);
+}
+
+#[test]
+fn ite_preset() {
+    let watchdog = Watchdog::ite(5000);
+    assert_eq!(watchdog.feed, Addr::XRam(0x1F07));
+    assert_eq!(watchdog.sequence, [0x5C]);
+    assert_eq!(watchdog.timeout, 5000);
+
+    let mut mcu = common::variant(Variant::Ite, &[0x80, 0xFE]);
+    mcu.watchdog = Some(watchdog);
+    mcu.store(Addr::XRam(0x1F07), 0x5C);
+    mcu.run(Budget::Cycles(4998), |_| false);
+    assert_eq!(mcu.watchdog.as_ref().unwrap().remaining(), Some(2));
+    mcu.run(Budget::Cycles(2), |_| false);
+    assert_eq!(mcu.watchdog.as_ref().unwrap().expired, Some(5000));
+}
+
+#[test]
+fn halt_action() {
+    let mut mcu = common::variant(Variant::Ite, &[0x80, 0xFE]);
+    mcu.watchdog = Some(Watchdog::new(Addr::XRam(0x1F07), vec![0x5C], 100, WatchdogAction::Halt));
+    mcu.store(Addr::XRam(0x1F07), 0x5C);
+    assert_eq!(mcu.run(Budget::Cycles(1000), |_| false), StopReason::Halted);
+    assert_eq!(mcu.cycles, 100);
+}